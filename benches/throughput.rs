@@ -0,0 +1,158 @@
+use connection::{CompressionMode, Connection};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+
+const MESSAGE_COUNTS: [usize; 3] = [10, 100, 1000];
+
+/// Connect a client to a server that reads and discards every message it receives
+async fn setup() -> Connection {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = Connection::dial(addr).await.unwrap();
+
+    let mut server = Connection::new(listener.accept().await.unwrap().0);
+    tokio::spawn(async move { while let Ok(Some(_)) = server.read::<u64>().await {} });
+
+    client
+}
+
+fn write_throughput(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("write");
+
+    for count in MESSAGE_COUNTS {
+        let values: Vec<u64> = (0..count as u64).collect();
+        group.throughput(Throughput::Elements(count as u64));
+
+        let mut conn = runtime.block_on(setup());
+        group.bench_with_input(BenchmarkId::new("write", count), &values, |b, values| {
+            b.iter(|| {
+                runtime.block_on(async {
+                    for value in values {
+                        conn.write(value).await.unwrap();
+                    }
+                })
+            })
+        });
+
+        let mut conn = runtime.block_on(setup());
+        group.bench_with_input(
+            BenchmarkId::new("write_batch", count),
+            &values,
+            |b, values| b.iter(|| runtime.block_on(conn.write_batch(values)).unwrap()),
+        );
+    }
+
+    group.finish();
+}
+
+/// Connect a client to a server that answers every three-message preamble with an acknowledgement
+async fn setup_preamble() -> Connection {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = Connection::dial(addr).await.unwrap();
+
+    let mut server = Connection::new(listener.accept().await.unwrap().0);
+    tokio::spawn(async move {
+        while let (Ok(Some(_)), Ok(Some(_)), Ok(Some(_))) = (
+            server.read::<String>().await,
+            server.read::<u64>().await,
+            server.read::<Vec<u8>>().await,
+        ) {
+            if server.write(&true).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    client
+}
+
+fn preamble_round_trip(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("round_trip");
+    let (name, id, body) = ("preamble".to_string(), 42u64, vec![0u8; 64]);
+
+    let mut conn = runtime.block_on(setup_preamble());
+    group.bench_function("write", |b| {
+        b.iter(|| {
+            runtime.block_on(async {
+                conn.write(&name).await.unwrap();
+                conn.write(&id).await.unwrap();
+                conn.write(&body).await.unwrap();
+                conn.read::<bool>().await.unwrap().unwrap()
+            })
+        })
+    });
+
+    let mut conn = runtime.block_on(setup_preamble());
+    group.bench_function("write_many", |b| {
+        b.iter(|| {
+            runtime.block_on(async {
+                let batch = conn.write_many().push(&name).push(&id).push(&body);
+                batch.flush().await.unwrap();
+                conn.read::<bool>().await.unwrap().unwrap()
+            })
+        })
+    });
+
+    group.finish();
+}
+
+/// Connect a client to a server that acknowledges every payload it receives
+async fn setup_acknowledging(compression: CompressionMode) -> Connection {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut client = Connection::dial(addr).await.unwrap();
+    client.set_compression(compression);
+
+    // Small compressed frames would otherwise be held back by Nagle's algorithm
+    client.set_nodelay(true).unwrap();
+    let mut server = Connection::new(listener.accept().await.unwrap().0);
+    server.set_nodelay(true).unwrap();
+    tokio::spawn(async move {
+        while let Ok(Some(_)) = server.read::<Vec<u8>>().await {
+            if server.write(&true).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    client
+}
+
+fn compression_throughput(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("compression");
+
+    // A 1 MB payload with the kind of repetition found in real messages
+    let payload: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8 / 16).collect();
+    group.throughput(Throughput::Bytes(payload.len() as u64));
+
+    for (name, compression) in [
+        ("none", CompressionMode::None),
+        ("zstd_1", CompressionMode::Zstd(1)),
+        ("zstd_3", CompressionMode::Zstd(3)),
+    ] {
+        let mut conn = runtime.block_on(setup_acknowledging(compression));
+        group.bench_with_input(BenchmarkId::new("1mb", name), &payload, |b, payload| {
+            b.iter(|| {
+                runtime.block_on(async {
+                    conn.write(payload).await.unwrap();
+                    conn.read::<bool>().await.unwrap().unwrap()
+                })
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    write_throughput,
+    preamble_round_trip,
+    compression_throughput
+);
+criterion_main!(benches);
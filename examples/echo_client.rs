@@ -0,0 +1,42 @@
+//! A client that sends messages to the `echo_server` example and checks that each comes back
+//!
+//! Start the server first, then run `cargo run --example echo_client -- --port 8080 --count 10`.
+use clap::Parser;
+use connection::Connection;
+use std::error::Error;
+
+#[derive(Parser)]
+#[command(about = "Send messages to an echo server and verify the replies")]
+struct Args {
+    /// The address of the echo server
+    #[arg(long, default_value = "127.0.0.1")]
+    address: String,
+    /// The port of the echo server
+    #[arg(long, default_value_t = 8080)]
+    port: u16,
+    /// The number of messages to send
+    #[arg(long, default_value_t = 10)]
+    count: usize,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    let mut conn = Connection::dial((args.address.as_str(), args.port)).await?;
+
+    for i in 0..args.count {
+        let message = format!("message {i}");
+        conn.write(&message).await?;
+
+        let reply: String = conn
+            .read()
+            .await?
+            .ok_or("the server closed the connection before replying")?;
+        if reply != message {
+            return Err(format!("sent {message:?} but received {reply:?}").into());
+        }
+    }
+
+    println!("all {} messages were echoed back", args.count);
+    Ok(())
+}
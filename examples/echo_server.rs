@@ -0,0 +1,46 @@
+//! A server that sends every message it receives straight back to its sender
+//!
+//! Run it with `cargo run --example echo_server -- --port 8080`, then talk to it with the
+//! `echo_client` example.
+use clap::Parser;
+use connection::{Connection, ConnectionError};
+use std::error::Error;
+use tokio::net::TcpListener;
+
+#[derive(Parser)]
+#[command(about = "Echo every message back to its sender")]
+struct Args {
+    /// The address to listen on
+    #[arg(long, default_value = "127.0.0.1")]
+    address: String,
+    /// The port to listen on
+    #[arg(long, default_value_t = 8080)]
+    port: u16,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    let listener = TcpListener::bind((args.address.as_str(), args.port)).await?;
+    println!("listening on {}", listener.local_addr()?);
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        tokio::spawn(async move {
+            match echo(Connection::new(stream)).await {
+                Ok(count) => println!("{peer} disconnected after {count} messages"),
+                Err(e) => eprintln!("{peer} failed: {e}"),
+            }
+        });
+    }
+}
+
+/// Echo messages until the peer closes the connection, returning how many were echoed
+async fn echo(mut conn: Connection) -> Result<usize, ConnectionError> {
+    let mut count = 0;
+    while let Some(message) = conn.read::<String>().await? {
+        conn.write(&message).await?;
+        count += 1;
+    }
+    Ok(count)
+}
@@ -0,0 +1,99 @@
+use crate::frame::{self, Control, LENGTH_PREFIX_SIZE};
+use crate::{Connection, ConnectionError};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    /// Write a serializable value into the stream, then wait up to `ack_timeout` for the peer to
+    /// confirm it received the value with [`Connection::read_with_ack`]
+    ///
+    /// The peer answers with an acknowledgment frame holding a hash of the payload it received.
+    /// Returning `Ok` means the peer's application deserialized the value, which is a stronger
+    /// guarantee than TCP's own acknowledgments: those only say the bytes reached the peer's
+    /// kernel, not that anyone read them. This is not a substitute for TCP's acknowledgments
+    /// either, it only adds a confirmation on top of them.
+    ///
+    /// On [`ConnectionError::Timeout`], the value may or may not have been delivered. The value is
+    /// sent at most once, so retrying is up to the caller. The peer must not send messages while
+    /// an acknowledgment is awaited, since they would have to be read first; receiving one fails
+    /// with [`ConnectionError::IoError`] and leaves the message buffered.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // Send a message and wait for the peer to confirm it
+    ///     conn.write_with_ack(&"Hello, world!", Duration::from_secs(5)).await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn write_with_ack<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+        ack_timeout: Duration,
+    ) -> Result<(), ConnectionError> {
+        let payload = self.record(self.format.serialize(value))?;
+        self.write_frame(&payload, 0).await?;
+
+        let hash = frame::payload_hash(&payload);
+        match tokio::time::timeout(ack_timeout, self.wait_for_ack(hash)).await {
+            Ok(result) => result,
+            Err(_) => self.record(Err(ConnectionError::Timeout)),
+        }
+    }
+
+    /// Reads a value like [`Connection::read`], then acknowledges it to a peer waiting in
+    /// [`Connection::write_with_ack`]
+    ///
+    /// The acknowledgment is only sent once the value has been deserialized, so a value that
+    /// fails to deserialize is never acknowledged.
+    pub async fn read_with_ack<T: DeserializeOwned>(
+        &mut self,
+    ) -> Result<Option<T>, ConnectionError> {
+        let Some(payload) = self.read_raw().await? else {
+            return Ok(None);
+        };
+
+        let value = self.record(self.format.deserialize(&payload))?;
+        self.write_control(Control::Ack(frame::payload_hash(&payload)))
+            .await?;
+        Ok(Some(value))
+    }
+
+    /// Waits for the acknowledgment of the payload with the given hash
+    async fn wait_for_ack(&mut self, hash: u64) -> Result<(), ConnectionError> {
+        self.skipped.restore(&mut self.buffer);
+        loop {
+            match frame::take_control(&mut self.buffer) {
+                Some(Control::Ack(acked)) if acked == hash => return Ok(()),
+                // A late acknowledgment of an earlier write that timed out
+                Some(Control::Ack(_)) => {}
+                Some(control) => self.handle_control(control).await?,
+                None if self.buffer.len() >= LENGTH_PREFIX_SIZE
+                    && !frame::at_control(&self.buffer) =>
+                {
+                    return self.record(Err(ConnectionError::IoError(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "received a message while waiting for an acknowledgment",
+                    ))));
+                }
+                None => {
+                    if 0 == self.read_to_buffer().await? {
+                        return self.record(Err(ConnectionError::Disconnected));
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use crate::{Connection, ConnectionError, ConnectionReader, ConnectionWriter, SerdeFormat};
+use bytes::Bytes;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// The number of frames each direction may buffer
+const CHANNEL_CAPACITY: usize = 32;
+
+/// Drives a [`Connection`] from a pair of [`mpsc`] channels, created by
+/// [`Connection::into_actor`]
+///
+/// Frames sent on [`ConnectionActor::sender`] are written to the connection in order, and every
+/// frame read from the connection is delivered to [`ConnectionActor::receiver`]. Each direction
+/// runs on its own task. The receiver yields `None` once the peer closes the connection or a read
+/// fails, and sends fail once a write fails; [`ConnectionActor::shutdown`] reports the error.
+///
+/// # Examples
+///
+/// ```no_run
+/// use connection::{Connection, OutgoingFrame};
+/// use std::error::Error;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn Error>> {
+///     // Connect to a peer
+///     let conn = Connection::dial("127.0.0.1:8080").await?;
+///     let format = conn.format();
+///     let mut actor = conn.into_actor();
+///
+///     // Send a message, then wait for the reply
+///     let sender = actor.sender();
+///     sender.send(OutgoingFrame::new(&"Hello, world!", format)?).await?;
+///     if let Some(frame) = actor.receiver().recv().await {
+///         let reply: String = frame.decode()?;
+///     }
+///
+///     // Stop both tasks once everything sent so far is written
+///     actor.shutdown().await?;
+///
+///     Ok(())
+/// }
+/// ```
+pub struct ConnectionActor {
+    format: SerdeFormat,
+    outgoing: mpsc::Sender<OutgoingFrame>,
+    incoming: mpsc::Receiver<IncomingFrame>,
+    writer: JoinHandle<Result<(), ConnectionError>>,
+    reader: JoinHandle<Result<(), ConnectionError>>,
+}
+
+/// A frame for a [`ConnectionActor`] to write
+#[derive(Debug, Clone)]
+pub struct OutgoingFrame(Outgoing);
+
+#[derive(Debug, Clone)]
+enum Outgoing {
+    Payload(Bytes),
+    /// Sent by [`ConnectionActor::shutdown`] to stop the writer task
+    Terminate,
+}
+
+/// A frame read by a [`ConnectionActor`]
+#[derive(Debug, Clone)]
+pub struct IncomingFrame {
+    payload: Bytes,
+    format: SerdeFormat,
+}
+
+impl OutgoingFrame {
+    /// Create a frame holding a value serialized with the given format, which must be the format
+    /// of the connection
+    pub fn new<T: Serialize + ?Sized>(
+        value: &T,
+        format: SerdeFormat,
+    ) -> Result<Self, ConnectionError> {
+        Ok(Self::raw(format.serialize(value)?))
+    }
+
+    /// Create a frame holding a payload that is already serialized
+    pub fn raw(payload: impl Into<Bytes>) -> Self {
+        OutgoingFrame(Outgoing::Payload(payload.into()))
+    }
+}
+
+impl IncomingFrame {
+    /// Deserialize the value held by the frame with the connection's format
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T, ConnectionError> {
+        self.format.deserialize(&self.payload)
+    }
+
+    /// Returns the payload of the frame without deserializing it
+    pub fn payload(&self) -> &Bytes {
+        &self.payload
+    }
+
+    /// Unwrap the payload of the frame
+    pub fn into_payload(self) -> Bytes {
+        self.payload
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> Connection<S> {
+    /// Hand the connection to a [`ConnectionActor`], which reads and writes it on background tasks
+    ///
+    /// This must be called from within a Tokio runtime.
+    pub fn into_actor(self) -> ConnectionActor {
+        let format = self.format();
+        let (reader, writer) = self.split();
+        let (outgoing, outgoing_rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let (incoming_tx, incoming) = mpsc::channel(CHANNEL_CAPACITY);
+
+        ConnectionActor {
+            format,
+            outgoing,
+            incoming,
+            writer: tokio::spawn(write_frames(writer, outgoing_rx)),
+            reader: tokio::spawn(read_frames(reader, format, incoming_tx)),
+        }
+    }
+}
+
+impl ConnectionActor {
+    /// Returns a sender for frames to write, which may be cloned and moved to other tasks
+    pub fn sender(&self) -> mpsc::Sender<OutgoingFrame> {
+        self.outgoing.clone()
+    }
+
+    /// Returns the receiver of the frames read from the connection
+    pub fn receiver(&mut self) -> &mut mpsc::Receiver<IncomingFrame> {
+        &mut self.incoming
+    }
+
+    /// Returns the serialization format of the connection
+    pub fn format(&self) -> SerdeFormat {
+        self.format
+    }
+
+    /// Stop both tasks and close the connection, returning the first error either task ran into
+    ///
+    /// A terminate sentinel is queued behind the frames already sent, so they are all written
+    /// before the writer stops. The reader is stopped once the writer has finished, and frames it
+    /// has not delivered yet are dropped.
+    pub async fn shutdown(mut self) -> Result<(), ConnectionError> {
+        // The writer is gone already if a write failed, in which case it holds the error
+        let _ = self.outgoing.send(OutgoingFrame(Outgoing::Terminate)).await;
+        let written = join(&mut self.writer).await;
+
+        self.reader.abort();
+        let read = join(&mut self.reader).await;
+        written.and(read)
+    }
+}
+
+impl Drop for ConnectionActor {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// Waits for a task, treating a cancelled task as having succeeded and resuming a panic
+async fn join(task: &mut JoinHandle<Result<(), ConnectionError>>) -> Result<(), ConnectionError> {
+    match task.await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Writes every frame sent on the channel until the terminate sentinel arrives, the senders are
+/// dropped, or a write fails
+async fn write_frames<S>(
+    mut writer: ConnectionWriter<S>,
+    mut outgoing: mpsc::Receiver<OutgoingFrame>,
+) -> Result<(), ConnectionError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    while let Some(OutgoingFrame(frame)) = outgoing.recv().await {
+        match frame {
+            Outgoing::Payload(payload) => writer.write_raw(&payload).await?,
+            Outgoing::Terminate => break,
+        }
+    }
+    Ok(())
+}
+
+/// Delivers every frame read from the connection until it closes, a read fails, or the receiver
+/// is dropped
+async fn read_frames<S>(
+    mut reader: ConnectionReader<S>,
+    format: SerdeFormat,
+    incoming: mpsc::Sender<IncomingFrame>,
+) -> Result<(), ConnectionError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    while let Some(payload) = reader.read_raw().await? {
+        if incoming
+            .send(IncomingFrame { payload, format })
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,191 @@
+use crate::{frame, Connection, ConnectionError};
+use bytes::{BufMut, BytesMut};
+use futures::FutureExt;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+
+/// Collects values of different types and writes them with a single flush, created by
+/// [`Connection::write_many`]
+///
+/// Values are serialized as they are pushed. If any of them fails to serialize, nothing is
+/// written and the error is returned by [`WriteMany::flush`].
+#[must_use = "nothing is written until the values are flushed"]
+pub struct WriteMany<'a, S> {
+    conn: &'a mut Connection<S>,
+    frames: BytesMut,
+    body_lens: Vec<usize>,
+    error: Option<ConnectionError>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    /// Start collecting values of any serializable types to write with a single flush
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // Send a preamble of different message types with one flush
+    ///     let written = conn
+    ///         .write_many()
+    ///         .push(&"HELLO")
+    ///         .push(&1u16)
+    ///         .push(&vec![1, 2, 3])
+    ///         .flush()
+    ///         .await?;
+    ///     assert_eq!(3, written);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn write_many(&mut self) -> WriteMany<'_, S> {
+        WriteMany {
+            conn: self,
+            frames: BytesMut::new(),
+            body_lens: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Wait for a message, then return it along with every other message that can be read
+    /// without waiting, up to `max` messages in total
+    ///
+    /// Everything the socket has ready is read into the internal buffer before parsing, so a
+    /// burst of messages is handled with a single call instead of one call per message. Like
+    /// [`Connection::read`], this waits on the peer until the first message arrives, and an empty
+    /// `Vec` means the peer closed the connection. Messages left over beyond `max` stay buffered
+    /// for the next read.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // Handle messages in batches of up to 64
+    ///     loop {
+    ///         let messages = conn.read_many::<String>(64).await?;
+    ///         if messages.is_empty() {
+    ///             break;
+    ///         }
+    ///         println!("received {} messages", messages.len());
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn read_many<T: DeserializeOwned>(
+        &mut self,
+        max: usize,
+    ) -> Result<Vec<T>, ConnectionError> {
+        if max == 0 {
+            return Ok(Vec::new());
+        }
+        let Some(first) = self.read::<T>().await? else {
+            return Ok(Vec::new());
+        };
+
+        let mut values = vec![first];
+        while values.len() < max {
+            if let Some(value) = self.parse_buffered().await? {
+                values.push(value);
+                continue;
+            }
+
+            // Only take what the socket already has
+            if !self.read_ready() {
+                break;
+            }
+        }
+        Ok(values)
+    }
+
+    /// Read every message the peer sends until it closes the connection
+    ///
+    /// This suits protocols where the peer sends a variable number of responses and then closes
+    /// the connection. Every message is held in memory until the peer closes it, so the peer
+    /// should be trusted to send a bounded number of them. If a read fails, the error is returned
+    /// right away and the messages read so far are discarded, including when the peer closes the
+    /// connection part of the way through a message.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Ask a peer for every matching record
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///     conn.write(&"SELECT *").await?;
+    ///
+    ///     let records = conn.read_all::<String>().await?;
+    ///     println!("received {} records", records.len());
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn read_all<T: DeserializeOwned>(&mut self) -> Result<Vec<T>, ConnectionError> {
+        let mut values = Vec::new();
+        while let Some(value) = self.read::<T>().await? {
+            values.push(value);
+        }
+        Ok(values)
+    }
+}
+
+impl<'a, S: AsyncRead + AsyncWrite + Unpin> WriteMany<'a, S> {
+    /// Serialize a value and add it to the values to write
+    pub fn push<T: Serialize>(mut self, value: &T) -> Self {
+        if self.error.is_none() {
+            if let Err(e) = self.encode(value) {
+                self.error = Some(e);
+            }
+        }
+        self
+    }
+
+    /// Write every pushed value into the stream and flush it, returning the number of values
+    /// written
+    pub async fn flush(self) -> Result<usize, ConnectionError> {
+        let conn = self.conn;
+        if let Some(e) = self.error {
+            return conn.record(Err(e));
+        }
+
+        conn.pending.extend_from_slice(&self.frames);
+        conn.flush().await?;
+        for len in &self.body_lens {
+            conn.stats.record_sent(*len);
+        }
+        Ok(self.body_lens.len())
+    }
+
+    fn encode<T: Serialize>(&mut self, value: &T) -> Result<(), ConnectionError> {
+        let payload = self.conn.format.serialize(value)?;
+        let (header, body) = frame::encode(
+            &payload,
+            self.conn.compression,
+            self.conn.hmac_key.as_ref(),
+            &self.conn.middleware,
+        )?;
+        self.frames.reserve(header.len() + body.len());
+        self.frames.put_slice(&header);
+        self.frames.put_slice(&body);
+        self.body_lens.push(body.len());
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+use crate::{Connection, ConnectionReader, ConnectionWriter, SerdeFormat};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+
+/// The number of messages each direction may buffer unless configured otherwise
+const DEFAULT_CHANNEL_CAPACITY: usize = 32;
+
+/// Pipes a pair of [`mpsc`] channels into a [`Connection`]
+///
+/// Values sent on the returned sender are written to the connection in order, and values read
+/// from the connection are delivered to the returned receiver. Both directions are driven by
+/// background tasks, so the rest of the application never touches the connection itself.
+///
+/// Errors are not reported directly. If a write fails, the sender is closed and further sends
+/// fail. If a read fails or the peer closes the connection, the receiver yields `None`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use connection::{ChannelBridge, Connection};
+/// use std::error::Error;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn Error>> {
+///     // Connect to a peer
+///     let conn = Connection::dial("127.0.0.1:8080").await?;
+///     let (outgoing, mut incoming) = ChannelBridge::new::<_, String, String>(conn);
+///
+///     // Send a message, then wait for the reply
+///     outgoing.send("Hello, world!".to_string()).await?;
+///     let reply = incoming.recv().await;
+///
+///     Ok(())
+/// }
+/// ```
+pub struct ChannelBridge;
+
+impl ChannelBridge {
+    /// Bridge the connection to a new channel pair with the default capacity
+    ///
+    /// This must be called from within a Tokio runtime.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new<S, Out, In>(conn: Connection<S>) -> (mpsc::Sender<Out>, mpsc::Receiver<In>)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        Out: Serialize + Send + 'static,
+        In: DeserializeOwned + Send + 'static,
+    {
+        Self::with_capacity(conn, DEFAULT_CHANNEL_CAPACITY)
+    }
+
+    /// Bridge the connection to a new channel pair, each buffering up to `capacity` messages
+    ///
+    /// This must be called from within a Tokio runtime.
+    pub fn with_capacity<S, Out, In>(
+        conn: Connection<S>,
+        capacity: usize,
+    ) -> (mpsc::Sender<Out>, mpsc::Receiver<In>)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        Out: Serialize + Send + 'static,
+        In: DeserializeOwned + Send + 'static,
+    {
+        let format = conn.format();
+        let (reader, writer) = conn.split();
+        let (outgoing, outgoing_rx) = mpsc::channel(capacity.max(1));
+        let (incoming_tx, incoming) = mpsc::channel(capacity.max(1));
+
+        tokio::spawn(forward_outgoing(writer, format, outgoing_rx));
+        tokio::spawn(forward_incoming(reader, incoming_tx));
+
+        (outgoing, incoming)
+    }
+}
+
+/// Writes every value sent on the channel until the senders are dropped or a write fails
+async fn forward_outgoing<S, T>(
+    mut writer: ConnectionWriter<S>,
+    format: SerdeFormat,
+    mut outgoing: mpsc::Receiver<T>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+    T: Serialize,
+{
+    while let Some(value) = outgoing.recv().await {
+        // Serialize before awaiting so the value need not be `Sync`
+        let payload = match format.serialize(&value) {
+            Ok(payload) => payload,
+            Err(_) => break,
+        };
+        if writer.write_raw(&payload).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Delivers every value read from the connection until it closes, a read fails, or the receiver
+/// is dropped
+async fn forward_incoming<S, T>(mut reader: ConnectionReader<S>, incoming: mpsc::Sender<T>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    T: DeserializeOwned,
+{
+    while let Ok(Some(value)) = reader.read().await {
+        if incoming.send(value).await.is_err() {
+            break;
+        }
+    }
+}
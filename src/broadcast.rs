@@ -0,0 +1,44 @@
+use crate::{Connection, ConnectionError};
+use futures::future::join_all;
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Write a serializable value to every connection concurrently
+///
+/// The result of each write is returned at the index of its connection, so a peer that fails does
+/// not prevent the value from reaching the others.
+///
+/// # Examples
+///
+/// ```no_run
+/// use connection::{broadcast, Connection};
+/// use std::error::Error;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn Error>> {
+///     // Connect to a few peers
+///     let mut connections = vec![
+///         Connection::dial("127.0.0.1:8080").await?,
+///         Connection::dial("127.0.0.1:8081").await?,
+///     ];
+///
+///     // Send the same message to all of them
+///     for result in broadcast(&mut connections, &"Hello, everyone!").await {
+///         if let Err(e) = result {
+///             eprintln!("failed to reach a peer: {e}");
+///         }
+///     }
+///
+///     Ok(())
+/// }
+/// ```
+pub async fn broadcast<S, T>(
+    connections: &mut [Connection<S>],
+    value: &T,
+) -> Vec<Result<(), ConnectionError>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    T: Serialize + Sync,
+{
+    join_all(connections.iter_mut().map(|conn| conn.write(value))).await
+}
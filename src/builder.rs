@@ -0,0 +1,266 @@
+use crate::{
+    connect, set_ip_ttl, set_tcp_keepalive, CompressionMode, Connection, ConnectionError,
+    SerdeFormat, Skipped, StatsRecorder, DEFAULT_BUFFER_SIZE, DEFAULT_MAX_IN_FLIGHT_BYTES,
+    DEFAULT_MAX_MESSAGE_SIZE,
+};
+use bytes::BytesMut;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, BufWriter};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::sync::watch;
+
+/// A builder used to configure and create a [`Connection`]
+///
+/// # Examples
+///
+/// ```no_run
+/// use connection::{ConnectionBuilder, SerdeFormat};
+/// use std::error::Error;
+/// use std::time::Duration;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn Error>> {
+///     // Connect to a peer
+///     let mut conn = ConnectionBuilder::new()
+///         .capacity(8 * 1024)
+///         .format(SerdeFormat::Json)
+///         .read_timeout(Duration::from_secs(5))
+///         .nodelay(true)
+///         .connect("127.0.0.1:8080")
+///         .await?;
+///
+///     // Send a message
+///     conn.write(&"Hello, world!").await?;
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ConnectionBuilder {
+    capacity: usize,
+    write_buffer_size: usize,
+    format: SerdeFormat,
+    compression: CompressionMode,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    nodelay: Option<bool>,
+    ttl: Option<u32>,
+    keepalive: Option<Duration>,
+    max_in_flight_bytes: usize,
+    max_message_size: usize,
+    name: Option<String>,
+    #[cfg(feature = "handshake")]
+    require_handshake: bool,
+}
+
+impl ConnectionBuilder {
+    /// Create a new builder with the default configuration
+    pub fn new() -> Self {
+        Self {
+            capacity: DEFAULT_BUFFER_SIZE,
+            write_buffer_size: DEFAULT_BUFFER_SIZE,
+            format: SerdeFormat::default(),
+            compression: CompressionMode::default(),
+            read_timeout: None,
+            write_timeout: None,
+            nodelay: None,
+            ttl: None,
+            keepalive: None,
+            max_in_flight_bytes: DEFAULT_MAX_IN_FLIGHT_BYTES,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            name: None,
+            #[cfg(feature = "handshake")]
+            require_handshake: false,
+        }
+    }
+
+    /// Set the initial capacity of the read buffer, which must be greater than zero
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Set the initial capacity of the read buffer, which must be greater than zero
+    ///
+    /// This is the same setting as [`ConnectionBuilder::capacity`].
+    pub fn read_buffer_size(self, size: usize) -> Self {
+        self.capacity(size)
+    }
+
+    /// Set the capacity of the write buffer, which must be greater than zero
+    ///
+    /// Frames are written into the buffer and flushed to the socket once it fills up or the write
+    /// completes.
+    pub fn write_buffer_size(mut self, size: usize) -> Self {
+        self.write_buffer_size = size;
+        self
+    }
+
+    /// Set the serialization format used to encode values
+    pub fn format(mut self, format: SerdeFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Set the compression applied to the payloads the connection writes
+    pub fn compression(mut self, compression: CompressionMode) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Set the maximum amount of time a single read from the socket may take
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the maximum amount of time a single write to the socket may take
+    ///
+    /// See [`Connection::set_write_timeout`] for why an expired write fails with
+    /// [`ConnectionError::WriteTimeout`].
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.write_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the value of the `TCP_NODELAY` option on the socket
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = Some(nodelay);
+        self
+    }
+
+    /// Set the time-to-live of the packets the socket sends, see [`Connection::set_ttl`]
+    pub fn ttl(mut self, ttl: u32) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Enable `SO_KEEPALIVE` on the socket, with probes starting after it has been idle for `time`
+    pub fn keepalive(mut self, time: Duration) -> Self {
+        self.keepalive = Some(time);
+        self
+    }
+
+    /// Set the number of bytes the `Sink` implementation may buffer before it applies back-pressure
+    pub fn max_in_flight_bytes(mut self, limit: usize) -> Self {
+        self.max_in_flight_bytes = limit;
+        self
+    }
+
+    /// Set the largest payload the connection accepts from its peer
+    pub fn max_message_size(mut self, limit: usize) -> Self {
+        self.max_message_size = limit;
+        self
+    }
+
+    /// Set a name that identifies the connection in its `Debug` output
+    ///
+    /// With the `metrics` feature, the name also labels the connection's metrics as
+    /// `connection_name`.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Set the name of the connection, or clear it with `None`
+    pub(crate) fn name_opt(mut self, name: Option<String>) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// Exchange protocol versions with the peer before the connection is returned
+    ///
+    /// Applies to [`ConnectionBuilder::connect`] and [`ConnectionBuilder::accept`]. See
+    /// [`Connection::handshake`] for details.
+    #[cfg(feature = "handshake")]
+    pub fn require_handshake(mut self, require: bool) -> Self {
+        self.require_handshake = require;
+        self
+    }
+
+    /// Create a new connection from an existing stream using this configuration
+    pub fn build(self, stream: TcpStream) -> Result<Connection, ConnectionError> {
+        if self.capacity == 0 {
+            return Err(ConnectionError::InvalidConfiguration(
+                "buffer capacity must be greater than zero".into(),
+            ));
+        }
+        if self.write_buffer_size == 0 {
+            return Err(ConnectionError::InvalidConfiguration(
+                "write buffer size must be greater than zero".into(),
+            ));
+        }
+        self.compression.validate()?;
+
+        if let Some(nodelay) = self.nodelay {
+            stream.set_nodelay(nodelay)?;
+        }
+
+        if let Some(ttl) = self.ttl {
+            set_ip_ttl(&stream, ttl)?;
+        }
+
+        if let Some(time) = self.keepalive {
+            set_tcp_keepalive(&stream, Some(time))?;
+        }
+
+        Ok(self.assemble(stream))
+    }
+
+    /// Connect to a socket address and return a new connection using this configuration
+    pub async fn connect<A: ToSocketAddrs>(self, addr: A) -> Result<Connection, ConnectionError> {
+        let stream = connect(addr).await?;
+
+        #[cfg(feature = "handshake")]
+        if self.require_handshake {
+            return self.accept(stream).await;
+        }
+
+        self.build(stream)
+    }
+
+    /// Create a new connection from an accepted stream, performing the handshake if required
+    #[cfg(feature = "handshake")]
+    pub async fn accept(self, stream: TcpStream) -> Result<Connection, ConnectionError> {
+        let require_handshake = self.require_handshake;
+        let mut conn = self.build(stream)?;
+        if require_handshake {
+            conn.handshake().await?;
+        }
+        Ok(conn)
+    }
+
+    /// Create the connection without validating the configuration
+    pub(crate) fn assemble<S: AsyncRead + AsyncWrite + Unpin>(self, stream: S) -> Connection<S> {
+        Connection {
+            buffer: BytesMut::with_capacity(self.capacity),
+            stream: BufWriter::with_capacity(self.write_buffer_size, stream),
+            format: self.format,
+            compression: self.compression,
+            read_timeout: self.read_timeout,
+            write_timeout: self.write_timeout,
+            flush_deadline: None,
+            pending: BytesMut::new(),
+            skipped: Skipped::default(),
+            max_in_flight_bytes: self.max_in_flight_bytes,
+            max_message_size: self.max_message_size,
+            write_buffer_size: self.write_buffer_size,
+            hmac_key: None,
+            middleware: Vec::new(),
+            stats: StatsRecorder::new(self.name.as_deref()),
+            keepalive: None,
+            idle: None,
+            liveness: Arc::new(watch::channel(true).0),
+            name: self.name,
+            #[cfg(feature = "error-hook")]
+            error_hook: None,
+        }
+    }
+}
+
+impl Default for ConnectionBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
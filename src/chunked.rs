@@ -0,0 +1,132 @@
+use crate::{frame, Connection, ConnectionError};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    /// Send everything `reader` produces until it reaches EOF, as a stream of chunks of at most
+    /// `chunk_size` bytes, returning the number of bytes sent
+    ///
+    /// Only one chunk is held in memory at a time, so this suits payloads too large to allocate at
+    /// once, such as files. Every chunk is sent as its own frame, compressed and authenticated
+    /// like any other, and all but the last are marked with a continuation flag. The last chunk is
+    /// the first one shorter than `chunk_size`, which may be empty. The peer must receive the
+    /// stream with [`Connection::read_stream`], and `chunk_size` must not exceed its maximum
+    /// message size.
+    ///
+    /// Fails with [`ConnectionError::InvalidConfiguration`] if `chunk_size` is zero. If reading
+    /// from `reader` fails part of the way through, the peer never receives the end of the stream,
+    /// so the connection should be closed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // Send a file in chunks of 64 KiB
+    ///     let mut file = tokio::fs::File::open("model.bin").await?;
+    ///     let sent = conn.write_stream(&mut file, 64 * 1024).await?;
+    ///     println!("sent {} bytes", sent);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn write_stream<R: AsyncRead + Unpin>(
+        &mut self,
+        reader: &mut R,
+        chunk_size: usize,
+    ) -> Result<u64, ConnectionError> {
+        if chunk_size == 0 {
+            return self.record(Err(ConnectionError::InvalidConfiguration(
+                "chunk size must be greater than zero".into(),
+            )));
+        }
+
+        let mut chunk = vec![0u8; chunk_size];
+        let mut total = 0u64;
+        loop {
+            let mut filled = 0;
+            while filled < chunk_size {
+                match reader.read(&mut chunk[filled..]).await? {
+                    0 => break,
+                    n => filled += n,
+                }
+            }
+
+            let more = filled == chunk_size;
+            let flags = if more { frame::CONTINUATION } else { 0 };
+            self.write_frame(&chunk[..filled], flags).await?;
+            total += filled as u64;
+            if !more {
+                trace!(bytes_written = total, "wrote stream");
+                return Ok(total);
+            }
+        }
+    }
+
+    /// Receive a stream of chunks sent with [`Connection::write_stream`], writing each one into
+    /// `writer` as it arrives and returning the number of bytes received
+    ///
+    /// Only one chunk is held in memory at a time. A frame sent with [`Connection::write_raw`]
+    /// is received as a stream of a single chunk. Fails with
+    /// [`ConnectionError::ConnectionReset`] if the peer closes the connection between two chunks
+    /// before the end of the stream, or with [`ConnectionError::UnexpectedEof`] if it closes the
+    /// connection part of the way through a chunk. If writing into `writer` fails, the rest of the stream is left unread, so the
+    /// connection should be closed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // Save the stream the peer sends into a file
+    ///     let mut file = tokio::fs::File::create("model.bin").await?;
+    ///     let received = conn.read_stream(&mut file).await?;
+    ///     println!("received {} bytes", received);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn read_stream<W: AsyncWrite + Unpin>(
+        &mut self,
+        writer: &mut W,
+    ) -> Result<u64, ConnectionError> {
+        let mut total = 0u64;
+        loop {
+            self.answer_controls().await?;
+            let result = frame::take_chunk(
+                &mut self.buffer,
+                self.max_message_size,
+                self.hmac_key.as_ref(),
+                &self.middleware,
+            );
+            if let Some((chunk, more)) = self.record(result)? {
+                self.stats.record_received_message();
+                writer.write_all(&chunk).await?;
+                total += chunk.len() as u64;
+                if !more {
+                    writer.flush().await?;
+                    trace!(bytes_read = total, "read stream");
+                    return Ok(total);
+                }
+                continue;
+            }
+
+            if 0 == self.read_to_buffer().await? {
+                return self.record(Err(ConnectionError::ConnectionReset(
+                    "connection closed in the middle of a stream".into(),
+                )));
+            }
+        }
+    }
+}
@@ -0,0 +1,292 @@
+//! Encoding and decoding of frames without a connection
+//!
+//! [`encode`] and [`decode`] turn a value into a frame and back exactly like a [`Connection`] with
+//! the default settings does, so serialization code and protocol compatibility can be tested
+//! without a socket. [`LengthDelimitedConnectionCodec`] speaks the same wire format over any
+//! stream through [`tokio_util::codec`], and [`FrameIterator`] splits a capture of that stream
+//! back into frames.
+//!
+//! # Examples
+//!
+//! ```
+//! use connection::codec;
+//!
+//! let frame = codec::encode(&"Hello, world!").unwrap();
+//! let (message, consumed): (String, usize) = codec::decode(&frame).unwrap();
+//! assert_eq!("Hello, world!", message);
+//! assert_eq!(frame.len(), consumed);
+//! ```
+use crate::frame::{self, FRAME_HEADER_SIZE};
+use crate::{
+    CompressionMode, Connection, ConnectionBuilder, ConnectionError, FrameHeader, SerdeFormat,
+    DEFAULT_MAX_MESSAGE_SIZE,
+};
+use bytes::{Bytes, BytesMut};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::{Error, ErrorKind};
+use std::iter::FusedIterator;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::{Decoder, Encoder, Framed, FramedParts};
+
+/// Encode a value as a complete frame, the way [`Connection::write`] sends it with the default
+/// settings
+///
+/// The value is serialized with the default [`SerdeFormat`] and is not compressed.
+pub fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Bytes, ConnectionError> {
+    let payload = SerdeFormat::default().serialize(value)?;
+    let (header, body) = frame::encode(&payload, CompressionMode::None, None, &[])?;
+    let mut frame = BytesMut::with_capacity(FRAME_HEADER_SIZE + body.len());
+    frame.extend_from_slice(&header);
+    frame.extend_from_slice(&body);
+    Ok(frame.freeze())
+}
+
+/// Decode the value held by the frame at the start of `bytes`, the way [`Connection::read`]
+/// receives it with the default settings, returning the value and the length of the frame
+///
+/// The payload is deserialized with the default [`SerdeFormat`], and may be compressed with any
+/// [`CompressionAlgorithm`](crate::CompressionAlgorithm). Bytes after the frame are left alone, so
+/// a buffer holding several frames is decoded by calling this again past the returned length.
+///
+/// Fails if `bytes` does not start with a complete data frame, if the frame is larger than
+/// [`DEFAULT_MAX_MESSAGE_SIZE`], or if it carries an HMAC tag or is part of a stream.
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<(T, usize), ConnectionError> {
+    if frame::at_control(bytes) {
+        return Err(control_frame());
+    }
+    let len = frame::frame_len(bytes, DEFAULT_MAX_MESSAGE_SIZE)?.ok_or_else(|| {
+        Error::new(
+            ErrorKind::UnexpectedEof,
+            "buffer does not hold a complete frame",
+        )
+    })?;
+
+    let value = frame::decode_value(
+        bytes,
+        len,
+        SerdeFormat::default(),
+        DEFAULT_MAX_MESSAGE_SIZE,
+        None,
+        &[],
+    )?;
+    Ok((value, FRAME_HEADER_SIZE + len))
+}
+
+/// A [`tokio_util::codec`] codec that speaks the same wire format as [`Connection`]
+///
+/// Values are encoded with the codec's [`SerdeFormat`] and [`CompressionMode`], then framed with a
+/// [`FrameHeader`]. Decoding yields the decompressed payload of
+/// each frame. The keep-alive control frames of [`Connection::start_keepalive`] are not understood
+/// by the codec.
+///
+/// # Examples
+///
+/// ```no_run
+/// use connection::{LengthDelimitedConnectionCodec, SerdeFormat};
+/// use futures::{SinkExt, StreamExt};
+/// use std::error::Error;
+/// use tokio::net::TcpStream;
+/// use tokio_util::codec::Framed;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn Error>> {
+///     let stream = TcpStream::connect("127.0.0.1:8080").await?;
+///     let codec = LengthDelimitedConnectionCodec::new(SerdeFormat::Bincode);
+///     let mut framed = Framed::new(stream, codec);
+///
+///     // Send a message, then receive the payload of the reply
+///     framed.send("Hello, world!").await?;
+///     let payload = framed.next().await.transpose()?;
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct LengthDelimitedConnectionCodec {
+    format: SerdeFormat,
+    compression: CompressionMode,
+    max_message_size: usize,
+}
+
+impl LengthDelimitedConnectionCodec {
+    /// Create a new codec that encodes values with the given serialization format
+    pub fn new(format: SerdeFormat) -> Self {
+        Self {
+            format,
+            compression: CompressionMode::default(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// Set the compression applied to the payloads the codec encodes
+    pub fn compression(mut self, compression: CompressionMode) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Set the largest payload the codec accepts from the peer
+    pub fn max_message_size(mut self, limit: usize) -> Self {
+        self.max_message_size = limit;
+        self
+    }
+}
+
+impl Default for LengthDelimitedConnectionCodec {
+    fn default() -> Self {
+        Self::new(SerdeFormat::default())
+    }
+}
+
+impl<T: Serialize> Encoder<T> for LengthDelimitedConnectionCodec {
+    type Error = ConnectionError;
+
+    fn encode(&mut self, item: T, dst: &mut BytesMut) -> Result<(), ConnectionError> {
+        let payload = self.format.serialize(&item)?;
+        let (header, body) = frame::encode(&payload, self.compression, None, &[])?;
+        dst.reserve(FRAME_HEADER_SIZE + body.len());
+        dst.extend_from_slice(&header);
+        dst.extend_from_slice(&body);
+        Ok(())
+    }
+}
+
+impl Decoder for LengthDelimitedConnectionCodec {
+    type Item = BytesMut;
+    type Error = ConnectionError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>, ConnectionError> {
+        if frame::at_control(src) {
+            return Err(control_frame());
+        }
+        frame::take_payload(src, self.max_message_size, None, &[])
+    }
+}
+
+/// A data frame found by a [`FrameIterator`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame<'a> {
+    /// The header of the frame
+    pub header: FrameHeader,
+    /// The payload as sent, still compressed and followed by its HMAC tag if the header says so
+    pub payload: &'a [u8],
+}
+
+/// An iterator over the data frames in a byte slice, such as the bytes one side of a connection
+/// sent, taken from a packet capture
+///
+/// Frames are yielded as they are on the wire, without verifying, decompressing or deserializing
+/// their payloads. Control frames are skipped. If the slice ends part of the way through a frame,
+/// the iterator yields [`ConnectionError::UnexpectedEof`] and then stops.
+///
+/// # Examples
+///
+/// ```
+/// use connection::codec::{self, FrameIterator};
+///
+/// let mut capture = codec::encode(&"Hello").unwrap().to_vec();
+/// capture.extend_from_slice(&codec::encode(&"world!").unwrap());
+///
+/// let frames = FrameIterator::new(&capture).collect::<Result<Vec<_>, _>>().unwrap();
+/// assert_eq!(2, frames.len());
+/// assert_eq!(13, frames[0].header.length);
+/// ```
+#[derive(Debug, Clone)]
+pub struct FrameIterator<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> FrameIterator<'a> {
+    /// Create an iterator over the frames at the start of `bytes`
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    /// Returns the bytes that have not been parsed yet
+    pub fn remaining(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// Reports a frame cut short by the end of the slice, after which the iterator is exhausted
+    fn truncated<T>(&mut self) -> Option<Result<T, ConnectionError>> {
+        self.bytes = &[];
+        Some(Err(ConnectionError::UnexpectedEof))
+    }
+}
+
+impl<'a> Iterator for FrameIterator<'a> {
+    type Item = Result<Frame<'a>, ConnectionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while frame::at_control(self.bytes) {
+            match frame::control_at(self.bytes) {
+                Some((_, len)) => self.bytes = &self.bytes[len..],
+                None => return self.truncated(),
+            }
+        }
+        if self.bytes.is_empty() {
+            return None;
+        }
+
+        let len = match frame::frame_len(self.bytes, usize::MAX) {
+            Ok(Some(len)) => len,
+            Ok(None) => return self.truncated(),
+            Err(e) => return Some(Err(e)),
+        };
+        let mut header = [0u8; FRAME_HEADER_SIZE];
+        header.copy_from_slice(&self.bytes[..FRAME_HEADER_SIZE]);
+        let (frame, rest) = self.bytes.split_at(FRAME_HEADER_SIZE + len);
+        self.bytes = rest;
+        Some(Ok(Frame {
+            header: FrameHeader::from_bytes(header),
+            payload: &frame[FRAME_HEADER_SIZE..],
+        }))
+    }
+}
+
+impl FusedIterator for FrameIterator<'_> {}
+
+fn control_frame() -> ConnectionError {
+    Error::new(
+        ErrorKind::InvalidData,
+        "received a control frame, which the codec does not support",
+    )
+    .into()
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    /// Create a connection from a [`Framed`] stream, keeping any bytes it has buffered
+    ///
+    /// The connection adopts the codec's serialization format, compression and message size limit.
+    pub fn from_framed(framed: Framed<S, LengthDelimitedConnectionCodec>) -> Connection<S> {
+        let parts = framed.into_parts();
+        let mut conn = ConnectionBuilder::new()
+            .format(parts.codec.format)
+            .compression(parts.codec.compression)
+            .max_message_size(parts.codec.max_message_size)
+            .assemble(parts.io);
+
+        // Unread frames are read first, and unwritten frames are sent before the next write
+        conn.buffer = parts.read_buf;
+        conn.pending = parts.write_buf;
+        conn
+    }
+
+    /// Convert the connection into a [`Framed`] stream, keeping any bytes it has buffered
+    ///
+    /// Unflushed writes are carried over to the framed stream's write buffer, so nothing is lost.
+    pub fn into_framed(mut self) -> Framed<S, LengthDelimitedConnectionCodec> {
+        let codec = LengthDelimitedConnectionCodec::new(self.format)
+            .compression(self.compression)
+            .max_message_size(self.max_message_size);
+
+        let mut write_buf = BytesMut::from(self.stream.buffer());
+        write_buf.extend_from_slice(&self.pending);
+
+        self.skipped.restore(&mut self.buffer);
+        let mut parts = FramedParts::new::<()>(self.stream.into_inner(), codec);
+        parts.read_buf = self.buffer;
+        parts.write_buf = write_buf;
+        Framed::from_parts(parts)
+    }
+}
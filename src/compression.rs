@@ -0,0 +1,168 @@
+use crate::ConnectionError;
+use std::borrow::Cow;
+use std::io::{Error, ErrorKind, Read};
+
+/// A compression algorithm, identified on the wire by the `compression` field of every
+/// [`FrameHeader`](crate::FrameHeader)
+///
+/// Every connection can decompress every algorithm, so the algorithm only has to be agreed on to
+/// make sure both peers are willing to spend the CPU time, see
+/// [`Connection::negotiate_compression`](crate::Connection::negotiate_compression).
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CompressionAlgorithm {
+    /// Payloads are sent as is
+    None,
+    /// Payloads are compressed with zstd
+    Zstd,
+    /// Payloads are compressed with LZ4, which is faster than zstd but compresses less
+    Lz4,
+    /// Payloads are compressed with Snappy, which is faster than zstd but compresses less
+    Snappy,
+}
+
+impl CompressionAlgorithm {
+    /// The identifier of the algorithm in the `compression` field of a frame header
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            CompressionAlgorithm::None => 0,
+            CompressionAlgorithm::Zstd => 1,
+            CompressionAlgorithm::Lz4 => 2,
+            CompressionAlgorithm::Snappy => 3,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(CompressionAlgorithm::None),
+            1 => Some(CompressionAlgorithm::Zstd),
+            2 => Some(CompressionAlgorithm::Lz4),
+            3 => Some(CompressionAlgorithm::Snappy),
+            _ => None,
+        }
+    }
+}
+
+/// The compression applied to payloads before they are framed
+///
+/// Every frame records how its payload is compressed, so a connection can always read
+/// compressed and uncompressed messages regardless of its own mode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CompressionMode {
+    /// Payloads are sent as is
+    #[default]
+    None,
+    /// Payloads are compressed with zstd at the given compression level
+    ///
+    /// A payload that does not shrink when compressed is sent as is.
+    Zstd(i32),
+    /// Payloads are compressed with LZ4
+    ///
+    /// A payload that does not shrink when compressed is sent as is.
+    Lz4,
+    /// Payloads are compressed with Snappy
+    ///
+    /// A payload that does not shrink when compressed is sent as is.
+    Snappy,
+}
+
+impl CompressionMode {
+    /// Returns the algorithm this mode compresses with
+    pub fn algorithm(self) -> CompressionAlgorithm {
+        match self {
+            CompressionMode::None => CompressionAlgorithm::None,
+            CompressionMode::Zstd(_) => CompressionAlgorithm::Zstd,
+            CompressionMode::Lz4 => CompressionAlgorithm::Lz4,
+            CompressionMode::Snappy => CompressionAlgorithm::Snappy,
+        }
+    }
+
+    /// Compresses a payload according to this mode, returning the algorithm it was compressed
+    /// with and the bytes to send
+    pub(crate) fn compress(
+        self,
+        payload: &[u8],
+    ) -> Result<(CompressionAlgorithm, Cow<'_, [u8]>), ConnectionError> {
+        let compressed = match self {
+            CompressionMode::None => {
+                return Ok((CompressionAlgorithm::None, Cow::Borrowed(payload)))
+            }
+            CompressionMode::Zstd(level) => zstd::bulk::compress(payload, level)?,
+            CompressionMode::Lz4 => lz4_flex::block::compress_prepend_size(payload),
+            CompressionMode::Snappy => snap::raw::Encoder::new()
+                .compress_vec(payload)
+                .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?,
+        };
+
+        if compressed.len() < payload.len() {
+            Ok((self.algorithm(), Cow::Owned(compressed)))
+        } else {
+            Ok((CompressionAlgorithm::None, Cow::Borrowed(payload)))
+        }
+    }
+
+    /// Fails if the compression level is not supported
+    pub(crate) fn validate(self) -> Result<(), ConnectionError> {
+        match self {
+            CompressionMode::Zstd(level) if !zstd::compression_level_range().contains(&level) => {
+                Err(ConnectionError::InvalidConfiguration(format!(
+                    "zstd compression level {} is out of range",
+                    level
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Restores the payload of a frame compressed with the algorithm of the given id, failing if it
+/// decompresses to more than `limit` bytes
+pub(crate) fn decompress(
+    id: u8,
+    payload: &[u8],
+    limit: usize,
+) -> Result<Cow<'_, [u8]>, ConnectionError> {
+    let algorithm = CompressionAlgorithm::from_id(id).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("frame has unknown compression algorithm {:#04x}", id),
+        )
+    })?;
+
+    // LZ4 and Snappy record the decompressed size, so oversized payloads are refused up front
+    let too_large = |claimed: usize| ConnectionError::MessageTooLarge { claimed, limit };
+    let invalid = |e: String| ConnectionError::from(Error::new(ErrorKind::InvalidData, e));
+    match algorithm {
+        CompressionAlgorithm::None => Ok(Cow::Borrowed(payload)),
+        CompressionAlgorithm::Zstd => {
+            let decoder = zstd::stream::read::Decoder::new(payload)?;
+            let mut decompressed = Vec::new();
+            let max = u64::try_from(limit).unwrap_or(u64::MAX).saturating_add(1);
+            decoder.take(max).read_to_end(&mut decompressed)?;
+            if decompressed.len() > limit {
+                return Err(too_large(decompressed.len()));
+            }
+            Ok(Cow::Owned(decompressed))
+        }
+        CompressionAlgorithm::Lz4 => {
+            let (claimed, _) =
+                lz4_flex::block::uncompressed_size(payload).map_err(|e| invalid(e.to_string()))?;
+            if claimed > limit {
+                return Err(too_large(claimed));
+            }
+            lz4_flex::block::decompress_size_prepended(payload)
+                .map(Cow::Owned)
+                .map_err(|e| invalid(e.to_string()))
+        }
+        CompressionAlgorithm::Snappy => {
+            let claimed = snap::raw::decompress_len(payload).map_err(|e| invalid(e.to_string()))?;
+            if claimed > limit {
+                return Err(too_large(claimed));
+            }
+            snap::raw::Decoder::new()
+                .decompress_vec(payload)
+                .map(Cow::Owned)
+                .map_err(|e| invalid(e.to_string()))
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use crate::ConnectionError;
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::{Error, ErrorKind};
+
+/// The serialization format used to encode values on the wire
+///
+/// Both ends of a connection must agree on the format, otherwise values will fail to deserialize.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SerdeFormat {
+    /// A compact binary encoding provided by [`bincode`](https://docs.rs/bincode)
+    ///
+    /// Integers are encoded at their full width in little-endian order, `usize` values and
+    /// lengths as `u64`, and enum variants by their index as a `u32`. The encoding is therefore the
+    /// same on every architecture, so enums make portable protocol messages.
+    #[default]
+    Bincode,
+    /// A human-readable encoding provided by [`serde_json`](https://docs.rs/serde_json)
+    Json,
+    /// A compact, self-describing binary encoding provided by [`rmp-serde`](https://docs.rs/rmp-serde)
+    ///
+    /// Structs are encoded as maps keyed by field name, so peers may add fields without breaking
+    /// older readers.
+    MsgPack,
+    /// The Concise Binary Object Representation (RFC 8949) provided by
+    /// [`ciborium`](https://docs.rs/ciborium)
+    Cbor,
+    /// A compact binary encoding provided by [`postcard`](https://docs.rs/postcard), which is
+    /// also available to `no_std` peers such as microcontrollers
+    #[cfg(feature = "postcard")]
+    Postcard,
+}
+
+impl SerdeFormat {
+    /// Serialize a value into a byte vector using this format
+    pub(crate) fn serialize<T: Serialize + ?Sized>(
+        &self,
+        value: &T,
+    ) -> Result<Vec<u8>, ConnectionError> {
+        match self {
+            SerdeFormat::Bincode => Ok(bincode::serialize(value)?),
+            SerdeFormat::Json => Ok(serde_json::to_vec(value)?),
+            SerdeFormat::MsgPack => Ok(rmp_serde::to_vec_named(value)?),
+            SerdeFormat::Cbor => {
+                let mut buf = Vec::new();
+                ciborium::into_writer(value, &mut buf)
+                    .map_err(|e| ConnectionError::CborError(Box::new(e)))?;
+                Ok(buf)
+            }
+            #[cfg(feature = "postcard")]
+            SerdeFormat::Postcard => Ok(postcard::to_stdvec(value)?),
+        }
+    }
+
+    /// Deserialize a value from a byte slice using this format
+    ///
+    /// Fails if the value does not span the whole slice, since that means the payload holds a
+    /// different type than the one requested.
+    pub(crate) fn deserialize<T: DeserializeOwned>(
+        &self,
+        bytes: &[u8],
+    ) -> Result<T, ConnectionError> {
+        match self {
+            SerdeFormat::Bincode => Ok(bincode::DefaultOptions::new()
+                .with_fixint_encoding()
+                .reject_trailing_bytes()
+                .deserialize(bytes)?),
+            SerdeFormat::Json => Ok(serde_json::from_slice(bytes)?),
+            SerdeFormat::MsgPack => {
+                let mut rest = bytes;
+                let value = rmp_serde::from_read(&mut rest)?;
+                match rest.len() {
+                    0 => Ok(value),
+                    n => Err(ConnectionError::MsgPackError(Box::new(trailing_bytes(n)))),
+                }
+            }
+            SerdeFormat::Cbor => {
+                let mut rest = bytes;
+                let value = ciborium::from_reader(&mut rest)
+                    .map_err(|e| ConnectionError::CborError(Box::new(e)))?;
+                match rest.len() {
+                    0 => Ok(value),
+                    n => Err(ConnectionError::CborError(Box::new(trailing_bytes(n)))),
+                }
+            }
+            #[cfg(feature = "postcard")]
+            SerdeFormat::Postcard => match postcard::take_from_bytes(bytes)? {
+                (value, []) => Ok(value),
+                // postcard has no error for trailing bytes, the closest is a bad encoding
+                _ => Err(postcard::Error::DeserializeBadEncoding.into()),
+            },
+        }
+    }
+}
+
+fn trailing_bytes(n: usize) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("{} trailing bytes after the value", n),
+    )
+}
@@ -0,0 +1,560 @@
+//! The length-prefixed framing shared by every connection type
+use crate::auth::{self, HmacKey};
+use crate::compression::{self, CompressionAlgorithm, CompressionMode};
+use crate::middleware::{self, Middleware};
+use crate::{with_timeout, with_write_timeout, ConnectionError, Priority, SerdeFormat};
+use bytes::{Buf, BytesMut};
+use serde::de::DeserializeOwned;
+use std::borrow::Cow;
+use std::io::Error;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The size of the length prefix that starts every frame
+pub(crate) const LENGTH_PREFIX_SIZE: usize = 4;
+
+/// The size of the header written before every payload
+pub(crate) const FRAME_HEADER_SIZE: usize = FrameHeader::SIZE;
+
+/// The frame flag bit of a chunk which is followed by further chunks of the same stream
+pub(crate) const CONTINUATION: u8 = 0b100;
+
+/// The frame flag bit of a value which a newer value may replace before it is read
+pub(crate) const OVERWRITABLE: u8 = 0b1000;
+
+/// The frame flag bits holding the priority of a value, see [`Priority`]
+pub(crate) const PRIORITY: u8 = 0b11_0000;
+
+/// The frame flag bits holding the schema version of a value, bit 0 being its lowest bit and bits
+/// 6 and 7 its upper bits
+pub(crate) const SCHEMA_VERSION: u8 = 0b1100_0001;
+
+/// The largest schema version that fits in the frame flags
+pub(crate) const MAX_SCHEMA_VERSION: u8 = 0b111;
+
+/// The largest payload that fits in a frame, the length prefixes above it are reserved
+const MAX_PAYLOAD_LEN: u32 = u32::MAX - 3;
+
+/// The reserved length prefix of a ping control frame
+const PING: u32 = u32::MAX;
+
+/// The reserved length prefix of a pong control frame
+const PONG: u32 = u32::MAX - 1;
+
+/// The reserved length prefix of an acknowledgment control frame
+const ACK: u32 = u32::MAX - 2;
+
+/// The size of the payload hash carried by an acknowledgment
+const ACK_HASH_SIZE: usize = 8;
+
+/// The header that starts every data frame, see `WIRE_FORMAT.md` for the complete wire format
+///
+/// A header is [`FrameHeader::SIZE`] bytes long: the length of the payload as a big-endian
+/// `u32`, a flags byte recording how the payload is encoded, the id of the algorithm the payload
+/// is compressed with, and two reserved bytes. The reserved bytes are sent as zero and ignored
+/// when received, so future releases can give them a meaning without breaking older peers.
+///
+/// # Examples
+///
+/// ```
+/// use connection::FrameHeader;
+///
+/// let header = FrameHeader::new(5, 0);
+/// assert_eq!([0, 0, 0, 5, 0, 0, 0, 0], header.to_bytes());
+/// assert_eq!(0, header.compression);
+/// assert_eq!(header, FrameHeader::from_bytes(header.to_bytes()));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    /// The length of the payload that follows the header, as sent
+    pub length: u32,
+    /// A bit field where bit 1 marks a payload followed by an HMAC-SHA256 tag, see
+    /// [`Connection::set_hmac_key`](crate::Connection::set_hmac_key), bit 2 a chunk followed by
+    /// further chunks of the same stream, see
+    /// [`Connection::write_stream`](crate::Connection::write_stream), and bit 3 a value which a
+    /// newer one may replace, see
+    /// [`Connection::write_overwriting`](crate::Connection::write_overwriting). Bits 4 and 5 hold
+    /// the [`Priority`](crate::Priority) of the value, and bits 0, 6 and 7 its schema version, see
+    /// [`Connection::write_versioned`](crate::Connection::write_versioned)
+    pub flags: u8,
+    /// The algorithm the payload is compressed with: `0` for none, `1` for zstd, `2` for LZ4 and
+    /// `3` for Snappy, see [`CompressionAlgorithm`](crate::CompressionAlgorithm)
+    pub compression: u8,
+    /// Reserved for future use, always zero when sent by this crate
+    pub reserved: [u8; 2],
+}
+
+impl FrameHeader {
+    /// The number of bytes a header occupies on the wire
+    pub const SIZE: usize = 8;
+
+    /// Create a header for an uncompressed payload of the given length with the reserved bytes
+    /// zeroed
+    pub fn new(length: u32, flags: u8) -> Self {
+        Self {
+            length,
+            flags,
+            compression: 0,
+            reserved: [0; 2],
+        }
+    }
+
+    /// Encodes the header as it is sent on the wire
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0u8; Self::SIZE];
+        bytes[..LENGTH_PREFIX_SIZE].copy_from_slice(&self.length.to_be_bytes());
+        bytes[LENGTH_PREFIX_SIZE] = self.flags;
+        bytes[LENGTH_PREFIX_SIZE + 1] = self.compression;
+        bytes[LENGTH_PREFIX_SIZE + 2..].copy_from_slice(&self.reserved);
+        bytes
+    }
+
+    /// Decodes a header received from the wire
+    pub fn from_bytes(bytes: [u8; Self::SIZE]) -> Self {
+        let mut length = [0u8; LENGTH_PREFIX_SIZE];
+        length.copy_from_slice(&bytes[..LENGTH_PREFIX_SIZE]);
+        let mut reserved = [0u8; 2];
+        reserved.copy_from_slice(&bytes[LENGTH_PREFIX_SIZE + 2..]);
+        Self {
+            length: u32::from_be_bytes(length),
+            flags: bytes[LENGTH_PREFIX_SIZE],
+            compression: bytes[LENGTH_PREFIX_SIZE + 1],
+            reserved,
+        }
+    }
+
+    /// Decodes the header at the front of a buffer holding at least [`FrameHeader::SIZE`] bytes
+    fn parse(buffer: &[u8]) -> Self {
+        let mut bytes = [0u8; Self::SIZE];
+        bytes.copy_from_slice(&buffer[..Self::SIZE]);
+        Self::from_bytes(bytes)
+    }
+}
+
+/// A frame used by the connections themselves rather than carrying a user payload
+///
+/// Control frames start with a reserved length prefix and have no [`FrameHeader`]. Only acknowledgments
+/// carry data after the prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Control {
+    /// A keep-alive probe which the peer answers with a pong
+    Ping,
+    /// The answer to a ping
+    Pong,
+    /// Confirms that the payload with the given hash was received, see [`payload_hash`]
+    Ack(u64),
+}
+
+impl Control {
+    fn encode(self) -> Vec<u8> {
+        match self {
+            Control::Ping => PING.to_be_bytes().to_vec(),
+            Control::Pong => PONG.to_be_bytes().to_vec(),
+            Control::Ack(hash) => [&ACK.to_be_bytes()[..], &hash.to_be_bytes()].concat(),
+        }
+    }
+}
+
+/// Returns `true` if the buffer starts with the length prefix of a control frame
+pub(crate) fn at_control(buffer: &[u8]) -> bool {
+    buffer.len() >= LENGTH_PREFIX_SIZE && reserved_prefix(buffer)
+}
+
+fn reserved_prefix(buffer: &[u8]) -> bool {
+    let mut prefix = [0u8; LENGTH_PREFIX_SIZE];
+    prefix.copy_from_slice(&buffer[..LENGTH_PREFIX_SIZE]);
+    u32::from_be_bytes(prefix) > MAX_PAYLOAD_LEN
+}
+
+/// Consumes the next frame from the buffer if it is a control frame that has been fully received
+pub(crate) fn take_control(buffer: &mut BytesMut) -> Option<Control> {
+    let (control, len) = control_at(buffer)?;
+    buffer.advance(len);
+    Some(control)
+}
+
+/// Decodes the control frame at the front of the buffer if it has been fully received, along
+/// with its length
+pub(crate) fn control_at(buffer: &[u8]) -> Option<(Control, usize)> {
+    if buffer.len() < LENGTH_PREFIX_SIZE {
+        return None;
+    }
+
+    let mut prefix = [0u8; LENGTH_PREFIX_SIZE];
+    prefix.copy_from_slice(&buffer[..LENGTH_PREFIX_SIZE]);
+    let (control, len) = match u32::from_be_bytes(prefix) {
+        PING => (Control::Ping, LENGTH_PREFIX_SIZE),
+        PONG => (Control::Pong, LENGTH_PREFIX_SIZE),
+        ACK if buffer.len() >= LENGTH_PREFIX_SIZE + ACK_HASH_SIZE => {
+            let mut hash = [0u8; ACK_HASH_SIZE];
+            hash.copy_from_slice(&buffer[LENGTH_PREFIX_SIZE..LENGTH_PREFIX_SIZE + ACK_HASH_SIZE]);
+            let control = Control::Ack(u64::from_be_bytes(hash));
+            (control, LENGTH_PREFIX_SIZE + ACK_HASH_SIZE)
+        }
+        _ => return None,
+    };
+
+    Some((control, len))
+}
+
+/// Write a control frame into the stream and flush it
+pub(crate) async fn write_control<W: AsyncWrite + Unpin>(
+    stream: &mut W,
+    control: Control,
+    timeout: Option<Duration>,
+) -> Result<(), ConnectionError> {
+    with_write_timeout(timeout, write_to_stream(stream, &control.encode())).await
+}
+
+/// Hashes a serialized payload with 64-bit FNV-1a, which is stable across platforms and releases
+pub(crate) fn payload_hash(payload: &[u8]) -> u64 {
+    payload.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Attempts to deserialize a T from the next complete frame in the buffer.
+///
+/// The frame is only consumed from the buffer once it has been fully received. A frame that fails
+/// to deserialize is consumed as well, so the next read starts at the following frame.
+pub(crate) fn parse_value<T: DeserializeOwned>(
+    buffer: &mut BytesMut,
+    format: SerdeFormat,
+    max_message_size: usize,
+    key: Option<&HmacKey>,
+    middleware: &[Arc<dyn Middleware>],
+) -> Result<Option<T>, ConnectionError> {
+    let len = match frame_len(buffer, max_message_size)? {
+        Some(len) => len,
+        None => return Ok(None),
+    };
+
+    let result = decode_value(buffer, len, format, max_message_size, key, middleware);
+    buffer.advance(FRAME_HEADER_SIZE + len);
+    result.map(Some)
+}
+
+/// Attempts to deserialize a T from the next complete frame in the buffer without consuming it
+pub(crate) fn peek_value<T: DeserializeOwned>(
+    buffer: &BytesMut,
+    format: SerdeFormat,
+    max_message_size: usize,
+    key: Option<&HmacKey>,
+    middleware: &[Arc<dyn Middleware>],
+) -> Result<Option<T>, ConnectionError> {
+    let len = match frame_len(buffer, max_message_size)? {
+        Some(len) => len,
+        None => return Ok(None),
+    };
+
+    decode_value(buffer, len, format, max_message_size, key, middleware).map(Some)
+}
+
+/// Verifies, decompresses and deserializes the payload of the complete frame at the front of the
+/// buffer
+pub(crate) fn decode_value<T: DeserializeOwned>(
+    buffer: &[u8],
+    len: usize,
+    format: SerdeFormat,
+    max_message_size: usize,
+    key: Option<&HmacKey>,
+    middleware: &[Arc<dyn Middleware>],
+) -> Result<T, ConnectionError> {
+    let header = FrameHeader::parse(buffer);
+    if check_flags(header.flags)? {
+        return Err(unexpected_chunk());
+    }
+    let body = &buffer[FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + len];
+    let payload = auth::open(key, &buffer[..FRAME_HEADER_SIZE], body)?;
+    let payload = middleware::on_read(middleware, payload);
+    compression::decompress(header.compression, &payload, max_message_size)
+        .and_then(|payload| format.deserialize(&payload))
+}
+
+/// Removes the payload of the next frame from the buffer if it has been fully received
+///
+/// Chunks of a stream are rejected, since they only make sense as part of the whole stream.
+pub(crate) fn take_payload(
+    buffer: &mut BytesMut,
+    max_message_size: usize,
+    key: Option<&HmacKey>,
+    middleware: &[Arc<dyn Middleware>],
+) -> Result<Option<BytesMut>, ConnectionError> {
+    match take_chunk(buffer, max_message_size, key, middleware)? {
+        Some((_, true)) => Err(unexpected_chunk()),
+        chunk => Ok(chunk.map(|(payload, _)| payload)),
+    }
+}
+
+fn unexpected_chunk() -> ConnectionError {
+    Error::new(
+        std::io::ErrorKind::InvalidData,
+        "received a chunk of a stream, which must be read with read_stream",
+    )
+    .into()
+}
+
+/// Fails if a data frame has flags this crate does not know, returning whether it is a chunk
+/// followed by further chunks of the same stream
+fn check_flags(flags: u8) -> Result<bool, ConnectionError> {
+    let known = auth::AUTHENTICATED | CONTINUATION | OVERWRITABLE | PRIORITY | SCHEMA_VERSION;
+    if flags & !known != 0 || Priority::from_flags(flags).is_none() {
+        return Err(unknown_flags(flags));
+    }
+    Ok(flags & CONTINUATION != 0)
+}
+
+fn unknown_flags(flags: u8) -> ConnectionError {
+    Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("frame has unknown flags {:#04x}", flags),
+    )
+    .into()
+}
+
+/// Removes the payload of the next frame from the buffer if it has been fully received, along
+/// with whether further chunks of the same stream follow it
+pub(crate) fn take_chunk(
+    buffer: &mut BytesMut,
+    max_message_size: usize,
+    key: Option<&HmacKey>,
+    middleware: &[Arc<dyn Middleware>],
+) -> Result<Option<(BytesMut, bool)>, ConnectionError> {
+    let len = match frame_len(buffer, max_message_size)? {
+        Some(len) => len,
+        None => return Ok(None),
+    };
+
+    let header = buffer.split_to(FRAME_HEADER_SIZE);
+    let mut payload = buffer.split_to(len);
+    let parsed = FrameHeader::parse(&header);
+    let more = check_flags(parsed.flags)?;
+    let payload_len = auth::open(key, &header, &payload)?.len();
+    payload.truncate(payload_len);
+    let transformed = match middleware::on_read(middleware, &payload) {
+        Cow::Owned(transformed) => Some(transformed),
+        Cow::Borrowed(_) => None,
+    };
+    if let Some(transformed) = transformed {
+        payload = BytesMut::from(&transformed[..]);
+    }
+
+    match compression::decompress(parsed.compression, &payload, max_message_size)? {
+        Cow::Borrowed(_) => Ok(Some((payload, more))),
+        Cow::Owned(decompressed) => Ok(Some((BytesMut::from(&decompressed[..]), more))),
+    }
+}
+
+/// Discards the next frame if it is marked as overwritable and another complete frame follows it,
+/// returning whether it was discarded
+pub(crate) fn skip_overwritten(
+    buffer: &mut BytesMut,
+    max_message_size: usize,
+) -> Result<bool, ConnectionError> {
+    let len = match frame_len(buffer, max_message_size)? {
+        Some(len) if buffer[LENGTH_PREFIX_SIZE] & OVERWRITABLE != 0 => len,
+        _ => return Ok(false),
+    };
+
+    if frame_len(&buffer[FRAME_HEADER_SIZE + len..], max_message_size)?.is_none() {
+        return Ok(false);
+    }
+    buffer.advance(FRAME_HEADER_SIZE + len);
+    Ok(true)
+}
+
+/// Removes the complete frame at the front of the buffer along with its priority, returning it
+/// whole so it can be decoded later like the front of a buffer
+///
+/// The buffer must not start with a control frame.
+pub(crate) fn take_prioritized(
+    buffer: &mut BytesMut,
+    max_message_size: usize,
+) -> Result<Option<(Priority, BytesMut)>, ConnectionError> {
+    let Some(len) = frame_len(buffer, max_message_size)? else {
+        return Ok(None);
+    };
+    let flags = buffer[LENGTH_PREFIX_SIZE];
+    let priority = Priority::from_flags(flags).ok_or_else(|| unknown_flags(flags))?;
+    Ok(Some((priority, buffer.split_to(FRAME_HEADER_SIZE + len))))
+}
+
+/// Returns the flag bits recording a schema version
+pub(crate) fn schema_version_flags(version: u8) -> u8 {
+    (version & 1) | (version >> 1) << 6
+}
+
+/// Returns the schema version recorded in the frame flags
+pub(crate) fn schema_version(flags: u8) -> u8 {
+    (flags & 1) | (flags >> 6) << 1
+}
+
+/// Returns the flags of the next frame if it is completely present in the buffer
+pub(crate) fn peek_flags(
+    buffer: &[u8],
+    max_message_size: usize,
+) -> Result<Option<u8>, ConnectionError> {
+    Ok(frame_len(buffer, max_message_size)?.map(|_| FrameHeader::parse(buffer).flags))
+}
+
+/// Returns the payload length of the next frame if it is completely present in the buffer
+///
+/// Fails as soon as the header is received if it claims a payload larger than `max_message_size`.
+/// The authentication tag of the frame, if it carries one, does not count toward the limit.
+pub(crate) fn frame_len(
+    buffer: &[u8],
+    max_message_size: usize,
+) -> Result<Option<usize>, ConnectionError> {
+    // A control frame that has not been fully received yet is not the start of a payload
+    if buffer.len() < LENGTH_PREFIX_SIZE || reserved_prefix(buffer) {
+        return Ok(None);
+    }
+
+    let mut prefix = [0u8; LENGTH_PREFIX_SIZE];
+    prefix.copy_from_slice(&buffer[..LENGTH_PREFIX_SIZE]);
+    let len = u32::from_be_bytes(prefix) as usize;
+
+    // Until the flags are received, the frame may still turn out to carry a tag
+    let authenticated = buffer
+        .get(LENGTH_PREFIX_SIZE)
+        .map(|flags| flags & auth::AUTHENTICATED != 0);
+    let tag_size = match authenticated {
+        Some(true) => auth::TAG_SIZE,
+        _ => 0,
+    };
+    let allowance = authenticated.map_or(auth::TAG_SIZE, |_| tag_size);
+
+    if len > max_message_size.saturating_add(allowance) {
+        return Err(ConnectionError::MessageTooLarge {
+            claimed: len - tag_size,
+            limit: max_message_size,
+        });
+    }
+
+    if buffer.len() < FRAME_HEADER_SIZE + len {
+        return Ok(None);
+    }
+
+    Ok(Some(len))
+}
+
+/// Compresses a payload as configured, passes it through the middleware and appends its tag if
+/// a key is given, returning the header of its frame and the bytes to write after it
+pub(crate) fn encode<'a>(
+    payload: &'a [u8],
+    compression: CompressionMode,
+    key: Option<&HmacKey>,
+    middleware: &[Arc<dyn Middleware>],
+) -> Result<([u8; FRAME_HEADER_SIZE], Cow<'a, [u8]>), ConnectionError> {
+    encode_with_flags(payload, compression, key, 0, middleware)
+}
+
+/// Encodes a frame like [`encode`], setting the given flags in its header
+pub(crate) fn encode_with_flags<'a>(
+    payload: &'a [u8],
+    compression: CompressionMode,
+    key: Option<&HmacKey>,
+    flags: u8,
+    middleware: &[Arc<dyn Middleware>],
+) -> Result<([u8; FRAME_HEADER_SIZE], Cow<'a, [u8]>), ConnectionError> {
+    let (algorithm, body) = compression.compress(payload)?;
+    let body = middleware::on_write(middleware, body);
+    let Some(key) = key else {
+        return Ok((frame_header(body.len(), flags, algorithm)?, body));
+    };
+
+    let flags = flags | auth::AUTHENTICATED;
+    let header = frame_header(body.len() + auth::TAG_SIZE, flags, algorithm)?;
+    let mut tagged = body.into_owned();
+    tagged.extend_from_slice(&key.tag(&header, &tagged));
+    Ok((header, Cow::Owned(tagged)))
+}
+
+/// Encodes the header for a payload of the given size
+pub(crate) fn frame_header(
+    len: usize,
+    flags: u8,
+    compression: CompressionAlgorithm,
+) -> Result<[u8; FRAME_HEADER_SIZE], ConnectionError> {
+    let len = u32::try_from(len)
+        .ok()
+        .filter(|len| *len <= MAX_PAYLOAD_LEN)
+        .ok_or_else(|| {
+            Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "payload is too large to fit in a single frame",
+            )
+        })?;
+
+    let header = FrameHeader {
+        compression: compression.id(),
+        ..FrameHeader::new(len, flags)
+    };
+    Ok(header.to_bytes())
+}
+
+/// Write a payload into the stream as a single length-prefixed frame, returning the size of the
+/// payload once compressed
+pub(crate) async fn write_frame<W: AsyncWrite + Unpin>(
+    stream: &mut W,
+    payload: &[u8],
+    flags: u8,
+    compression: CompressionMode,
+    timeout: Option<Duration>,
+    key: Option<&HmacKey>,
+    middleware: &[Arc<dyn Middleware>],
+) -> Result<usize, ConnectionError> {
+    let (header, body) = encode_with_flags(payload, compression, key, flags, middleware)?;
+
+    with_write_timeout(timeout, async {
+        stream.write_all(&header).await?;
+        write_to_stream(stream, &body).await
+    })
+    .await?;
+    Ok(body.len())
+}
+
+/// Write a payload into the stream as a single length-prefixed frame without flushing it,
+/// returning the size of the payload once compressed
+pub(crate) async fn buffer_frame<W: AsyncWrite + Unpin>(
+    stream: &mut W,
+    payload: &[u8],
+    flags: u8,
+    compression: CompressionMode,
+    key: Option<&HmacKey>,
+    middleware: &[Arc<dyn Middleware>],
+) -> Result<usize, ConnectionError> {
+    let (header, body) = encode_with_flags(payload, compression, key, flags, middleware)?;
+    stream.write_all(&header).await?;
+    stream.write_all(&body).await?;
+    Ok(body.len())
+}
+
+/// Write a byte slice into the stream
+pub(crate) async fn write_to_stream<W: AsyncWrite + Unpin>(
+    stream: &mut W,
+    buf: &[u8],
+) -> Result<(), ConnectionError> {
+    stream.write_all(buf).await?;
+    stream.flush().await?;
+    trace!(bytes_written = buf.len(), "flushed stream");
+    Ok(())
+}
+
+/// Reads more bytes from the stream into the buffer, returning the number of bytes read
+///
+/// A return value of `0` means the peer closed the connection on a frame boundary. Fails with
+/// [`ConnectionError::UnexpectedEof`] if the peer closed the connection with part of a frame
+/// still in the buffer.
+pub(crate) async fn read_to_buffer<R: AsyncRead + Unpin>(
+    stream: &mut R,
+    buffer: &mut BytesMut,
+    timeout: Option<Duration>,
+) -> Result<usize, ConnectionError> {
+    let n = with_timeout(timeout, async { Ok(stream.read_buf(buffer).await?) }).await?;
+    if 0 == n && !buffer.is_empty() {
+        return Err(ConnectionError::UnexpectedEof);
+    }
+    Ok(n)
+}
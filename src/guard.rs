@@ -0,0 +1,127 @@
+use crate::Connection;
+use serde::Serialize;
+use std::ops::{Deref, DerefMut};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::runtime::Handle;
+
+/// A connection that sends a final goodbye message to its peer when it is dropped, created by
+/// [`Connection::into_guarded`]
+///
+/// The guard dereferences to the connection, so it can be used like one until it is dropped.
+///
+/// Since a destructor cannot wait on the socket, dropping the guard spawns a detached task onto
+/// the current Tokio runtime which writes the goodbye message, shuts down the write side of the
+/// connection and then releases the socket. This has a few limitations:
+///
+/// - The goodbye message is not sent if the guard is dropped outside of a Tokio runtime.
+/// - Nothing waits for the task, so the message may be lost if the runtime shuts down first, for
+///   example when the guard is dropped at the end of `main`.
+/// - A failure to send the message cannot be reported and is ignored.
+///
+/// Use [`ConnectionGuard::into_inner`] to take the connection back without saying goodbye.
+///
+/// # Examples
+///
+/// ```no_run
+/// use connection::Connection;
+/// use std::error::Error;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn Error>> {
+///     // Connect to a peer
+///     let conn = Connection::dial("127.0.0.1:8080").await?;
+///     let mut conn = conn.into_guarded("goodbye".to_string());
+///
+///     // Use the connection as usual, the peer receives "goodbye" once it is dropped
+///     conn.write(&"Hello, world!").await?;
+///     drop(conn);
+///
+///     Ok(())
+/// }
+/// ```
+pub struct ConnectionGuard<G, S = TcpStream>
+where
+    G: Serialize,
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    /// Both are only taken on drop or by `into_inner`
+    conn: Option<Connection<S>>,
+    goodbye: Option<G>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> Connection<S> {
+    /// Wrap the connection in a guard that sends `goodbye` to the peer when it is dropped
+    ///
+    /// See [`ConnectionGuard`] for the limitations of sending a message from a destructor.
+    pub fn into_guarded<G: Serialize>(self, goodbye: G) -> ConnectionGuard<G, S> {
+        ConnectionGuard {
+            conn: Some(self),
+            goodbye: Some(goodbye),
+        }
+    }
+}
+
+impl<G, S> ConnectionGuard<G, S>
+where
+    G: Serialize,
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    /// Take back the connection without sending the goodbye message
+    pub fn into_inner(mut self) -> Connection<S> {
+        self.goodbye = None;
+        self.conn
+            .take()
+            .expect("the connection is only taken on drop")
+    }
+}
+
+impl<G, S> Deref for ConnectionGuard<G, S>
+where
+    G: Serialize,
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Target = Connection<S>;
+
+    fn deref(&self) -> &Connection<S> {
+        self.conn
+            .as_ref()
+            .expect("the connection is only taken on drop")
+    }
+}
+
+impl<G, S> DerefMut for ConnectionGuard<G, S>
+where
+    G: Serialize,
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    fn deref_mut(&mut self) -> &mut Connection<S> {
+        self.conn
+            .as_mut()
+            .expect("the connection is only taken on drop")
+    }
+}
+
+impl<G, S> Drop for ConnectionGuard<G, S>
+where
+    G: Serialize,
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    fn drop(&mut self) {
+        let (Some(mut conn), Some(goodbye)) = (self.conn.take(), self.goodbye.take()) else {
+            return;
+        };
+
+        // Without a runtime there is nothing to drive the write, so the socket is simply closed,
+        // as it is when the goodbye message fails to serialize
+        let (Ok(handle), Ok(payload)) = (Handle::try_current(), conn.format.serialize(&goodbye))
+        else {
+            return;
+        };
+        handle.spawn(async move {
+            if conn.write_raw(&payload).await.is_ok() {
+                let _ = conn.stream.shutdown().await;
+            }
+        });
+    }
+}
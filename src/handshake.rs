@@ -0,0 +1,89 @@
+use crate::{Connection, ConnectionError};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Identifies a handshake frame sent by this library ("CONN")
+const MAGIC: u32 = 0x434F_4E4E;
+
+/// The version of the wire format spoken by this build of the library
+pub const PROTOCOL_VERSION: u16 = 3;
+
+/// The size of an encoded handshake frame's payload
+const HANDSHAKE_LEN: usize = 4 + 2 + 8;
+
+/// The first frame each side sends when a handshake is required
+///
+/// It is encoded with fixed-width big-endian fields rather than the connection's
+/// [`SerdeFormat`](crate::SerdeFormat), so peers can compare versions even if they disagree on
+/// the format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct HandshakeFrame {
+    magic: u32,
+    version: u16,
+    capabilities: u64,
+}
+
+impl HandshakeFrame {
+    fn local() -> Self {
+        Self {
+            magic: MAGIC,
+            version: PROTOCOL_VERSION,
+            capabilities: 0,
+        }
+    }
+
+    fn encode(&self) -> [u8; HANDSHAKE_LEN] {
+        let mut buf = [0u8; HANDSHAKE_LEN];
+        buf[..4].copy_from_slice(&self.magic.to_be_bytes());
+        buf[4..6].copy_from_slice(&self.version.to_be_bytes());
+        buf[6..].copy_from_slice(&self.capabilities.to_be_bytes());
+        buf
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        if buf.len() != HANDSHAKE_LEN {
+            return None;
+        }
+
+        Some(Self {
+            magic: u32::from_be_bytes(buf[..4].try_into().ok()?),
+            version: u16::from_be_bytes(buf[4..6].try_into().ok()?),
+            capabilities: u64::from_be_bytes(buf[6..].try_into().ok()?),
+        })
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    /// Exchange protocol versions with the peer, which must perform the handshake as well
+    ///
+    /// This must happen before any other message is sent. It fails with
+    /// [`ConnectionError::VersionMismatch`] if the peer speaks a different version of the wire
+    /// format, reporting a remote version of `0` if the peer did not send a handshake at all.
+    ///
+    /// Connections created by [`ConnectionBuilder::connect`](crate::ConnectionBuilder::connect)
+    /// and [`ConnectionBuilder::accept`](crate::ConnectionBuilder::accept) perform the handshake
+    /// automatically when [`ConnectionBuilder::require_handshake`](crate::ConnectionBuilder::require_handshake)
+    /// is set.
+    pub async fn handshake(&mut self) -> Result<(), ConnectionError> {
+        let local = HandshakeFrame::local();
+        self.write_raw(&local.encode()).await?;
+
+        let payload = self
+            .read_raw()
+            .await?
+            .ok_or(ConnectionError::Disconnected)?;
+
+        let remote = HandshakeFrame::decode(&payload)
+            .filter(|remote| remote.magic == MAGIC)
+            .map_or(0, |remote| remote.version);
+        if remote != local.version {
+            debug!(local = local.version, remote, "handshake failed");
+            return Err(ConnectionError::VersionMismatch {
+                local: local.version,
+                remote,
+            });
+        }
+
+        trace!(version = remote, "completed handshake");
+        Ok(())
+    }
+}
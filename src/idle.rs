@@ -0,0 +1,102 @@
+use crate::{Connection, ConnectionError};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::time::Instant;
+
+/// Tracks how long a connection has gone without activity
+pub(crate) struct IdleTimer {
+    timeout: Duration,
+    last_activity: Instant,
+    /// Set once the connection has been closed for being idle
+    expired: bool,
+}
+
+impl IdleTimer {
+    fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            last_activity: Instant::now(),
+            expired: false,
+        }
+    }
+
+    /// Returns the instant at which the connection becomes idle
+    pub(crate) fn deadline(&self) -> Instant {
+        self.last_activity + self.timeout
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    /// Close the connection once it has gone `timeout` without any activity, or remove the idle
+    /// timeout with `None`
+    ///
+    /// Every read that receives bytes and every write that completes counts as activity and
+    /// restarts the timer. A read that waits on the peer for longer than the idle timeout closes
+    /// the connection and fails with [`ConnectionError::IdleTimeout`]. Since nothing runs in the
+    /// background, a connection that is not being read from is only closed by the next read or
+    /// write, which fails the same way. Every later read or write fails with
+    /// [`ConnectionError::IdleTimeout`] as well.
+    ///
+    /// This catches peers that disappear without closing the connection, which would otherwise
+    /// keep it open forever.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection, ConnectionError};
+    /// use std::error::Error;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///     conn.set_idle_timeout(Some(Duration::from_secs(60)));
+    ///
+    ///     // Read messages until the peer goes quiet for a minute
+    ///     loop {
+    ///         match conn.read::<String>().await {
+    ///             Ok(Some(message)) => println!("{}", message),
+    ///             Ok(None) | Err(ConnectionError::IdleTimeout) => break,
+    ///             Err(e) => return Err(e.into()),
+    ///         }
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.idle = timeout.map(IdleTimer::new);
+    }
+
+    /// Returns the idle timeout of this connection
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle.as_ref().map(|idle| idle.timeout)
+    }
+
+    /// Restart the idle timer after a successful read or write
+    pub(crate) fn touch(&mut self) {
+        if let Some(idle) = &mut self.idle {
+            idle.last_activity = Instant::now();
+        }
+    }
+
+    /// Fails with [`ConnectionError::IdleTimeout`] if the connection has been idle for too long,
+    /// closing it the first time
+    pub(crate) async fn check_idle(&mut self) -> Result<(), ConnectionError> {
+        match &self.idle {
+            Some(idle) if idle.expired => self.record(Err(ConnectionError::IdleTimeout)),
+            Some(idle) if idle.deadline() <= Instant::now() => self.expire_idle().await,
+            _ => Ok(()),
+        }
+    }
+
+    /// Close the connection for being idle and fail with [`ConnectionError::IdleTimeout`]
+    pub(crate) async fn expire_idle<T>(&mut self) -> Result<T, ConnectionError> {
+        if let Some(idle) = &mut self.idle {
+            idle.expired = true;
+        }
+        let _ = self.stream.shutdown().await;
+        self.record(Err(ConnectionError::IdleTimeout))
+    }
+}
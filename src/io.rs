@@ -0,0 +1,233 @@
+use crate::compression::CompressionAlgorithm;
+use crate::{frame, with_write_timeout, CompressionMode, Connection, ConnectionError};
+use bytes::Buf;
+use std::io::{Error, IoSlice};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::TcpStream;
+
+/// Zero bytes are written from this buffer in chunks instead of allocating one per call
+static ZEROES: [u8; 4096] = [0; 4096];
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    /// Write exactly `n` zero bytes into the stream, bypassing the serialization layer
+    ///
+    /// This is meant for binary protocols that pad between fields to keep them aligned. The
+    /// padding is not framed, so the peer must know to expect it and discard it with
+    /// [`Connection::skip`] before reading the next message.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // Pad the message out to the next 64 byte boundary
+    ///     conn.write(&"Hello, world!").await?;
+    ///     let sent = conn.stats().bytes_sent as usize;
+    ///     conn.write_zeroes((64 - sent % 64) % 64).await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn write_zeroes(&mut self, n: usize) -> Result<(), ConnectionError> {
+        self.check_idle().await?;
+        let timeout = self.write_timeout;
+        let result = with_write_timeout(timeout, async {
+            self.write_pending().await?;
+            let mut remaining = n;
+            while remaining > 0 {
+                let chunk = remaining.min(ZEROES.len());
+                self.stream.write_all(&ZEROES[..chunk]).await?;
+                remaining -= chunk;
+            }
+            self.stream.flush().await?;
+            Ok(())
+        })
+        .await;
+        if result.is_ok() {
+            self.stats.record_sent_bytes(n);
+            self.touch();
+        }
+        self.record(result)
+    }
+
+    /// Read and discard exactly `n` bytes from the stream, bypassing the serialization layer
+    ///
+    /// This is the counterpart of [`Connection::write_zeroes`]. Bytes that were already received
+    /// are skipped first. Fails with [`ConnectionError::ConnectionReset`] if the peer closes the
+    /// connection before `n` bytes arrive.
+    pub async fn skip(&mut self, n: usize) -> Result<(), ConnectionError> {
+        self.skipped.restore(&mut self.buffer);
+        let mut remaining = n;
+        loop {
+            let skipped = remaining.min(self.buffer.len());
+            self.buffer.advance(skipped);
+            remaining -= skipped;
+            if remaining == 0 {
+                return Ok(());
+            }
+
+            if 0 == self.read_to_buffer().await? {
+                return self.record(Err(ConnectionError::ConnectionReset(format!(
+                    "connection closed with {remaining} of {n} bytes left to skip"
+                ))));
+            }
+        }
+    }
+
+    /// Write the concatenation of several buffers into the stream as a single frame, without
+    /// copying them into one payload first
+    ///
+    /// The peer receives one payload, as if it had been written with [`Connection::write_raw`],
+    /// so this suits protocols that already hold a message in several segments, such as a header
+    /// and a body. Returns the length of the payload.
+    ///
+    /// The frame header and the buffers are handed to the stream together with a vectored write.
+    /// When the connection compresses or authenticates payloads, or passes them through
+    /// middleware, the buffers have to be joined first, so nothing is saved. This method shadows [`AsyncWriteExt::write_vectored`], which writes
+    /// raw bytes without framing them.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    /// use std::io::IoSlice;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // Send a header and a body as one payload
+    ///     let header = b"LEN 5\n";
+    ///     let body = b"hello";
+    ///     conn.write_vectored(&[IoSlice::new(header), IoSlice::new(body)]).await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize, ConnectionError> {
+        let len = bufs.iter().map(|buf| buf.len()).sum();
+        if self.compression != CompressionMode::None
+            || self.hmac_key.is_some()
+            || !self.middleware.is_empty()
+        {
+            let payload: Vec<&[u8]> = bufs.iter().map(|buf| &**buf).collect();
+            self.write_raw(&payload.concat()).await?;
+            return Ok(len);
+        }
+
+        self.check_idle().await?;
+        let header = self.record(frame::frame_header(len, 0, CompressionAlgorithm::None))?;
+        let timeout = self.write_timeout;
+        let result = with_write_timeout(timeout, async {
+            self.write_pending().await?;
+            let mut slices = Vec::with_capacity(bufs.len() + 1);
+            slices.push(IoSlice::new(&header));
+            slices.extend(bufs.iter().map(|buf| IoSlice::new(buf)));
+
+            let mut slices = &mut slices[..];
+            while !slices.is_empty() {
+                let n = self.stream.write_vectored(slices).await?;
+                if n == 0 {
+                    return Err(Error::from(std::io::ErrorKind::WriteZero).into());
+                }
+                IoSlice::advance_slices(&mut slices, n);
+            }
+            self.stream.flush().await?;
+            Ok(())
+        })
+        .await;
+        if result.is_ok() {
+            self.stats.record_sent(len);
+            self.touch();
+        }
+        self.record(result).map(|_| len)
+    }
+}
+
+/// Reads the raw bytes of the stream, bypassing the serialization layer
+///
+/// Bytes that were already received but not yet read as a message are returned first.
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for Connection<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = &mut *self;
+        this.skipped.restore(&mut this.buffer);
+        if !this.buffer.is_empty() {
+            let n = buf.remaining().min(this.buffer.len());
+            buf.put_slice(&this.buffer[..n]);
+            this.buffer.advance(n);
+            return Poll::Ready(Ok(()));
+        }
+
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut this.stream).poll_read(cx, buf))?;
+        this.stats
+            .record_received_bytes(buf.filled().len() - filled);
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Writes raw bytes into the stream, bypassing the serialization layer
+///
+/// Frames queued by the `Sink` implementation are written first, so bytes are never interleaved
+/// with a queued frame.
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for Connection<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        ready!(self.poll_write_pending(cx)).map_err(into_io_error)?;
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        ready!(self.poll_write_pending(cx)).map_err(into_io_error)?;
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        ready!(self.poll_write_pending(cx)).map_err(into_io_error)?;
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> From<S> for Connection<S> {
+    fn from(stream: S) -> Self {
+        Connection::new(stream)
+    }
+}
+
+/// Unwraps the underlying stream without flushing it
+///
+/// Unflushed writes and bytes that were received but not yet read are discarded. Use
+/// [`Connection::into_inner`] to flush the connection and keep the unread bytes.
+impl From<Connection> for TcpStream {
+    fn from(conn: Connection) -> Self {
+        conn.stream.into_inner()
+    }
+}
+
+fn into_io_error(e: ConnectionError) -> Error {
+    match e {
+        ConnectionError::IoError(e) => e,
+        // Keep the kind of the underlying error so callers can still match on it
+        ConnectionError::IoErrorContext(e, context) => {
+            Error::new(e.kind(), ConnectionError::IoErrorContext(e, context))
+        }
+        e => Error::other(e),
+    }
+}
@@ -0,0 +1,201 @@
+use crate::frame::{self, Control, LENGTH_PREFIX_SIZE};
+use crate::{with_timeout, Connection, ConnectionError};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{watch, Notify};
+use tokio::task::JoinHandle;
+
+/// The background task that decides when pings are due and whether the peer answered them
+pub(crate) struct Keepalive {
+    shared: Arc<Shared>,
+    task: JoinHandle<()>,
+}
+
+/// The signals exchanged between a connection and its keep-alive task
+pub(crate) struct Shared {
+    ping_due: Notify,
+    /// Number of pings written to the stream
+    pings_sent: AtomicU64,
+    /// Number of pings the peer answered, which pongs nobody asked for do not count towards
+    pongs_received: AtomicU64,
+    /// Wakes the keep-alive task when either count changes
+    progress: Notify,
+}
+
+impl Keepalive {
+    fn start(interval: Duration, timeout: Duration, liveness: Arc<watch::Sender<bool>>) -> Self {
+        let shared = Arc::new(Shared {
+            ping_due: Notify::new(),
+            pings_sent: AtomicU64::new(0),
+            pongs_received: AtomicU64::new(0),
+            progress: Notify::new(),
+        });
+
+        let task = tokio::spawn(run(shared.clone(), interval, timeout, liveness));
+        Keepalive { shared, task }
+    }
+
+    pub(crate) fn shared(&self) -> Arc<Shared> {
+        self.shared.clone()
+    }
+}
+
+impl Drop for Keepalive {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Shared {
+    /// Completes when the connection should send a ping
+    pub(crate) async fn ping_due(&self) {
+        self.ping_due.notified().await
+    }
+
+    /// Tell the keep-alive task that a ping was written to the stream
+    pub(crate) fn ping_sent(&self) {
+        self.pings_sent.fetch_add(1, Ordering::SeqCst);
+        self.progress.notify_one();
+    }
+
+    /// Tell the keep-alive task that the peer answered a ping
+    pub(crate) fn pong_received(&self) {
+        let answered =
+            self.pongs_received
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pongs| {
+                    (pongs < self.pings_sent.load(Ordering::SeqCst)).then_some(pongs + 1)
+                });
+        if answered.is_ok() {
+            self.progress.notify_one();
+        }
+    }
+
+    /// Completes once `count` reaches `target`
+    async fn reached(&self, count: &AtomicU64, target: u64) {
+        loop {
+            // Registered before the check so that an update in between is not missed
+            let progress = self.progress.notified();
+            if count.load(Ordering::SeqCst) >= target {
+                return;
+            }
+            progress.await;
+        }
+    }
+}
+
+async fn run(
+    shared: Arc<Shared>,
+    interval: Duration,
+    timeout: Duration,
+    liveness: Arc<watch::Sender<bool>>,
+) {
+    loop {
+        tokio::time::sleep(interval).await;
+        let ping = shared.pings_sent.load(Ordering::SeqCst) + 1;
+        shared.ping_due.notify_one();
+
+        // A connection that stops reading cannot send the ping, so nothing shows the peer is
+        // still there
+        let sent =
+            tokio::time::timeout(interval + timeout, shared.reached(&shared.pings_sent, ping));
+        if sent.await.is_err() {
+            liveness.send_replace(false);
+            return;
+        }
+
+        let pong = tokio::time::timeout(timeout, shared.reached(&shared.pongs_received, ping));
+        if pong.await.is_err() {
+            liveness.send_replace(false);
+            return;
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    /// Start probing the peer with a ping every `interval`, expecting a pong within `timeout`
+    ///
+    /// If the peer does not answer in time, the liveness channel returned by
+    /// [`Connection::liveness_rx`] flips to `false`. Pings are sent while the connection is waiting
+    /// in [`Connection::read`], the state an idle connection spends its time in, and the peer must
+    /// be reading as well to answer them. A connection that does not read within `interval +
+    /// timeout` of a ping falling due cannot send it, and flips to `false` as well. Every connection answers pings automatically, and so do
+    /// the halves returned by [`Connection::split`]. Starting keep-alive again replaces the
+    /// previous settings. This must be called from within a Tokio runtime.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///     conn.start_keepalive(Duration::from_secs(30), Duration::from_secs(5));
+    ///
+    ///     // Find out when the peer stops answering
+    ///     let mut liveness = conn.liveness_rx();
+    ///     tokio::spawn(async move {
+    ///         while liveness.changed().await.is_ok() {
+    ///             if !*liveness.borrow() {
+    ///                 println!("peer is gone");
+    ///             }
+    ///         }
+    ///     });
+    ///
+    ///     while let Some(message) = conn.read::<String>().await? {
+    ///         println!("{}", message);
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn start_keepalive(&mut self, interval: Duration, timeout: Duration) {
+        self.keepalive = Some(Keepalive::start(interval, timeout, self.liveness.clone()));
+    }
+
+    /// Stop probing the peer
+    pub fn stop_keepalive(&mut self) {
+        self.keepalive = None;
+    }
+
+    /// Returns a channel that holds `false` once the peer fails to answer a keep-alive ping
+    pub fn liveness_rx(&self) -> watch::Receiver<bool> {
+        self.liveness.subscribe()
+    }
+
+    /// Send a single ping and wait up to `timeout` for the peer to answer it
+    ///
+    /// Fails if the peer sends a message before the pong, since it would be left unread.
+    pub(crate) async fn probe(&mut self, timeout: Duration) -> Result<(), ConnectionError> {
+        self.write_control(Control::Ping).await?;
+        self.skipped.restore(&mut self.buffer);
+        with_timeout(Some(timeout), async {
+            loop {
+                match frame::take_control(&mut self.buffer) {
+                    Some(Control::Pong) => return Ok(()),
+                    Some(Control::Ping) => self.write_control(Control::Pong).await?,
+                    Some(Control::Ack(_)) => {}
+                    None if self.buffer.len() >= LENGTH_PREFIX_SIZE
+                        && !frame::at_control(&self.buffer) =>
+                    {
+                        return Err(ConnectionError::IoError(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            "received a message while waiting for a pong",
+                        )));
+                    }
+                    None => {
+                        if 0 == self.read_to_buffer().await? {
+                            return Err(ConnectionError::Disconnected);
+                        }
+                    }
+                }
+            }
+        })
+        .await
+    }
+}
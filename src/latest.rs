@@ -0,0 +1,105 @@
+use crate::{frame, Connection, ConnectionError};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    /// Write a serializable value into the stream, allowing the peer to skip it in favor of a
+    /// newer value
+    ///
+    /// This behaves like [`Connection::write`], except that the frame is marked as overwritable,
+    /// so a [`Connection::read_latest`] on the other side may discard it unread if a later frame
+    /// has already arrived. A [`Connection::read`] receives it like any other value.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // Stream sensor readings, of which only the newest matters to the peer
+    ///     for reading in [20.5f32, 20.7, 21.0] {
+    ///         conn.write_overwriting(&reading).await?;
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn write_overwriting<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), ConnectionError> {
+        let buf = self.record(self.format.serialize(value))?;
+        if let Err(e) = self.write_frame(&buf, frame::OVERWRITABLE).await {
+            debug!(type_name = std::any::type_name::<T>(), error = %e, "write failed");
+            return Err(e);
+        }
+
+        trace!(
+            type_name = std::any::type_name::<T>(),
+            bytes_written = buf.len(),
+            "wrote overwritable value"
+        );
+        Ok(())
+    }
+
+    /// Reads the most recent value the peer has sent, discarding overwritable values that have
+    /// already been superseded
+    ///
+    /// Everything the socket already has is taken in first. Values written with
+    /// [`Connection::write_overwriting`] are then skipped as long as another complete frame
+    /// follows them, and the first value that remains is returned. Values written any other way
+    /// are never skipped, so they are returned in order like with [`Connection::read`]. If
+    /// nothing has arrived yet, this waits for the next value. Skipped values are not
+    /// deserialized, but count as received in [`Connection::stats`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // Redraw with the newest reading, however many arrived in the meantime
+    ///     while let Some(reading) = conn.read_latest::<f32>().await? {
+    ///         println!("temperature: {}", reading);
+    ///         tokio::time::sleep(Duration::from_millis(100)).await;
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn read_latest<T: DeserializeOwned>(&mut self) -> Result<Option<T>, ConnectionError> {
+        loop {
+            while self.read_ready() {}
+
+            self.answer_controls().await?;
+            loop {
+                let skipped = frame::skip_overwritten(&mut self.buffer, self.max_message_size);
+                if !self.record(skipped)? {
+                    break;
+                }
+                self.stats.record_received_message();
+                self.answer_controls().await?;
+            }
+
+            if let Some(value) = self.parse_buffered().await? {
+                return Ok(Some(value));
+            }
+
+            if 0 == self.read_to_buffer().await? {
+                return Ok(None);
+            }
+        }
+    }
+}
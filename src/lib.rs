@@ -28,39 +28,451 @@
 //! async fn server_side(mut server_conn: Connection) {
 //!   let message: Message = server_conn.read::<Message>().await.unwrap().unwrap();
 //! }
-use bytes::BytesMut;
+//! ```
+//!
+//! # Wire format
+//!
+//! Every value is sent as a single frame: an 8-byte [`FrameHeader`] followed by the serialized
+//! payload. The header holds a 4-byte big-endian `u32` with the length of the payload as sent, a
+//! flags byte, a compression byte, and two reserved bytes which are always zero. The payload is encoded with the
+//! connection's [`SerdeFormat`], which defaults to bincode.
+//!
+//! The flags byte is a bit field. Bit 1 marks a payload followed by a 32-byte HMAC-SHA256 tag,
+//! see [`Connection::set_hmac_key`], bit 2 a chunk followed by further chunks of the same
+//! stream, see [`Connection::write_stream`], and bit 3 a value which a newer one may replace, see
+//! [`Connection::write_overwriting`]. Bits 4 and 5 hold the [`Priority`] of the value, and bits
+//! 0, 6 and 7 its schema version, see [`Connection::write_versioned`]. The compression byte is the id of the
+//! [`CompressionAlgorithm`] the payload is compressed with, `0` for none.
+//!
+//! The three largest length prefixes are reserved for control frames, which consist of the
+//! 4-byte prefix alone and have no flags or reserved bytes. `0xFFFFFFFF` and `0xFFFFFFFE` are the ping and pong frames used by
+//! [`Connection::start_keepalive`]. `0xFFFFFFFD` is the acknowledgment sent by
+//! [`Connection::read_with_ack`], followed by an 8-byte big-endian FNV-1a hash of the payload.
+//!
+//! The complete wire format, including test vectors, is published in `WIRE_FORMAT.md` at the
+//! root of the repository.
+use bytes::{Bytes, BytesMut};
+use futures::FutureExt;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
-use std::io::{Cursor, Error};
+use std::any::Any;
+use std::fmt;
+use std::future::Future;
+use std::io::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter};
 use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+#[macro_use]
+mod trace;
+
+mod ack;
+mod actor;
+mod auth;
+mod batch;
+mod bridge;
+mod broadcast;
+mod builder;
+mod chunked;
+pub mod codec;
+mod compression;
+mod format;
+mod frame;
+mod guard;
+#[cfg(feature = "handshake")]
+mod handshake;
+mod idle;
+mod io;
+mod keepalive;
+mod latest;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod middleware;
+#[cfg(feature = "test-helpers")]
+pub mod mock;
+mod mux;
+mod negotiate;
+mod ordered;
+mod pipeline;
+mod pool;
+mod priority;
+mod protocol;
+#[cfg(feature = "rate-limit")]
+mod rate_limit;
+mod reconnect;
+mod relay;
+mod request;
+mod retry;
+mod schema;
+mod server;
+mod shared;
+mod sink;
+mod socks;
+mod split;
+mod stats;
+mod stream;
+#[cfg(feature = "tls")]
+mod tls;
+mod typed;
+#[cfg(unix)]
+mod unix;
+
+pub use actor::{ConnectionActor, IncomingFrame, OutgoingFrame};
+use auth::HmacKey;
+pub use batch::WriteMany;
+pub use bridge::ChannelBridge;
+pub use broadcast::broadcast;
+pub use builder::ConnectionBuilder;
+pub use codec::LengthDelimitedConnectionCodec;
+pub use compression::{CompressionAlgorithm, CompressionMode};
+pub use erased_serde;
+pub use format::SerdeFormat;
+use frame::Control;
+pub use frame::FrameHeader;
+pub use guard::ConnectionGuard;
+#[cfg(feature = "handshake")]
+pub use handshake::PROTOCOL_VERSION;
+use idle::IdleTimer;
+use keepalive::Keepalive;
+pub use middleware::Middleware;
+pub use mux::{ChannelReceiver, ChannelWriter, Multiplexer};
+pub use negotiate::{Capabilities, NegotiatedCapabilities, ProtocolNegotiator};
+pub use ordered::OrderedConnection;
+pub use pipeline::Pipeline;
+pub use pool::{ConnectionPool, PoolStats, PooledConnection};
+pub use priority::Priority;
+use priority::Skipped;
+pub use protocol::{Protocol, ProtocolConnection};
+#[cfg(feature = "rate-limit")]
+pub use rate_limit::RateLimitedConnection;
+pub use reconnect::{BackoffStrategy, ReconnectingConnection};
+pub use relay::relay;
+pub use request::{AwaitingResponse, ReadyToSend, RequestResponse};
+pub use retry::RetryPolicy;
+pub use server::Server;
+pub use shared::SharedConnection;
+pub use socks::Socks5Auth;
+pub use split::{ConnectionReader, ConnectionWriter};
+pub use stats::ConnectionStats;
+use stats::StatsRecorder;
+pub use stream::ConnectionStream;
+#[cfg(feature = "tls")]
+pub use tls::{rustls, ClientTlsStream, ServerTlsStream, TlsConnection, TlsServerConnection};
+pub use typed::TypedConnection;
+#[cfg(unix)]
+pub use unix::UnixConnection;
 
 static DEFAULT_BUFFER_SIZE: usize = 4 * 1024;
 
+static DEFAULT_MAX_IN_FLIGHT_BYTES: usize = 64 * 1024;
+
+/// The largest payload a connection accepts from its peer unless configured otherwise (64 MiB)
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
 /// The failure modes of a connection
 #[derive(Error, Debug)]
 pub enum ConnectionError {
     /// An error encountered during IO
     #[error("`{0}`")]
-    IoError(Error),
+    IoError(#[source] Error),
+    /// An error encountered during IO, along with what the connection was doing at the time, such
+    /// as the addresses it was connecting
+    #[error("{1}: `{0}`")]
+    IoErrorContext(#[source] Error, String),
     /// An error encountered during (de)serialization
     #[error("`{0}`")]
-    BincodeError(Box<bincode::Error>),
-    /// An error encountered when the network connection is dropped
+    BincodeError(#[source] Box<bincode::Error>),
+    /// An error encountered during JSON (de)serialization
+    #[error("`{0}`")]
+    JsonError(#[source] serde_json::Error),
+    /// An error encountered during MessagePack (de)serialization
+    #[error("`{0}`")]
+    MsgPackError(#[source] Box<dyn std::error::Error + Send + Sync>),
+    /// An error encountered during CBOR (de)serialization
+    #[error("`{0}`")]
+    CborError(#[source] Box<dyn std::error::Error + Send + Sync>),
+    /// An error encountered during postcard (de)serialization
+    #[cfg(feature = "postcard")]
+    #[error("`{0}`")]
+    PostcardError(#[source] postcard::Error),
+    /// An error encountered when the peer closes the connection cleanly, between two frames,
+    /// while a message was still expected
+    #[error("connection closed by peer")]
+    Disconnected,
+    /// An error encountered when the network connection is dropped in the middle of an exchange,
+    /// such as between the chunks of a stream
     #[error("`{0}`")]
     ConnectionReset(String),
+    /// An error encountered when the peer closes the connection after sending only part of a
+    /// frame
+    #[error("connection closed in the middle of a frame")]
+    UnexpectedEof,
+    /// An error encountered when a connection is configured with invalid options
+    #[error("`{0}`")]
+    InvalidConfiguration(String),
+    /// An error encountered during a TLS handshake
+    #[cfg(feature = "tls")]
+    #[error("`{0}`")]
+    TlsError(#[source] rustls::Error),
+    /// An error encountered when a read, or a wait such as for a reply or a lock, does not
+    /// complete before its timeout
+    #[error("operation timed out")]
+    Timeout,
+    /// An error encountered when a write does not complete before its timeout, which may leave
+    /// part of a frame on the stream
+    #[error("write timed out")]
+    WriteTimeout,
+    /// An error encountered when the peer speaks an incompatible version of the wire format
+    #[cfg(feature = "handshake")]
+    #[error("local protocol version {local} is incompatible with remote version {remote}")]
+    VersionMismatch {
+        /// The protocol version spoken by this side of the connection
+        local: u16,
+        /// The protocol version announced by the peer, or `0` if it sent no handshake
+        remote: u16,
+    },
+    /// An error encountered when the connection was closed after going without activity for
+    /// longer than its idle timeout
+    #[error("connection closed after being idle")]
+    IdleTimeout,
+    /// An error encountered when every address given to [`Connection::dial_first_of`] refused the
+    /// connection, holding the error of each address in the order they were tried
+    #[error("failed to connect to any of {} addresses", .0.len())]
+    AllAddressesFailed(Vec<Error>),
+    /// An error encountered when no pooled connection becomes available before the acquire timeout
+    #[error("timed out waiting for a pooled connection")]
+    PoolExhausted,
+    /// An error encountered when every attempt allowed by a [`RetryPolicy`] failed, holding the
+    /// error of the last attempt
+    #[error("giving up after the last retry failed: `{0}`")]
+    MaxRetriesExceeded(#[source] Box<ConnectionError>),
+    /// An error encountered when two peers have no serialization format or compression mode in
+    /// common, see [`ProtocolNegotiator`]
+    #[error("`{0}`")]
+    NegotiationFailed(String),
+    /// An error encountered when two peers have no compression algorithm in common, see
+    /// [`Connection::negotiate_compression`]
+    #[error("no compression algorithm in common with the peer")]
+    NoCommonCompression,
+    /// An error encountered when a received frame fails HMAC verification, is missing its tag
+    /// while a key is set, or carries one while no key is set, see [`Connection::set_hmac_key`]
+    #[error("`{0}`")]
+    AuthenticationFailed(String),
+    /// An error encountered when a SOCKS5 proxy does not speak the protocol or fails to connect
+    /// to the target, see [`Connection::dial_via_socks5`]
+    #[error("`{0}`")]
+    ProxyError(String),
+    /// An error encountered when a SOCKS5 proxy rejects the credentials or requires ones that were
+    /// not given, see [`Connection::dial_via_socks5_with_auth`]
+    #[error("`{0}`")]
+    ProxyAuthenticationFailed(String),
+    /// An error encountered when an [`OrderedConnection`] receives a value whose sequence number
+    /// is not the one it expected
+    #[error("expected sequence number {expected} but received {got}")]
+    OutOfOrder {
+        /// The sequence number the connection expected
+        expected: u64,
+        /// The sequence number the value carried
+        got: u64,
+    },
+    /// An error along with what the caller was doing at the time, added by
+    /// [`ConnectionError::with_context`]
+    #[error("{1}: `{0}`")]
+    Context(#[source] Box<ConnectionError>, String),
+    /// An error encountered when the peer announces a message larger than the configured limit
+    #[error("message of {claimed} bytes exceeds the limit of {limit} bytes")]
+    MessageTooLarge {
+        /// The payload size claimed by the frame header
+        claimed: usize,
+        /// The maximum payload size accepted by the connection
+        limit: usize,
+    },
+}
+
+impl ConnectionError {
+    /// Returns `true` if the connection is still usable after this error, so the operation may be
+    /// retried
+    ///
+    /// The errors are classified as follows:
+    ///
+    /// - [`IoError`](ConnectionError::IoError) and
+    ///   [`IoErrorContext`](ConnectionError::IoErrorContext) are recoverable if their kind is
+    ///   [`WouldBlock`](std::io::ErrorKind::WouldBlock),
+    ///   [`Interrupted`](std::io::ErrorKind::Interrupted) or
+    ///   [`TimedOut`](std::io::ErrorKind::TimedOut), and fatal otherwise.
+    /// - Serialization errors are recoverable, since a value that fails to serialize is never
+    ///   written and a frame that fails to deserialize is discarded whole.
+    /// - [`Timeout`](ConnectionError::Timeout) is recoverable, since bytes received before a read
+    ///   times out stay buffered for the next read.
+    /// - [`WriteTimeout`](ConnectionError::WriteTimeout) is fatal, since the peer may have
+    ///   received part of a frame and can no longer tell where the next one starts.
+    /// - [`PoolExhausted`](ConnectionError::PoolExhausted) is recoverable once a pooled connection
+    ///   is returned.
+    /// - [`Disconnected`](ConnectionError::Disconnected),
+    ///   [`ConnectionReset`](ConnectionError::ConnectionReset),
+    ///   [`UnexpectedEof`](ConnectionError::UnexpectedEof),
+    ///   [`InvalidConfiguration`](ConnectionError::InvalidConfiguration),
+    ///   [`AllAddressesFailed`](ConnectionError::AllAddressesFailed),
+    ///   [`IdleTimeout`](ConnectionError::IdleTimeout),
+    ///   [`MaxRetriesExceeded`](ConnectionError::MaxRetriesExceeded),
+    ///   [`NegotiationFailed`](ConnectionError::NegotiationFailed),
+    ///   [`NoCommonCompression`](ConnectionError::NoCommonCompression),
+    ///   [`AuthenticationFailed`](ConnectionError::AuthenticationFailed),
+    ///   [`ProxyError`](ConnectionError::ProxyError),
+    ///   [`ProxyAuthenticationFailed`](ConnectionError::ProxyAuthenticationFailed),
+    ///   [`OutOfOrder`](ConnectionError::OutOfOrder),
+    ///   [`MessageTooLarge`](ConnectionError::MessageTooLarge) and the TLS and handshake errors
+    ///   are fatal.
+    /// - [`Context`](ConnectionError::Context) is classified like the error it wraps.
+    pub fn is_recoverable(&self) -> bool {
+        match self {
+            ConnectionError::Context(e, _) => e.is_recoverable(),
+            ConnectionError::IoError(e) | ConnectionError::IoErrorContext(e, _) => matches!(
+                e.kind(),
+                std::io::ErrorKind::WouldBlock
+                    | std::io::ErrorKind::Interrupted
+                    | std::io::ErrorKind::TimedOut
+            ),
+            ConnectionError::BincodeError(_)
+            | ConnectionError::JsonError(_)
+            | ConnectionError::MsgPackError(_)
+            | ConnectionError::CborError(_)
+            | ConnectionError::Timeout
+            | ConnectionError::PoolExhausted => true,
+            #[cfg(feature = "postcard")]
+            ConnectionError::PostcardError(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Wrap this error with a description of what was being done when it occurred
+    ///
+    /// The context is shown before the original error when the error is displayed, and the
+    /// original error is still available as its [`source`](std::error::Error::source). Contexts
+    /// can be stacked, with the outermost shown first.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection, ConnectionError};
+    /// use serde::Deserialize;
+    /// use std::error::Error;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Login { user: String }
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // Name the message that could not be read
+    ///     let login = conn
+    ///         .read::<Login>()
+    ///         .await
+    ///         .map_err(|e| e.with_context("failed to read the login message"))?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn with_context(self, context: impl fmt::Display) -> ConnectionError {
+        ConnectionError::Context(Box::new(self), context.to_string())
+    }
+
+    /// Returns `true` if the connection should be closed after this error
+    ///
+    /// This is the opposite of [`ConnectionError::is_recoverable`].
+    pub fn is_fatal(&self) -> bool {
+        !self.is_recoverable()
+    }
 }
 
-/// A TCP connection that can be used to send and receive serializable values
-pub struct Connection {
+/// A connection that can be used to send and receive serializable values
+///
+/// Connections are backed by a [`TcpStream`] by default, but can wrap any stream that implements
+/// [`AsyncRead`] and [`AsyncWrite`], such as a TLS stream or an in-memory duplex.
+pub struct Connection<S = TcpStream> {
     buffer: BytesMut,
-    stream: BufWriter<TcpStream>,
+    stream: BufWriter<S>,
+    format: SerdeFormat,
+    compression: CompressionMode,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    /// When the frames buffered by write coalescing must be flushed
+    flush_deadline: Option<Instant>,
+    pending: BytesMut,
+    /// Frames skipped by [`Connection::read_prioritized`], which were received before the buffer
+    skipped: Skipped,
+    max_in_flight_bytes: usize,
+    max_message_size: usize,
+    write_buffer_size: usize,
+    hmac_key: Option<HmacKey>,
+    middleware: Vec<Arc<dyn Middleware>>,
+    stats: StatsRecorder,
+    keepalive: Option<Keepalive>,
+    idle: Option<IdleTimer>,
+    liveness: Arc<watch::Sender<bool>>,
+    name: Option<String>,
+    #[cfg(feature = "error-hook")]
+    error_hook: Option<ErrorHook>,
+}
+
+/// A callback invoked with every error a connection returns
+#[cfg(feature = "error-hook")]
+type ErrorHook = Box<dyn Fn(&ConnectionError) + Send + Sync>;
+
+/// Shows the configuration and buffer state of the connection without exposing the stream
+///
+/// The peer address is included for connections backed by a [`TcpStream`].
+impl<S: AsyncRead + AsyncWrite + Unpin + 'static> fmt::Debug for Connection<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Connection")
+            .field("name", &self.name)
+            .field("peer_addr", &self.debug_peer_addr())
+            .field("format", &self.format)
+            .field("compression", &self.compression)
+            .field("buffer_len", &self.buffer.len())
+            .field("buffer_capacity", &self.buffer.capacity())
+            .field("pending_len", &self.pending.len())
+            .finish()
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + 'static> Connection<S> {
+    /// Returns the address of the peer if the stream is a TCP stream
+    fn debug_peer_addr(&self) -> Option<SocketAddr> {
+        let stream: &dyn Any = self.stream.get_ref();
+        if let Some(stream) = stream.downcast_ref::<TcpStream>() {
+            return stream.peer_addr().ok();
+        }
+        #[cfg(feature = "tls")]
+        if let Some(stream) = stream.downcast_ref::<ClientTlsStream>() {
+            return stream.get_ref().0.peer_addr().ok();
+        }
+        #[cfg(feature = "tls")]
+        if let Some(stream) = stream.downcast_ref::<ServerTlsStream>() {
+            return stream.get_ref().0.peer_addr().ok();
+        }
+        None
+    }
 }
 
+/// A connection backed by a [`TcpStream`]
+pub type TcpConnection = Connection<TcpStream>;
+
 impl Connection {
     /// Connect to a socket address and return a new connection with the default buffer capacity
     ///
+    /// If `addr` resolves to several addresses, such as a host name with both `A` and `AAAA`
+    /// records, or a slice of [`SocketAddr`]s, each one is tried in order and the first connection
+    /// that succeeds is returned. If none does, the error of the last address is returned, naming
+    /// that address. [`Connection::dial_first_of`] reports the errors of every address instead.
+    ///
     /// # Examples
     ///
     /// ```no_run
@@ -79,10 +491,61 @@ impl Connection {
     /// }
     /// ```
     pub async fn dial<A: ToSocketAddrs>(addr: A) -> Result<Connection, ConnectionError> {
-        let stream = TcpStream::connect(addr).await?;
+        let stream = connect(addr).await?;
+        debug!(peer_addr = ?stream.peer_addr().ok(), "connected");
         Ok(Connection::new(stream))
     }
 
+    /// Connect to each of the given addresses in turn and return a new connection to the first one
+    /// that accepts it
+    ///
+    /// Every address is only tried once the previous one has failed. If all of them fail, this
+    /// returns [`ConnectionError::AllAddressesFailed`] with the error of each address, in the
+    /// order they were tried. Fails with [`ConnectionError::InvalidConfiguration`] if `addrs` is
+    /// empty.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    /// use std::net::SocketAddr;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to the primary peer, falling back to the replica
+    ///     let addrs: [SocketAddr; 2] = ["10.0.0.1:8080".parse()?, "10.0.0.2:8080".parse()?];
+    ///     let mut conn = Connection::dial_first_of(&addrs).await?;
+    ///
+    ///     // Send a message
+    ///     conn.write(&"Hello, world!").await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn dial_first_of(addrs: &[SocketAddr]) -> Result<Connection, ConnectionError> {
+        if addrs.is_empty() {
+            return Err(ConnectionError::InvalidConfiguration(
+                "no addresses to connect to".into(),
+            ));
+        }
+
+        let mut errors = Vec::with_capacity(addrs.len());
+        for remote in addrs {
+            match TcpStream::connect(remote).await {
+                Ok(stream) => {
+                    debug!(peer_addr = %remote, "connected");
+                    return Ok(Connection::new(stream));
+                }
+                Err(e) => {
+                    debug!(peer_addr = %remote, error = %e, "failed to connect");
+                    errors.push(e);
+                }
+            }
+        }
+        Err(ConnectionError::AllAddressesFailed(errors))
+    }
+
     /// Connect to a socket address and return a new connection with a custom buffer capacity
     ///
     /// # Examples
@@ -107,24 +570,22 @@ impl Connection {
         addr: A,
         capacity: usize,
     ) -> Result<Connection, ConnectionError> {
-        let stream = TcpStream::connect(addr).await?;
+        let stream = connect(addr).await?;
         Ok(Connection::new_with_capacity(stream, capacity))
     }
 
-    /// Create a new connection with the default buffer capacity
+    /// Connect to a socket address and return a new connection that uses the given serialization format
     ///
     /// # Examples
     ///
     /// ```no_run
-    /// use connection::{Connection};
-    /// use tokio::net::TcpStream;
+    /// use connection::{Connection, SerdeFormat};
     /// use std::error::Error;
     ///
     /// #[tokio::main]
     /// async fn main() -> Result<(), Box<dyn Error>> {
-    ///     // Connect to a peer
-    ///     let stream = TcpStream::connect("127.0.0.1:8080").await?;
-    ///     let mut conn = Connection::new(stream);
+    ///     // Connect to a peer that speaks JSON
+    ///     let mut conn = Connection::dial_with_format("127.0.0.1:8080", SerdeFormat::Json).await?;
     ///
     ///     // Send a message
     ///     conn.write(&"Hello, world!").await?;
@@ -132,25 +593,30 @@ impl Connection {
     ///     Ok(())
     /// }
     /// ```
-    pub fn new(stream: TcpStream) -> Self {
-        Self::new_with_capacity(stream, DEFAULT_BUFFER_SIZE)
+    pub async fn dial_with_format<A: ToSocketAddrs>(
+        addr: A,
+        format: SerdeFormat,
+    ) -> Result<Connection, ConnectionError> {
+        let stream = connect(addr).await?;
+        Ok(Connection::new_with_format(stream, format))
     }
 
-    /// Create a new connection with a custom buffer capacity
+    /// Connect to a socket address from the given local address and return a new connection
+    ///
+    /// On a host with several network interfaces, this picks the interface the connection uses.
+    /// The socket is bound with `SO_REUSEADDR` set. Only remote addresses of the same family as
+    /// the local address are tried.
     ///
     /// # Examples
     ///
     /// ```no_run
     /// use connection::{Connection};
-    /// use tokio::net::TcpStream;
     /// use std::error::Error;
     ///
     /// #[tokio::main]
     /// async fn main() -> Result<(), Box<dyn Error>> {
-    ///     // Connect to a peer
-    ///     let stream = TcpStream::connect("127.0.0.1:8080").await?;
-    ///     let buffer_size = 4096;
-    ///     let mut conn = Connection::new_with_capacity(stream, buffer_size);
+    ///     // Connect to a peer through the interface with address 10.0.0.2, on any free port
+    ///     let mut conn = Connection::dial_from("10.0.0.2:0", "10.0.0.1:8080").await?;
     ///
     ///     // Send a message
     ///     conn.write(&"Hello, world!").await?;
@@ -158,118 +624,1404 @@ impl Connection {
     ///     Ok(())
     /// }
     /// ```
-    pub fn new_with_capacity(stream: TcpStream, capacity: usize) -> Self {
-        Self {
-            buffer: BytesMut::with_capacity(capacity),
-            stream: BufWriter::new(stream),
+    pub async fn dial_from<A: ToSocketAddrs, B: ToSocketAddrs>(
+        local: A,
+        remote: B,
+    ) -> Result<Connection, ConnectionError> {
+        let local = tokio::net::lookup_host(local)
+            .await?
+            .next()
+            .ok_or_else(|| {
+                Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "could not resolve the local address",
+                )
+            })?;
+
+        let mut last_error = None;
+        let remotes = tokio::net::lookup_host(remote).await?;
+        for remote in remotes.filter(|remote| remote.is_ipv4() == local.is_ipv4()) {
+            match connect_from(local, remote).await {
+                Ok(stream) => {
+                    debug!(local_addr = ?stream.local_addr().ok(), peer_addr = %remote, "connected");
+                    return Ok(Connection::new(stream));
+                }
+                Err(e) => last_error = Some(e),
+            }
         }
+
+        Err(last_error.unwrap_or_else(|| {
+            Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "no remote address matches the family of the local address",
+            )
+            .into()
+        }))
     }
 
-    /// Write a serializable value into the stream
+    /// Create a new connection from a blocking [`std::net::TcpStream`], such as one returned by
+    /// [`std::net::TcpListener::accept`]
+    ///
+    /// The stream is switched to non-blocking mode before it is handed to Tokio, which is the
+    /// step callers of [`TcpStream::from_std`] usually forget. This must be called from within a
+    /// Tokio runtime.
     ///
     /// # Examples
     ///
     /// ```no_run
-    /// use serde::{Serialize, Deserialize};
     /// use connection::{Connection};
     /// use std::error::Error;
     ///
-    /// #[derive(Serialize, Deserialize)]
-    /// struct Message { id: usize, text: String }
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Accept a connection without the runtime
+    ///     let listener = std::net::TcpListener::bind("127.0.0.1:8080")?;
+    ///     let (stream, _) = listener.accept()?;
+    ///
+    ///     // Hand it over to the runtime
+    ///     let mut conn = Connection::from_std(stream)?;
+    ///     conn.write(&"Hello, world!").await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn from_std(stream: std::net::TcpStream) -> Result<Connection, ConnectionError> {
+        stream.set_nonblocking(true).map_err(|e| {
+            let context = "failed to switch the blocking stream to non-blocking mode".into();
+            ConnectionError::IoErrorContext(e, context)
+        })?;
+        let stream = TcpStream::from_std(stream).map_err(|e| {
+            let context = "failed to register the stream with the Tokio runtime".into();
+            ConnectionError::IoErrorContext(e, context)
+        })?;
+        Ok(Connection::new(stream))
+    }
+
+    /// Returns a builder used to configure a new connection
+    pub fn builder() -> ConnectionBuilder {
+        ConnectionBuilder::new()
+    }
+
+    /// Returns the socket address of the remote peer of this connection
+    pub fn peer_addr(&self) -> std::io::Result<SocketAddr> {
+        self.stream.get_ref().peer_addr()
+    }
+
+    /// Returns the socket address of the local half of this connection
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.stream.get_ref().local_addr()
+    }
+
+    /// Set the value of the `TCP_NODELAY` option, which disables Nagle's algorithm when `true`
+    pub fn set_nodelay(&self, nodelay: bool) -> Result<(), ConnectionError> {
+        Ok(self.stream.get_ref().set_nodelay(nodelay)?)
+    }
+
+    /// Returns the value of the `TCP_NODELAY` option on the socket
+    pub fn nodelay(&self) -> Result<bool, ConnectionError> {
+        Ok(self.stream.get_ref().nodelay()?)
+    }
+
+    /// Set the time-to-live of the packets the socket sends, which is the `IP_TTL` option on IPv4
+    /// sockets and the `IPV6_UNICAST_HOPS` option on IPv6 sockets
+    ///
+    /// Every router a packet passes decrements it, and drops the packet once it reaches zero.
+    pub fn set_ttl(&self, ttl: u32) -> Result<(), ConnectionError> {
+        Ok(set_ip_ttl(self.stream.get_ref(), ttl)?)
+    }
+
+    /// Returns the time-to-live of the packets the socket sends
+    pub fn ttl(&self) -> Result<u32, ConnectionError> {
+        let stream = self.stream.get_ref();
+        if stream.local_addr()?.is_ipv4() {
+            Ok(stream.ttl()?)
+        } else {
+            Ok(socket2::SockRef::from(stream).unicast_hops_v6()?)
+        }
+    }
+
+    /// Set the value of the `SO_LINGER` option, which controls what happens to unsent data when
+    /// the socket is closed
+    ///
+    /// With `None`, closing the socket returns immediately and the OS sends any unsent data in the
+    /// background before a graceful FIN handshake. The side that closes first then keeps the
+    /// address in `TIME_WAIT` for a while, which can exhaust ephemeral ports on a server that
+    /// closes connections at a high rate.
+    ///
+    /// With `Some(Duration::ZERO)`, closing the socket discards any unsent data and sends a RST
+    /// instead, so no `TIME_WAIT` is left behind. The peer sees the connection reset rather than
+    /// closed, and may lose messages that were still in flight, so only use this when the
+    /// protocol already knows that nothing more needs to be delivered.
+    ///
+    /// With any other duration, closing the socket blocks until the unsent data is delivered or
+    /// the duration elapses.
+    pub fn set_linger(&self, linger: Option<Duration>) -> Result<(), ConnectionError> {
+        Ok(socket2::SockRef::from(self.stream.get_ref()).set_linger(linger)?)
+    }
+
+    /// Returns the value of the `SO_LINGER` option on the socket
+    pub fn linger(&self) -> Result<Option<Duration>, ConnectionError> {
+        Ok(socket2::SockRef::from(self.stream.get_ref()).linger()?)
+    }
+
+    /// Close the connection immediately, discarding anything that has not been sent yet
+    ///
+    /// Unlike [`Connection::close`], this neither flushes buffered values nor waits for the peer.
+    /// The socket is closed with a zero [linger](Connection::set_linger) timeout, so the peer sees
+    /// the connection reset rather than closed. This suits peers that must not be served any
+    /// further, for example after failing to authenticate.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use std::error::Error;
     ///
     /// #[tokio::main]
     /// async fn main() -> Result<(), Box<dyn Error>> {
     ///     // Connect to a peer
     ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
     ///
-    ///     // Create a message
-    ///     let message = Message { id: 1, text: "Hello, world!".to_string() };
+    ///     // Hang up on a peer that sends the wrong password
+    ///     if conn.read::<String>().await?.as_deref() != Some("open sesame") {
+    ///         conn.abort();
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn abort(self) {
+        // Failing to set the linger only means the socket is closed gracefully instead
+        let _ = self.set_linger(Some(Duration::ZERO));
+    }
+
+    /// Enable `SO_KEEPALIVE` with probes starting after the socket has been idle for `time`, or
+    /// disable it with `None`
+    ///
+    /// This is enforced by the operating system and independent of
+    /// [`Connection::start_keepalive`].
+    pub fn set_keepalive(&self, time: Option<Duration>) -> Result<(), ConnectionError> {
+        Ok(set_tcp_keepalive(self.stream.get_ref(), time)?)
+    }
+
+    /// Set the size of the operating system's send buffer for the socket (`SO_SNDBUF`)
+    ///
+    /// The operating system may round or double the requested size, see
+    /// [`Connection::send_buffer_size`] for the size actually in use.
+    pub fn set_send_buffer_size(&self, size: usize) -> Result<(), ConnectionError> {
+        Ok(socket2::SockRef::from(self.stream.get_ref()).set_send_buffer_size(size)?)
+    }
+
+    /// Returns the size of the operating system's send buffer for the socket
+    pub fn send_buffer_size(&self) -> Result<usize, ConnectionError> {
+        Ok(socket2::SockRef::from(self.stream.get_ref()).send_buffer_size()?)
+    }
+
+    /// Set the size of the operating system's receive buffer for the socket (`SO_RCVBUF`)
+    ///
+    /// The operating system may round or double the requested size, see
+    /// [`Connection::recv_buffer_size`] for the size actually in use.
+    pub fn set_recv_buffer_size(&self, size: usize) -> Result<(), ConnectionError> {
+        Ok(socket2::SockRef::from(self.stream.get_ref()).set_recv_buffer_size(size)?)
+    }
+
+    /// Returns the size of the operating system's receive buffer for the socket
+    pub fn recv_buffer_size(&self) -> Result<usize, ConnectionError> {
+        Ok(socket2::SockRef::from(self.stream.get_ref()).recv_buffer_size()?)
+    }
+
+    /// Returns a new connection to the same socket with its own buffer and the same
+    /// serialization format and HMAC key
+    ///
+    /// Both connections share the underlying socket file descriptor. Reading or writing through
+    /// both of them concurrently without external synchronization will interleave their data and
+    /// corrupt the stream of messages.
+    ///
+    /// This must be called from within a Tokio runtime.
+    pub async fn try_clone(&self) -> Result<Connection, ConnectionError> {
+        let socket = socket2::SockRef::from(self.stream.get_ref()).try_clone()?;
+        let stream = TcpStream::from_std(std::net::TcpStream::from(socket))?;
+        let mut conn = ConnectionBuilder::new()
+            .format(self.format)
+            .compression(self.compression)
+            .write_buffer_size(self.write_buffer_size)
+            .name_opt(self.name.clone())
+            .assemble(stream);
+        conn.hmac_key = self.hmac_key;
+        conn.middleware = self.middleware.clone();
+        Ok(conn)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    /// Create a new connection with the default buffer capacity
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use tokio::net::TcpStream;
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let stream = TcpStream::connect("127.0.0.1:8080").await?;
+    ///     let mut conn = Connection::new(stream);
     ///
     ///     // Send a message
-    ///     conn.write::<Message>(&message).await?;
+    ///     conn.write(&"Hello, world!").await?;
     ///
     ///     Ok(())
     /// }
     /// ```
-    pub async fn write<T: Serialize>(&mut self, value: &T) -> Result<(), ConnectionError> {
-        let buf = bincode::serialize(value)?;
-        self.write_to_stream(&buf).await?;
-        Ok(())
+    pub fn new(stream: S) -> Self {
+        ConnectionBuilder::new().assemble(stream)
     }
 
-    /// Reads from the socket until a complete message is received, or an error occurs
+    /// Create a new connection with a custom buffer capacity
     ///
     /// # Examples
     ///
     /// ```no_run
-    /// use serde::{Serialize, Deserialize};
     /// use connection::{Connection};
+    /// use tokio::net::TcpStream;
     /// use std::error::Error;
     ///
-    /// #[derive(Serialize, Deserialize)]
-    /// struct Message { id: usize, text: String }
-    ///
     /// #[tokio::main]
     /// async fn main() -> Result<(), Box<dyn Error>> {
     ///     // Connect to a peer
-    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///     let stream = TcpStream::connect("127.0.0.1:8080").await?;
+    ///     let buffer_size = 4096;
+    ///     let mut conn = Connection::new_with_capacity(stream, buffer_size);
     ///
-    ///     // Read a message
-    ///     let message: Message = conn.read::<Message>().await?.unwrap();
+    ///     // Send a message
+    ///     conn.write(&"Hello, world!").await?;
     ///
     ///     Ok(())
     /// }
     /// ```
-    pub async fn read<T: DeserializeOwned>(&mut self) -> Result<Option<T>, ConnectionError> {
-        loop {
-            if let Some(value) = self.parse_value()? {
-                self.buffer.clear();
-                return Ok(Some(value));
-            }
+    pub fn new_with_capacity(stream: S, capacity: usize) -> Self {
+        ConnectionBuilder::new().capacity(capacity).assemble(stream)
+    }
 
-            self.read_to_buffer().await?;
-        }
+    /// Create a new connection that uses the given serialization format
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection, SerdeFormat};
+    /// use tokio::net::TcpStream;
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer that speaks JSON
+    ///     let stream = TcpStream::connect("127.0.0.1:8080").await?;
+    ///     let mut conn = Connection::new_with_format(stream, SerdeFormat::Json);
+    ///
+    ///     // Send a message
+    ///     conn.write(&"Hello, world!").await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn new_with_format(stream: S, format: SerdeFormat) -> Self {
+        ConnectionBuilder::new().format(format).assemble(stream)
     }
 
-    /// Attempts to deserialize a T from the internal buffer.
-    fn parse_value<T: DeserializeOwned>(&mut self) -> Result<Option<T>, ConnectionError> {
-        let mut buf = Cursor::new(&self.buffer[..]);
-        match bincode::deserialize_from(&mut buf) {
-            Ok(value) => Ok(Some(value)),
-            Err(_) => Ok(None),
-        }
+    /// Set the maximum amount of time a single read from the socket may take
+    ///
+    /// A read that exceeds the timeout fails with [`ConnectionError::Timeout`]. Passing `None`
+    /// removes the timeout.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
     }
 
-    /// Write a byte slice into the stream
-    async fn write_to_stream(&mut self, buf: &[u8]) -> Result<(), ConnectionError> {
-        self.stream.write_all(buf).await?;
-        self.stream.flush().await?;
-        Ok(())
+    /// Set the maximum amount of time a single write to the socket may take
+    ///
+    /// A write that exceeds the timeout fails with [`ConnectionError::WriteTimeout`] rather than
+    /// [`ConnectionError::Timeout`]: unlike a read, a write cut short may leave part of a frame on
+    /// the stream, so the error is fatal and the connection should be closed. Passing `None`
+    /// removes the timeout.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        self.write_timeout = timeout;
     }
 
-    /// Reads more bytes from the socket into the internal buffer
-    async fn read_to_buffer(&mut self) -> Result<(), ConnectionError> {
-        if 0 == self.stream.read_buf(&mut self.buffer).await? {
-            return if self.buffer.is_empty() {
-                Ok(())
-            } else {
-                Err(ConnectionError::ConnectionReset(
-                    "connection reset by peer".into(),
-                ))
-            };
-        }
-        Ok(())
+    /// Set the largest payload this connection accepts from its peer
+    ///
+    /// Reading a frame whose header announces a larger payload fails with
+    /// [`ConnectionError::MessageTooLarge`] before the payload is buffered. The tag that
+    /// [`Connection::set_hmac_key`] adds to every frame does not count toward the limit, so the
+    /// same payloads are accepted with and without a key. The connection should
+    /// be closed after such an error since the rest of the frame is never consumed. Defaults to
+    /// [`DEFAULT_MAX_MESSAGE_SIZE`].
+    pub fn set_max_message_size(&mut self, limit: usize) {
+        self.max_message_size = limit;
     }
-}
 
-impl From<std::io::Error> for ConnectionError {
-    fn from(e: std::io::Error) -> Self {
-        ConnectionError::IoError(e)
+    /// Returns the largest payload this connection accepts from its peer
+    pub fn max_message_size(&self) -> usize {
+        self.max_message_size
     }
-}
 
-impl From<Box<bincode::ErrorKind>> for ConnectionError {
-    fn from(e: Box<bincode::ErrorKind>) -> Self {
-        ConnectionError::BincodeError(Box::new(e))
+    /// Returns the number of bytes the read buffer can hold without reallocating
+    ///
+    /// The buffer starts at the capacity the connection was created with and grows to fit the
+    /// largest frame received.
+    pub fn buffer_capacity(&self) -> usize {
+        self.buffer.capacity()
+    }
+
+    /// Returns the number of received bytes that have not been read as a message yet
+    pub fn buffer_len(&self) -> usize {
+        self.skipped.len() + self.buffer.len()
+    }
+
+    /// Grow the read buffer so it can hold at least `min` bytes without reallocating
+    ///
+    /// Does nothing if the buffer is already large enough.
+    pub fn set_min_buffer_capacity(&mut self, min: usize) {
+        self.buffer.reserve(min.saturating_sub(self.buffer.len()));
+    }
+
+    /// Returns the number of bytes the write buffer holds before it is flushed to the socket
+    pub fn write_buffer_size(&self) -> usize {
+        self.write_buffer_size
+    }
+
+    /// Returns the transfer statistics of this connection
+    pub fn stats(&self) -> &ConnectionStats {
+        self.stats.get()
+    }
+
+    /// Returns the read timeout of this connection
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout
+    }
+
+    /// Returns the write timeout of this connection
+    pub fn write_timeout(&self) -> Option<Duration> {
+        self.write_timeout
+    }
+
+    /// Returns the serialization format used by this connection
+    pub fn format(&self) -> SerdeFormat {
+        self.format
+    }
+
+    /// Set a callback that is invoked with every error this connection returns, just before it is
+    /// returned
+    ///
+    /// The hook only observes errors and cannot change them. If the hook panics, the panic is
+    /// caught and the error is returned as usual. This method is only available with the
+    /// `error-hook` feature enabled.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // Log every error without handling it at each call site
+    ///     conn.set_error_hook(|e| eprintln!("connection error: {e}"));
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    #[cfg(feature = "error-hook")]
+    pub fn set_error_hook(&mut self, hook: impl Fn(&ConnectionError) + Send + Sync + 'static) {
+        self.error_hook = Some(Box::new(hook));
+    }
+
+    /// Returns the name given to this connection with [`ConnectionBuilder::name`]
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Set the compression applied to the payloads this connection writes
+    ///
+    /// Compressed payloads are always decompressed on read, whatever the mode of the reader.
+    pub fn set_compression(&mut self, compression: CompressionMode) {
+        self.compression = compression;
+    }
+
+    /// Returns the compression applied to the payloads this connection writes
+    pub fn compression(&self) -> CompressionMode {
+        self.compression
+    }
+
+    /// Write a serializable value into the stream
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use serde::{Serialize, Deserialize};
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    ///
+    /// #[derive(Serialize, Deserialize)]
+    /// struct Message { id: usize, text: String }
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // Create a message
+    ///     let message = Message { id: 1, text: "Hello, world!".to_string() };
+    ///
+    ///     // Send a message
+    ///     conn.write::<Message>(&message).await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn write<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConnectionError> {
+        let buf = self.record(self.format.serialize(value))?;
+        if let Err(e) = self.write_frame(&buf, 0).await {
+            debug!(type_name = std::any::type_name::<T>(), error = %e, "write failed");
+            return Err(e);
+        }
+
+        trace!(
+            type_name = std::any::type_name::<T>(),
+            bytes_written = buf.len(),
+            "wrote value"
+        );
+        Ok(())
+    }
+
+    /// Write a type-erased serializable value into the stream
+    ///
+    /// The value is serialized with the connection's format, which makes it possible to write
+    /// values from a heterogeneous collection such as `Vec<Box<dyn erased_serde::Serialize>>`.
+    /// The peer reads each value as its concrete type with [`Connection::read`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{erased_serde, Connection};
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // Queue messages of different types
+    ///     let outgoing: Vec<Box<dyn erased_serde::Serialize>> =
+    ///         vec![Box::new("Hello, world!"), Box::new(42u32), Box::new(vec![1, 2, 3])];
+    ///
+    ///     // Send them in order
+    ///     for message in &outgoing {
+    ///         conn.write_erased(message.as_ref()).await?;
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn write_erased(
+        &mut self,
+        value: &dyn erased_serde::Serialize,
+    ) -> Result<(), ConnectionError> {
+        self.write(value).await
+    }
+
+    /// Write a payload that is already serialized into the stream, skipping the serializer
+    ///
+    /// The bytes are framed exactly like a value passed to [`Connection::write`], so the peer can
+    /// read them with [`Connection::read_raw`], or with [`Connection::read`] if they hold a value
+    /// serialized in the peer's format.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Relay every message from one peer to another without deserializing it
+    ///     let mut upstream = Connection::dial("127.0.0.1:8080").await?;
+    ///     let mut downstream = Connection::dial("127.0.0.1:8081").await?;
+    ///
+    ///     while let Some(payload) = upstream.read_raw().await? {
+    ///         downstream.write_raw(&payload).await?;
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn write_raw(&mut self, bytes: &[u8]) -> Result<(), ConnectionError> {
+        if let Err(e) = self.write_frame(bytes, 0).await {
+            debug!(error = %e, "raw write failed");
+            return Err(e);
+        }
+
+        trace!(bytes_written = bytes.len(), "wrote raw payload");
+        Ok(())
+    }
+
+    /// Write a serializable value into the write buffer without flushing it to the stream
+    ///
+    /// The value is not guaranteed to reach the peer until [`Connection::flush`] is called. The
+    /// buffer is still flushed automatically whenever it fills up.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // Queue a few messages and send them together
+    ///     conn.write_no_flush(&"Hello").await?;
+    ///     conn.write_no_flush(&"world!").await?;
+    ///     conn.flush().await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn write_no_flush<T: Serialize>(&mut self, value: &T) -> Result<(), ConnectionError> {
+        let buf = self.record(self.format.serialize(value))?;
+        self.buffer_frame(&buf, 0).await
+    }
+
+    /// Write a slice of serializable values into the stream, flushing only once at the end
+    ///
+    /// Returns the number of values written.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // Send several messages with a single flush
+    ///     let written = conn.write_batch(&[1, 2, 3]).await?;
+    ///     assert_eq!(3, written);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn write_batch<T: Serialize>(
+        &mut self,
+        values: &[T],
+    ) -> Result<usize, ConnectionError> {
+        for value in values {
+            self.write_no_flush(value).await?;
+        }
+        self.flush().await?;
+        Ok(values.len())
+    }
+
+    /// Flush any buffered values to the stream
+    pub async fn flush(&mut self) -> Result<(), ConnectionError> {
+        self.check_idle().await?;
+        let timeout = self.write_timeout;
+        let result = with_write_timeout(timeout, async {
+            self.write_pending().await?;
+            self.stream.flush().await?;
+            Ok(())
+        })
+        .await;
+        if result.is_ok() {
+            self.flush_deadline = None;
+            self.touch();
+        }
+        self.record(result)
+    }
+
+    /// Gracefully close the connection
+    ///
+    /// Any buffered values are flushed and the write half of the stream is shut down, which lets
+    /// the peer observe a clean end of stream. Incoming bytes are then read and discarded until
+    /// the peer closes its side as well, so this waits for the peer unless a read timeout is set.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // Send a final message, then hang up
+    ///     conn.write(&"Goodbye!").await?;
+    ///     conn.close().await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn close(&mut self) -> Result<(), ConnectionError> {
+        self.flush().await?;
+
+        let timeout = self.write_timeout;
+        with_write_timeout(timeout, async { Ok(self.stream.shutdown().await?) }).await?;
+
+        self.buffer.clear();
+        while 0 != self.read_to_buffer().await? {
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+
+    /// Flush any buffered values and return the underlying stream
+    ///
+    /// Bytes that were received but not yet read are returned alongside the stream, since they
+    /// would otherwise be lost. This allows the stream to be handed to another protocol, such as
+    /// after a custom handshake.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer and agree to switch protocols
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///     conn.write(&"upgrade").await?;
+    ///
+    ///     // Take back the socket along with anything the peer already sent
+    ///     let (stream, unread) = conn.into_inner().await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn into_inner(mut self) -> Result<(S, BytesMut), ConnectionError> {
+        self.flush().await?;
+        self.skipped.restore(&mut self.buffer);
+        Ok((self.stream.into_inner(), self.buffer))
+    }
+
+    /// Reads from the socket until a complete message is received, or an error occurs
+    ///
+    /// Returns `None` once the peer closes the connection between two messages. If it closes
+    /// the connection part of the way through a message, this fails with
+    /// [`ConnectionError::UnexpectedEof`] instead. A complete message that cannot be deserialized
+    /// as `T` is discarded, and the deserialization error is returned instead of waiting for more
+    /// bytes.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use serde::{Serialize, Deserialize};
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    ///
+    /// #[derive(Serialize, Deserialize)]
+    /// struct Message { id: usize, text: String }
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // Read a message
+    ///     let message: Message = conn.read::<Message>().await?.unwrap();
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn read<T: DeserializeOwned>(&mut self) -> Result<Option<T>, ConnectionError> {
+        let result = self.read_value::<T>().await;
+        match &result {
+            Ok(Some(_)) => {}
+            Ok(None) => trace!(type_name = std::any::type_name::<T>(), "peer closed"),
+            Err(e) => debug!(type_name = std::any::type_name::<T>(), error = %e, "read failed"),
+        }
+        result
+    }
+
+    /// Read a deserializable value from the stream, failing with [`ConnectionError::Timeout`] if
+    /// no value arrives within `timeout`
+    ///
+    /// The timeout applies to this call only, unlike [`Connection::set_read_timeout`] which
+    /// bounds every read from the socket. Bytes received before the timeout elapses stay buffered,
+    /// so a later read picks up where this one stopped.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection, ConnectionError};
+    /// use std::error::Error;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // Wait at most a second for a message
+    ///     match conn.receive_timeout::<String>(Duration::from_secs(1)).await {
+    ///         Ok(message) => println!("received {message:?}"),
+    ///         Err(ConnectionError::Timeout) => println!("the peer is quiet"),
+    ///         Err(e) => return Err(e.into()),
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn receive_timeout<T: DeserializeOwned>(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<T>, ConnectionError> {
+        self.read_before(Instant::now() + timeout).await
+    }
+
+    /// Read a deserializable value from the stream, failing with [`ConnectionError::Timeout`] if
+    /// no value arrives before `deadline`
+    ///
+    /// This is [`Connection::receive_timeout`] with an absolute deadline, for protocols that pass
+    /// one deadline down through several operations. Bytes received before the deadline passes
+    /// stay buffered, so a later read picks up where this one stopped.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    /// use std::time::Duration;
+    /// use tokio::time::Instant;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // The request and its reply share a single deadline
+    ///     let deadline = Instant::now() + Duration::from_secs(1);
+    ///     conn.write_before(&"ping", deadline).await?;
+    ///     let reply: Option<String> = conn.read_before(deadline).await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn read_before<T: DeserializeOwned>(
+        &mut self,
+        deadline: Instant,
+    ) -> Result<Option<T>, ConnectionError> {
+        match tokio::time::timeout_at(deadline, self.read()).await {
+            Ok(result) => result,
+            Err(_) => self.record(Err(ConnectionError::Timeout)),
+        }
+    }
+
+    /// Write a serializable value into the stream, failing with [`ConnectionError::WriteTimeout`]
+    /// if the write does not complete before `deadline`
+    ///
+    /// A write that misses its deadline may have sent part of the frame, so the connection should
+    /// be closed afterwards. See [`Connection::read_before`] for an example.
+    pub async fn write_before<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+        deadline: Instant,
+    ) -> Result<(), ConnectionError> {
+        match tokio::time::timeout_at(deadline, self.write(value)).await {
+            Ok(result) => result,
+            Err(_) => self.record(Err(ConnectionError::WriteTimeout)),
+        }
+    }
+
+    /// Reads from the socket until a complete frame is received, returning its payload without
+    /// deserializing it
+    ///
+    /// A frame sent with [`Connection::write`] yields the serialized representation of the value
+    /// in the sender's format. Like [`Connection::read`], this returns `None` once the peer closes
+    /// the connection.
+    pub async fn read_raw(&mut self) -> Result<Option<Bytes>, ConnectionError> {
+        loop {
+            self.answer_controls().await?;
+            let result = frame::take_payload(
+                &mut self.buffer,
+                self.max_message_size,
+                self.hmac_key.as_ref(),
+                &self.middleware,
+            );
+            if let Ok(Some(payload)) = &result {
+                self.stats.record_received_message();
+                trace!(bytes_read = payload.len(), "read raw payload");
+            }
+            if let Some(payload) = self.record(result)? {
+                return Ok(Some(payload.freeze()));
+            }
+
+            if 0 == self.read_to_buffer().await? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Returns a message if one can be read without waiting, or `None` if no complete message has
+    /// arrived yet
+    ///
+    /// Only what is already buffered and a single read of whatever the socket has ready are
+    /// considered, so this never waits on the peer. Because `None` means "not yet", a peer closing
+    /// the connection is reported as [`ConnectionError::Disconnected`], or as
+    /// [`ConnectionError::UnexpectedEof`] if part of a message was left unread.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // Handle messages when they are available, doing other work in between
+    ///     loop {
+    ///         match conn.try_read::<String>().await? {
+    ///             Some(message) => println!("{}", message),
+    ///             None => tokio::task::yield_now().await,
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub async fn try_read<T: DeserializeOwned>(&mut self) -> Result<Option<T>, ConnectionError> {
+        if let Some(value) = self.parse_buffered().await? {
+            return Ok(Some(value));
+        }
+
+        let result = match self.stream.read_buf(&mut self.buffer).now_or_never() {
+            None => return Ok(None),
+            Some(Ok(0)) if !self.buffer.is_empty() => Err(ConnectionError::UnexpectedEof),
+            Some(Ok(0)) => Err(ConnectionError::Disconnected),
+            Some(result) => result.map_err(ConnectionError::from),
+        };
+        if let Ok(n) = result {
+            self.stats.record_received_bytes(n);
+        }
+        self.record(result)?;
+
+        self.parse_buffered().await
+    }
+
+    /// Discard every received byte, both in the internal buffer and ready on the socket
+    ///
+    /// Reads from the socket until it would block, so this never waits on the peer. Bytes the peer
+    /// sends afterwards are read as usual, which makes this useful for resetting a conversation
+    /// after a malformed message without closing the connection.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // Start over if the peer sends something unexpected
+    ///     if conn.read::<u32>().await.is_err() {
+    ///         conn.drain().await?;
+    ///         conn.write(&"RESET").await?;
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn drain(&mut self) -> Result<(), ConnectionError> {
+        self.clear_buffer();
+        loop {
+            let result = match self.stream.read_buf(&mut self.buffer).now_or_never() {
+                None | Some(Ok(0)) => return Ok(()),
+                Some(result) => result,
+            };
+            let n = self.record(result.map_err(ConnectionError::from))?;
+            self.stats.record_received_bytes(n);
+            self.clear_buffer();
+        }
+    }
+
+    /// Discard the bytes received into the internal buffer without reading from the socket
+    pub fn clear_buffer(&mut self) {
+        self.skipped.clear();
+        self.buffer.clear();
+    }
+
+    /// Reads from the socket until a complete message is received and returns it without
+    /// consuming it, so the next [`Connection::peek`] or [`Connection::read`] sees it again
+    ///
+    /// A message that fails to deserialize as `T` stays buffered as well, so it can still be
+    /// read as another type.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use serde::{Serialize, Deserialize};
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    ///
+    /// #[derive(Serialize, Deserialize)]
+    /// enum Message { Ping, Data(Vec<u8>) }
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // Look at the next message before deciding who reads it
+    ///     if let Some(Message::Data(_)) = conn.peek::<Message>().await? {
+    ///         let message: Message = conn.read().await?.unwrap();
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn peek<T: DeserializeOwned>(&mut self) -> Result<Option<T>, ConnectionError> {
+        loop {
+            self.answer_controls().await?;
+            let result = frame::peek_value(
+                &self.buffer,
+                self.format,
+                self.max_message_size,
+                self.hmac_key.as_ref(),
+                &self.middleware,
+            );
+            if let Some(value) = self.record(result)? {
+                return Ok(Some(value));
+            }
+
+            if 0 == self.read_to_buffer().await? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Reads from the socket until a complete message is received, or an error occurs
+    async fn read_value<T: DeserializeOwned>(&mut self) -> Result<Option<T>, ConnectionError> {
+        loop {
+            if let Some(value) = self.parse_buffered().await? {
+                return Ok(Some(value));
+            }
+
+            if 0 == self.read_to_buffer().await? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Answers any control frames at the front of the internal buffer, then attempts to
+    /// deserialize a T from the next complete frame
+    async fn parse_buffered<T: DeserializeOwned>(&mut self) -> Result<Option<T>, ConnectionError> {
+        self.answer_controls().await?;
+        let buffered = self.buffer.len();
+        let value = self.parse_value()?;
+        if value.is_some() {
+            trace!(
+                type_name = std::any::type_name::<T>(),
+                bytes_read = buffered - self.buffer.len(),
+                "read value"
+            );
+        }
+        Ok(value)
+    }
+
+    /// Attempts to deserialize a T from the next complete frame in the internal buffer.
+    fn parse_value<T: DeserializeOwned>(&mut self) -> Result<Option<T>, ConnectionError> {
+        let result = frame::parse_value(
+            &mut self.buffer,
+            self.format,
+            self.max_message_size,
+            self.hmac_key.as_ref(),
+            &self.middleware,
+        );
+        if let Ok(Some(_)) = result {
+            self.stats.record_received_message();
+        }
+        self.record(result)
+    }
+
+    /// Write a payload into the stream as a single length-prefixed frame with the given flags
+    async fn write_frame(&mut self, payload: &[u8], flags: u8) -> Result<(), ConnectionError> {
+        self.check_idle().await?;
+        let result = match self.write_pending().await {
+            Ok(()) => {
+                let compression = self.compression;
+                let key = self.hmac_key.as_ref();
+                frame::write_frame(
+                    &mut self.stream,
+                    payload,
+                    flags,
+                    compression,
+                    self.write_timeout,
+                    key,
+                    &self.middleware,
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        };
+        if let Ok(n) = result {
+            self.stats.record_sent(n);
+            self.touch();
+        }
+        self.record(result).map(|_| ())
+    }
+
+    /// Write a serializable value into the write buffer, flushing it only once `delay` has elapsed
+    /// since the first frame buffered after the last flush
+    pub(crate) async fn write_coalesced<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+        delay: Duration,
+    ) -> Result<(), ConnectionError> {
+        let buf = self.record(self.format.serialize(value))?;
+        self.buffer_frame(&buf, 0).await?;
+        match self.flush_deadline {
+            Some(deadline) if deadline <= Instant::now() => self.flush().await,
+            Some(_) => Ok(()),
+            None => {
+                self.flush_deadline = Some(Instant::now() + delay);
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns when the frames buffered by write coalescing must be flushed, if any are waiting
+    pub(crate) fn flush_deadline(&self) -> Option<Instant> {
+        self.flush_deadline
+    }
+
+    /// Returns the number of bytes written into the connection that have not reached the stream
+    pub(crate) fn unflushed(&self) -> usize {
+        self.stream.buffer().len() + self.pending.len()
+    }
+
+    /// Write a payload into the write buffer as a single length-prefixed frame without flushing
+    async fn buffer_frame(&mut self, payload: &[u8], flags: u8) -> Result<(), ConnectionError> {
+        self.check_idle().await?;
+        let timeout = self.write_timeout;
+        let result = with_write_timeout(timeout, async {
+            self.write_pending().await?;
+            frame::buffer_frame(
+                &mut self.stream,
+                payload,
+                flags,
+                self.compression,
+                self.hmac_key.as_ref(),
+                &self.middleware,
+            )
+            .await
+        })
+        .await;
+        if let Ok(n) = result {
+            self.stats.record_sent(n);
+            self.touch();
+        }
+        self.record(result).map(|_| ())
+    }
+
+    /// Write any frames queued through the `Sink` implementation, which must be sent first to
+    /// preserve ordering
+    async fn write_pending(&mut self) -> Result<(), ConnectionError> {
+        if !self.pending.is_empty() {
+            let pending = self.pending.split();
+            self.stream.write_all(&pending).await?;
+        }
+        Ok(())
+    }
+
+    /// Reads more bytes from the socket into the internal buffer, returning the number of bytes read
+    ///
+    /// A return value of `0` means the peer closed the connection on a frame boundary.
+    async fn read_to_buffer(&mut self) -> Result<usize, ConnectionError> {
+        self.check_idle().await?;
+        let idle_deadline = self.idle.as_ref().map(IdleTimer::deadline);
+
+        // The timeout covers the whole wait, so sending pings does not extend it
+        let read = with_timeout(self.read_timeout, self.read_or_ping());
+        let result = match idle_deadline {
+            Some(deadline) => tokio::select! {
+                result = read => Some(result),
+                _ = tokio::time::sleep_until(deadline) => None,
+            },
+            None => Some(read.await),
+        };
+        let Some(result) = result else {
+            return self.expire_idle().await;
+        };
+
+        if let Ok(n) = result {
+            self.stats.record_received_bytes(n);
+            if n > 0 {
+                self.touch();
+            }
+        }
+        self.record(result)
+    }
+
+    /// Reads whatever bytes the socket already has into the internal buffer without waiting,
+    /// returning whether any were read
+    ///
+    /// A closed or failed socket is left for the next read to run into, so values that were
+    /// already received are not lost.
+    fn read_ready(&mut self) -> bool {
+        match self.stream.read_buf(&mut self.buffer).now_or_never() {
+            Some(Ok(n)) if n > 0 => {
+                self.stats.record_received_bytes(n);
+                self.touch();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Waits for more bytes from the peer, sending keep-alive pings whenever they are due and
+    /// flushing coalesced frames once their delay elapses
+    ///
+    /// Waiting on the peer is the state an idle connection spends its time in, which makes it the
+    /// place to probe whether the peer is still there.
+    async fn read_or_ping(&mut self) -> Result<usize, ConnectionError> {
+        loop {
+            let keepalive = self.keepalive.as_ref().map(Keepalive::shared);
+            let flush_deadline = self.flush_deadline;
+            if keepalive.is_none() && flush_deadline.is_none() {
+                return frame::read_to_buffer(&mut self.stream, &mut self.buffer, None).await;
+            }
+
+            let ping_due = async {
+                match &keepalive {
+                    Some(keepalive) => keepalive.ping_due().await,
+                    None => std::future::pending().await,
+                }
+            };
+            let flush_due = async {
+                match flush_deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            };
+            let ping = tokio::select! {
+                result = frame::read_to_buffer(&mut self.stream, &mut self.buffer, None) => return result,
+                _ = ping_due => true,
+                _ = flush_due => false,
+            };
+
+            if let (true, Some(keepalive)) = (ping, keepalive) {
+                self.write_pending().await?;
+                frame::write_control(&mut self.stream, Control::Ping, self.write_timeout).await?;
+                keepalive.ping_sent();
+            } else {
+                // Frames held back by write coalescing are due, and the peer may be waiting on them
+                with_write_timeout(self.write_timeout, async {
+                    self.stream.flush().await.map_err(ConnectionError::from)
+                })
+                .await?;
+                self.flush_deadline = None;
+            }
+        }
+    }
+
+    /// Respond to every control frame at the front of the internal buffer, once the frames skipped
+    /// by [`Connection::read_prioritized`] are back in front of it
+    async fn answer_controls(&mut self) -> Result<(), ConnectionError> {
+        self.skipped.restore(&mut self.buffer);
+        while let Some(control) = frame::take_control(&mut self.buffer) {
+            self.handle_control(control).await?;
+        }
+        Ok(())
+    }
+
+    /// Respond to a control frame received from the peer
+    async fn handle_control(&mut self, control: Control) -> Result<(), ConnectionError> {
+        match control {
+            Control::Ping => self.write_control(Control::Pong).await,
+            Control::Pong => {
+                if let Some(keepalive) = &self.keepalive {
+                    keepalive.shared().pong_received();
+                }
+                Ok(())
+            }
+            // Nobody is waiting for this acknowledgment anymore
+            Control::Ack(_) => Ok(()),
+        }
+    }
+
+    /// Write a control frame into the stream and flush it, after any frames queued through the
+    /// `Sink` implementation so that it never lands inside a partially written one
+    async fn write_control(&mut self, control: Control) -> Result<(), ConnectionError> {
+        self.check_idle().await?;
+        let result = match self.write_pending().await {
+            Ok(()) => frame::write_control(&mut self.stream, control, self.write_timeout).await,
+            Err(e) => Err(e),
+        };
+        self.record(result)
+    }
+
+    /// Record the outcome of an operation, counting it and reporting it to the error hook if it
+    /// failed
+    pub(crate) fn record<T>(
+        &mut self,
+        result: Result<T, ConnectionError>,
+    ) -> Result<T, ConnectionError> {
+        #[cfg(feature = "error-hook")]
+        if let (Err(e), Some(hook)) = (&result, &self.error_hook) {
+            // A panicking hook must not unwind out of an operation that is half done
+            let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| hook(e)));
+        }
+        self.stats.record(result)
+    }
+}
+
+/// Configures the OS-level keep-alive of a TCP socket
+pub(crate) fn set_tcp_keepalive(stream: &TcpStream, time: Option<Duration>) -> std::io::Result<()> {
+    let socket = socket2::SockRef::from(stream);
+    match time {
+        Some(time) => socket.set_tcp_keepalive(&socket2::TcpKeepalive::new().with_time(time)),
+        None => socket.set_keepalive(false),
+    }
+}
+
+/// Sets the time-to-live or hop limit of the packets a socket sends, depending on its family
+pub(crate) fn set_ip_ttl(stream: &TcpStream, ttl: u32) -> std::io::Result<()> {
+    if stream.local_addr()?.is_ipv4() {
+        stream.set_ttl(ttl)
+    } else {
+        socket2::SockRef::from(stream).set_unicast_hops_v6(ttl)
+    }
+}
+
+/// Connects a new TCP socket bound to `local` to `remote`
+async fn connect_from(local: SocketAddr, remote: SocketAddr) -> Result<TcpStream, ConnectionError> {
+    let bind = || {
+        let socket = socket2::Socket::new(
+            socket2::Domain::for_address(local),
+            socket2::Type::STREAM,
+            Some(socket2::Protocol::TCP),
+        )?;
+        socket.set_reuse_address(true)?;
+        socket.bind(&local.into())?;
+        socket.set_nonblocking(true)?;
+        Ok::<_, Error>(socket)
+    };
+    let socket = bind()
+        .map_err(|e| ConnectionError::IoErrorContext(e, format!("failed to bind {}", local)))?;
+
+    // Tokio drives the connect so it does not block the runtime
+    let socket = tokio::net::TcpSocket::from_std_stream(socket.into());
+    socket.connect(remote).await.map_err(|e| {
+        let context = format!("failed to connect from {} to {}", local, remote);
+        ConnectionError::IoErrorContext(e, context)
+    })
+}
+
+/// Connects to the first address `addr` resolves to that accepts the connection
+///
+/// Unlike [`TcpStream::connect`], a failure names the address that refused the connection.
+pub(crate) async fn connect<A: ToSocketAddrs>(addr: A) -> Result<TcpStream, ConnectionError> {
+    let resolve_error =
+        |e| ConnectionError::IoErrorContext(e, "failed to resolve the remote address".into());
+
+    let mut last_error = None;
+    for remote in tokio::net::lookup_host(addr).await.map_err(resolve_error)? {
+        match TcpStream::connect(remote).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => {
+                let context = format!("failed to connect to {}", remote);
+                last_error = Some(ConnectionError::IoErrorContext(e, context));
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| {
+        resolve_error(Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "could not resolve to any address",
+        ))
+    }))
+}
+
+/// Runs a fallible operation, failing with [`ConnectionError::Timeout`] if it does not complete in time
+pub(crate) async fn with_timeout<T, F>(
+    timeout: Option<Duration>,
+    operation: F,
+) -> Result<T, ConnectionError>
+where
+    F: Future<Output = Result<T, ConnectionError>>,
+{
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, operation)
+            .await
+            .map_err(|_| ConnectionError::Timeout)?,
+        None => operation.await,
+    }
+}
+
+/// Runs a write, failing with [`ConnectionError::WriteTimeout`] if it does not complete in time
+pub(crate) async fn with_write_timeout<T, F>(
+    timeout: Option<Duration>,
+    operation: F,
+) -> Result<T, ConnectionError>
+where
+    F: Future<Output = Result<T, ConnectionError>>,
+{
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, operation)
+            .await
+            .map_err(|_| ConnectionError::WriteTimeout)?,
+        None => operation.await,
+    }
+}
+
+impl From<std::io::Error> for ConnectionError {
+    fn from(e: std::io::Error) -> Self {
+        ConnectionError::IoError(e)
+    }
+}
+
+impl From<Box<bincode::ErrorKind>> for ConnectionError {
+    fn from(e: Box<bincode::ErrorKind>) -> Self {
+        ConnectionError::BincodeError(Box::new(e))
+    }
+}
+
+#[cfg(feature = "postcard")]
+impl From<postcard::Error> for ConnectionError {
+    fn from(e: postcard::Error) -> Self {
+        ConnectionError::PostcardError(e)
+    }
+}
+
+impl From<serde_json::Error> for ConnectionError {
+    fn from(e: serde_json::Error) -> Self {
+        ConnectionError::JsonError(e)
+    }
+}
+
+impl From<rmp_serde::encode::Error> for ConnectionError {
+    fn from(e: rmp_serde::encode::Error) -> Self {
+        ConnectionError::MsgPackError(Box::new(e))
+    }
+}
+
+impl From<rmp_serde::decode::Error> for ConnectionError {
+    fn from(e: rmp_serde::decode::Error) -> Self {
+        ConnectionError::MsgPackError(Box::new(e))
     }
 }
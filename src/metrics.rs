@@ -0,0 +1,98 @@
+use crate::ConnectionError;
+use metrics::{counter, gauge, Counter, Gauge, Label};
+
+/// The metrics of a single connection, labeled with its name if it has one
+///
+/// The connection counts towards `connection_active_count` for as long as this is alive.
+pub(crate) struct Metrics {
+    labels: Vec<Label>,
+    bytes_sent: Counter,
+    bytes_received: Counter,
+    messages_sent: Counter,
+    messages_received: Counter,
+    active: Gauge,
+}
+
+impl Metrics {
+    pub(crate) fn new(name: Option<&str>) -> Self {
+        let labels: Vec<Label> = name
+            .map(|name| Label::new("connection_name", name.to_string()))
+            .into_iter()
+            .collect();
+        let active = gauge!("connection_active_count", labels.clone());
+        active.increment(1.0);
+
+        Self {
+            bytes_sent: counter!("connection_bytes_sent_total", labels.clone()),
+            bytes_received: counter!("connection_bytes_received_total", labels.clone()),
+            messages_sent: counter!("connection_messages_sent_total", labels.clone()),
+            messages_received: counter!("connection_messages_received_total", labels.clone()),
+            active,
+            labels,
+        }
+    }
+
+    pub(crate) fn record_sent_bytes(&self, n: u64) {
+        self.bytes_sent.increment(n);
+    }
+
+    pub(crate) fn record_sent_message(&self) {
+        self.messages_sent.increment(1);
+    }
+
+    pub(crate) fn record_received_bytes(&self, n: u64) {
+        self.bytes_received.increment(n);
+    }
+
+    pub(crate) fn record_received_message(&self) {
+        self.messages_received.increment(1);
+    }
+
+    /// Errors are rare, so their counter is only looked up once one of a given kind occurs
+    pub(crate) fn record_error(&self, e: &ConnectionError) {
+        let mut labels = self.labels.clone();
+        labels.push(Label::new("kind", kind(e)));
+        counter!("connection_errors_total", labels).increment(1);
+    }
+}
+
+impl Drop for Metrics {
+    fn drop(&mut self) {
+        self.active.decrement(1.0);
+    }
+}
+
+/// Returns the value of the `kind` label of an error
+fn kind(e: &ConnectionError) -> &'static str {
+    match e {
+        ConnectionError::IoError(_) | ConnectionError::IoErrorContext(..) => "io",
+        ConnectionError::BincodeError(_) => "bincode",
+        ConnectionError::JsonError(_) => "json",
+        ConnectionError::MsgPackError(_) => "msgpack",
+        ConnectionError::CborError(_) => "cbor",
+        #[cfg(feature = "postcard")]
+        ConnectionError::PostcardError(_) => "postcard",
+        ConnectionError::Disconnected => "disconnected",
+        ConnectionError::ConnectionReset(_) => "connection_reset",
+        ConnectionError::UnexpectedEof => "unexpected_eof",
+        ConnectionError::InvalidConfiguration(_) => "invalid_configuration",
+        #[cfg(feature = "tls")]
+        ConnectionError::TlsError(_) => "tls",
+        ConnectionError::Timeout => "timeout",
+        ConnectionError::WriteTimeout => "write_timeout",
+        #[cfg(feature = "handshake")]
+        ConnectionError::VersionMismatch { .. } => "version_mismatch",
+        ConnectionError::IdleTimeout => "idle_timeout",
+        ConnectionError::AllAddressesFailed(_) => "all_addresses_failed",
+        ConnectionError::PoolExhausted => "pool_exhausted",
+        ConnectionError::MaxRetriesExceeded(_) => "max_retries_exceeded",
+        ConnectionError::NegotiationFailed(_) => "negotiation_failed",
+        ConnectionError::NoCommonCompression => "no_common_compression",
+        ConnectionError::AuthenticationFailed(_) => "authentication_failed",
+        ConnectionError::ProxyError(_) => "proxy",
+        ConnectionError::ProxyAuthenticationFailed(_) => "proxy_authentication_failed",
+        ConnectionError::OutOfOrder { .. } => "out_of_order",
+        ConnectionError::Context(e, _) => kind(e),
+        ConnectionError::MessageTooLarge { .. } => "message_too_large",
+    }
+}
@@ -0,0 +1,118 @@
+use crate::Connection;
+use std::borrow::Cow;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Transforms the payload of every data frame a connection writes or reads, added with
+/// [`Connection::with_middleware`]
+///
+/// [`Middleware::on_write`] sees a payload once it is serialized and compressed, right before it
+/// is framed, and [`Middleware::on_read`] sees it right after it is taken out of its frame and
+/// before it is decompressed, so a middleware that encrypts payloads still benefits from
+/// compression. The frame header and HMAC tag are computed over the transformed bytes. Both
+/// methods do nothing by default, so a middleware that only observes traffic implements just
+/// the one it needs.
+///
+/// Control frames such as keep-alive pings do not pass through middleware. The peer must apply
+/// the inverse transformation, usually by adding the same middleware.
+///
+/// # Examples
+///
+/// ```
+/// use connection::Middleware;
+///
+/// /// Flips every bit of the payload, which the peer undoes with the same middleware
+/// struct Invert;
+///
+/// impl Middleware for Invert {
+///     fn on_write(&self, bytes: &mut Vec<u8>) {
+///         bytes.iter_mut().for_each(|byte| *byte = !*byte);
+///     }
+///
+///     fn on_read(&self, bytes: &mut Vec<u8>) {
+///         bytes.iter_mut().for_each(|byte| *byte = !*byte);
+///     }
+/// }
+/// ```
+pub trait Middleware: Send + Sync {
+    /// Transform the payload of a frame about to be written
+    fn on_write(&self, bytes: &mut Vec<u8>) {
+        let _ = bytes;
+    }
+
+    /// Transform the payload of a frame that was just read
+    fn on_read(&self, bytes: &mut Vec<u8>) {
+        let _ = bytes;
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    /// Pass the payload of every frame this connection writes and reads through a middleware
+    ///
+    /// Middleware composes by chaining: writes pass through the middleware in the order they were
+    /// added, and reads in the reverse order, so each one undoes its own transformation before the
+    /// ones added earlier see the payload. Both halves of a [`Connection::split`] keep the
+    /// middleware.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection, Middleware};
+    /// use std::error::Error;
+    ///
+    /// struct LogSizes;
+    ///
+    /// impl Middleware for LogSizes {
+    ///     fn on_write(&self, bytes: &mut Vec<u8>) {
+    ///         println!("writing {} bytes", bytes.len());
+    ///     }
+    /// }
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080")
+    ///         .await?
+    ///         .with_middleware(LogSizes);
+    ///
+    ///     // Send a message
+    ///     conn.write(&"Hello, world!").await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn with_middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+}
+
+/// Passes the body of a frame about to be written through the middleware in the order they were
+/// added
+pub(crate) fn on_write<'a>(
+    middleware: &[Arc<dyn Middleware>],
+    body: Cow<'a, [u8]>,
+) -> Cow<'a, [u8]> {
+    if middleware.is_empty() {
+        return body;
+    }
+
+    let mut bytes = body.into_owned();
+    for layer in middleware {
+        layer.on_write(&mut bytes);
+    }
+    Cow::Owned(bytes)
+}
+
+/// Passes the body of a frame that was just read through the middleware in reverse order
+pub(crate) fn on_read<'a>(middleware: &[Arc<dyn Middleware>], body: &'a [u8]) -> Cow<'a, [u8]> {
+    if middleware.is_empty() {
+        return Cow::Borrowed(body);
+    }
+
+    let mut bytes = body.to_vec();
+    for layer in middleware.iter().rev() {
+        layer.on_read(&mut bytes);
+    }
+    Cow::Owned(bytes)
+}
@@ -0,0 +1,36 @@
+//! In-memory connections for testing code that uses a [`Connection`] without a network stack
+//!
+//! This module is only available with the `test-helpers` feature enabled.
+//!
+//! # Examples
+//!
+//! ```
+//! use connection::mock::mock_pair;
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let (mut client, mut server) = mock_pair();
+//!
+//!     client.write(&"Hello, world!").await.unwrap();
+//!     let message: String = server.read().await.unwrap().unwrap();
+//!     assert_eq!("Hello, world!", message);
+//! }
+//! ```
+use crate::{Connection, DEFAULT_BUFFER_SIZE};
+use tokio::io::DuplexStream;
+
+/// A connection backed by an in-memory stream
+pub type MockConnection = Connection<DuplexStream>;
+
+/// Create a pair of connections where everything written to one can be read from the other
+pub fn mock_pair() -> (MockConnection, MockConnection) {
+    mock_pair_with_capacity(DEFAULT_BUFFER_SIZE)
+}
+
+/// Create a pair of connected connections whose in-memory pipe holds up to `max_buf_size` bytes
+///
+/// Writes block once the pipe is full until the other side reads from it.
+pub fn mock_pair_with_capacity(max_buf_size: usize) -> (MockConnection, MockConnection) {
+    let (client, server) = tokio::io::duplex(max_buf_size);
+    (Connection::new(client), Connection::new(server))
+}
@@ -0,0 +1,321 @@
+use crate::{Connection, ConnectionError, ConnectionReader, ConnectionWriter, SerdeFormat};
+use bytes::{BufMut, Bytes, BytesMut};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::JoinHandle;
+
+/// The number of messages a channel may have in flight unless configured otherwise
+const DEFAULT_CHANNEL_CAPACITY: usize = 32;
+
+/// The number of frames waiting to be written to the connection
+const OUTGOING_CAPACITY: usize = 64;
+
+/// The size of the channel ID and frame kind that precede every multiplexed payload
+const MUX_HEADER_SIZE: usize = 4 + 1;
+
+/// A frame carrying a message for a channel
+const DATA: u8 = 0;
+
+/// A frame allowing the peer to send more messages on a channel
+const CREDIT: u8 = 1;
+
+/// Tunnels many logical channels over a single [`Connection`]
+///
+/// Every frame is prefixed with the `u32` ID of its channel. Channels are paired by ID, so the
+/// first channel opened on each side talks to the first channel opened on the other, and so on.
+///
+/// Each channel may have at most `capacity` messages in flight. A writer waits for the peer to
+/// receive earlier messages before sending more, so a slow consumer on one channel only holds back
+/// its own writer and never the other channels. Both sides must use the same capacity.
+///
+/// # Examples
+///
+/// ```no_run
+/// use connection::{Connection, Multiplexer};
+/// use std::error::Error;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn Error>> {
+///     // Connect to a peer
+///     let conn = Connection::dial("127.0.0.1:8080").await?;
+///     let mux = Multiplexer::new(conn);
+///
+///     // Open two independent channels over the same connection
+///     let (control, _) = mux.open_channel();
+///     let (data, mut replies) = mux.open_channel();
+///
+///     control.send(&"start").await?;
+///     data.send(&vec![1, 2, 3]).await?;
+///     let reply: Option<String> = replies.recv().await?;
+///
+///     Ok(())
+/// }
+/// ```
+pub struct Multiplexer {
+    shared: Arc<Shared>,
+    next_id: AtomicU32,
+    reader: JoinHandle<()>,
+}
+
+/// The sending half of a multiplexed channel, created by [`Multiplexer::open_channel`]
+#[derive(Clone)]
+pub struct ChannelWriter {
+    id: u32,
+    format: SerdeFormat,
+    credits: Arc<Semaphore>,
+    outgoing: mpsc::Sender<Bytes>,
+}
+
+/// The receiving half of a multiplexed channel, created by [`Multiplexer::open_channel`]
+pub struct ChannelReceiver {
+    id: u32,
+    format: SerdeFormat,
+    incoming: mpsc::Receiver<Bytes>,
+    outgoing: mpsc::Sender<Bytes>,
+}
+
+/// The state shared between a multiplexer and its background tasks
+struct Shared {
+    format: SerdeFormat,
+    capacity: usize,
+    outgoing: mpsc::Sender<Bytes>,
+    /// `None` once the connection has closed
+    channels: Mutex<Option<HashMap<u32, Channel>>>,
+}
+
+struct Channel {
+    incoming: mpsc::Sender<Bytes>,
+    /// Taken when the channel is opened locally
+    receiver: Option<mpsc::Receiver<Bytes>>,
+    credits: Arc<Semaphore>,
+}
+
+impl Multiplexer {
+    /// Start multiplexing channels over the connection with the default channel capacity
+    ///
+    /// This must be called from within a Tokio runtime.
+    pub fn new<S>(conn: Connection<S>) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        Self::with_capacity(conn, DEFAULT_CHANNEL_CAPACITY)
+    }
+
+    /// Start multiplexing channels over the connection, allowing `capacity` messages in flight
+    /// per channel
+    ///
+    /// This must be called from within a Tokio runtime.
+    pub fn with_capacity<S>(conn: Connection<S>, capacity: usize) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let format = conn.format();
+        let (reader, writer) = conn.split();
+        let (outgoing, outgoing_rx) = mpsc::channel(OUTGOING_CAPACITY);
+
+        let shared = Arc::new(Shared {
+            format,
+            capacity: capacity.max(1),
+            outgoing,
+            channels: Mutex::new(Some(HashMap::new())),
+        });
+
+        tokio::spawn(write_frames(writer, outgoing_rx));
+        let reader = tokio::spawn(dispatch(reader, shared.clone()));
+
+        Multiplexer {
+            shared,
+            next_id: AtomicU32::new(0),
+            reader,
+        }
+    }
+
+    /// Open the next channel, returning its writer and receiver
+    pub fn open_channel(&self) -> (ChannelWriter, ChannelReceiver) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (credits, incoming) = self.shared.open(id);
+
+        let writer = ChannelWriter {
+            id,
+            format: self.shared.format,
+            credits,
+            outgoing: self.shared.outgoing.clone(),
+        };
+
+        let receiver = ChannelReceiver {
+            id,
+            format: self.shared.format,
+            incoming,
+            outgoing: self.shared.outgoing.clone(),
+        };
+
+        (writer, receiver)
+    }
+}
+
+impl Drop for Multiplexer {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+impl ChannelWriter {
+    /// Returns the ID of the channel
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Send a serializable value on the channel, waiting while the channel is at capacity
+    pub async fn send<T: Serialize>(&self, value: &T) -> Result<(), ConnectionError> {
+        self.credits.acquire().await.map_err(|_| closed())?.forget();
+
+        let payload = self.format.serialize(value)?;
+        let frame = encode(self.id, DATA, &payload);
+        self.outgoing.send(frame).await.map_err(|_| closed())
+    }
+}
+
+impl ChannelReceiver {
+    /// Returns the ID of the channel
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Receive the next value sent on the channel, or `None` once the connection has closed
+    pub async fn recv<T: DeserializeOwned>(&mut self) -> Result<Option<T>, ConnectionError> {
+        let payload = match self.incoming.recv().await {
+            Some(payload) => payload,
+            None => return Ok(None),
+        };
+
+        // Let the peer send another message in place of this one
+        let credit = encode(self.id, CREDIT, &1u32.to_be_bytes());
+        let _ = self.outgoing.send(credit).await;
+
+        self.format.deserialize(&payload).map(Some)
+    }
+}
+
+impl Shared {
+    /// Returns the credits and receiver of a channel that is being opened locally
+    fn open(&self, id: u32) -> (Arc<Semaphore>, mpsc::Receiver<Bytes>) {
+        let mut channels = self.channels.lock().unwrap();
+        match channels.as_mut() {
+            Some(channels) => {
+                let channel = channels.entry(id).or_insert_with(|| self.new_channel());
+                let receiver = channel
+                    .receiver
+                    .take()
+                    .expect("channel IDs are never reused");
+                (channel.credits.clone(), receiver)
+            }
+            None => {
+                // The connection is gone, so the channel is closed from the start
+                let channel = self.new_channel();
+                channel.credits.close();
+                (channel.credits, channel.receiver.unwrap())
+            }
+        }
+    }
+
+    fn new_channel(&self) -> Channel {
+        let (incoming, receiver) = mpsc::channel(self.capacity);
+        Channel {
+            incoming,
+            receiver: Some(receiver),
+            credits: Arc::new(Semaphore::new(self.capacity)),
+        }
+    }
+
+    /// Runs an operation on a channel, creating it if the peer opened it first
+    fn with_channel<T>(&self, id: u32, f: impl FnOnce(&Channel) -> T) -> Option<T> {
+        let mut channels = self.channels.lock().unwrap();
+        let channel = channels
+            .as_mut()?
+            .entry(id)
+            .or_insert_with(|| self.new_channel());
+        Some(f(channel))
+    }
+
+    /// Wake every receiver and writer after the connection has closed
+    fn close(&self) {
+        if let Some(channels) = self.channels.lock().unwrap().take() {
+            for channel in channels.values() {
+                channel.credits.close();
+            }
+        }
+    }
+}
+
+/// Writes frames queued by the channels until every channel and the multiplexer are dropped
+async fn write_frames<S: AsyncRead + AsyncWrite + Unpin>(
+    mut writer: ConnectionWriter<S>,
+    mut outgoing: mpsc::Receiver<Bytes>,
+) {
+    while let Some(frame) = outgoing.recv().await {
+        if writer.write_raw(&frame).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Routes every frame received from the peer to its channel until the connection closes
+async fn dispatch<S: AsyncRead + AsyncWrite + Unpin>(
+    mut reader: ConnectionReader<S>,
+    shared: Arc<Shared>,
+) {
+    while let Ok(Some(frame)) = reader.read_raw().await {
+        if frame.len() < MUX_HEADER_SIZE {
+            break;
+        }
+
+        let id = u32::from_be_bytes([frame[0], frame[1], frame[2], frame[3]]);
+        let body = frame.slice(MUX_HEADER_SIZE..);
+        let delivered = match frame[4] {
+            // The peer never sends more than the channel can hold, so this only fails if the
+            // receiver was dropped, in which case the message has nowhere to go
+            DATA => shared
+                .with_channel(id, |channel| channel.incoming.try_send(body))
+                .map(|result| !matches!(result, Err(mpsc::error::TrySendError::Full(_)))),
+            CREDIT if body.len() == 4 => {
+                let credits = u32::from_be_bytes([body[0], body[1], body[2], body[3]]);
+                let capacity = shared.capacity;
+                shared.with_channel(id, |channel| {
+                    // The peer never returns more credits than were used, so anything beyond the
+                    // capacity of the channel is a protocol error
+                    let available = channel.credits.available_permits();
+                    let valid = available.saturating_add(credits as usize) <= capacity;
+                    if valid {
+                        channel.credits.add_permits(credits as usize);
+                    }
+                    valid
+                })
+            }
+            _ => Some(false),
+        };
+
+        if delivered != Some(true) {
+            break;
+        }
+    }
+
+    shared.close();
+}
+
+/// Prefix a payload with its channel ID and frame kind
+fn encode(id: u32, kind: u8, payload: &[u8]) -> Bytes {
+    let mut frame = BytesMut::with_capacity(MUX_HEADER_SIZE + payload.len());
+    frame.put_u32(id);
+    frame.put_u8(kind);
+    frame.put_slice(payload);
+    frame.freeze()
+}
+
+fn closed() -> ConnectionError {
+    ConnectionError::ConnectionReset("multiplexed connection closed".into())
+}
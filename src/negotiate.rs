@@ -0,0 +1,374 @@
+use crate::{
+    CompressionAlgorithm, CompressionMode, Connection, ConnectionError, SerdeFormat,
+    DEFAULT_MAX_MESSAGE_SIZE,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Identifies a capabilities frame sent by this library ("NEGO")
+const MAGIC: u32 = 0x4E45_474F;
+
+/// Identifies a frame listing compression algorithms ("COMP")
+const COMPRESSION_MAGIC: u32 = 0x434F_4D50;
+
+/// The serialization formats, compression modes and message size a peer supports
+///
+/// Formats and compression modes are listed in order of preference, most preferred first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The serialization formats this side can speak
+    pub formats: Vec<SerdeFormat>,
+    /// The compression modes this side can use, where the level of [`CompressionMode::Zstd`] is
+    /// the highest level this side is willing to compress with
+    pub compression: Vec<CompressionMode>,
+    /// The largest message this side accepts
+    pub max_message_size: usize,
+}
+
+/// Every format in its declaration order, no compression and the default message size limit
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self {
+            formats: vec![
+                SerdeFormat::Bincode,
+                SerdeFormat::Json,
+                SerdeFormat::MsgPack,
+                SerdeFormat::Cbor,
+                #[cfg(feature = "postcard")]
+                SerdeFormat::Postcard,
+            ],
+            compression: vec![CompressionMode::None],
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+}
+
+/// The settings two peers agreed on, returned by [`ProtocolNegotiator::negotiate`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NegotiatedCapabilities {
+    /// The serialization format both peers speak
+    pub format: SerdeFormat,
+    /// The compression mode both peers use, at the lower of their zstd levels
+    pub compression: CompressionMode,
+    /// The smaller of the two peers' message size limits
+    pub max_message_size: usize,
+}
+
+/// Agrees on a serialization format, compression mode and message size with the peer before the
+/// first application message is sent
+///
+/// Each side sends its [`Capabilities`] and receives the peer's. Both sides then pick the format
+/// and compression mode they have in common with the best combined rank in the two preference
+/// lists, breaking ties in the order of [`Capabilities::default`], so both arrive at the same
+/// choice without another round trip. The capabilities are encoded independently of the
+/// connection's format, so peers that start out with different formats can still negotiate.
+///
+/// # Examples
+///
+/// ```no_run
+/// use connection::{Capabilities, CompressionMode, Connection, ProtocolNegotiator, SerdeFormat};
+/// use std::error::Error;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn Error>> {
+///     // Connect to a peer
+///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+///
+///     // Prefer MessagePack with zstd, but settle for bincode without compression
+///     let mut negotiator = ProtocolNegotiator::new(Capabilities {
+///         formats: vec![SerdeFormat::MsgPack, SerdeFormat::Bincode],
+///         compression: vec![CompressionMode::Zstd(3), CompressionMode::None],
+///         max_message_size: 1024 * 1024,
+///     });
+///     let negotiated = negotiator.negotiate(&mut conn).await?;
+///     println!("speaking {:?}", negotiated.format);
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ProtocolNegotiator {
+    supported: Capabilities,
+}
+
+impl ProtocolNegotiator {
+    /// Create a negotiator that offers the given capabilities to the peer
+    pub fn new(supported: Capabilities) -> Self {
+        Self { supported }
+    }
+
+    /// Exchange capabilities with the peer, which must negotiate as well, and configure the
+    /// connection with the result
+    ///
+    /// This must happen before any other message is sent. The agreed format, compression mode
+    /// and message size limit are applied to `conn` before they are returned. Fails with
+    /// [`ConnectionError::NegotiationFailed`] if the peers have no format or compression mode in
+    /// common, or if the peer did not send its capabilities.
+    pub async fn negotiate<S: AsyncRead + AsyncWrite + Unpin>(
+        &mut self,
+        conn: &mut Connection<S>,
+    ) -> Result<NegotiatedCapabilities, ConnectionError> {
+        conn.write_raw(&encode(&self.supported)).await?;
+
+        let payload = conn
+            .read_raw()
+            .await?
+            .ok_or(ConnectionError::Disconnected)?;
+
+        let negotiated = match decode(&payload) {
+            Some(remote) => choose(&self.supported, &remote),
+            None => Err(ConnectionError::NegotiationFailed(
+                "the peer did not send its capabilities".into(),
+            )),
+        };
+        let negotiated = conn.record(negotiated)?;
+
+        conn.format = negotiated.format;
+        conn.compression = negotiated.compression;
+        conn.max_message_size = negotiated.max_message_size;
+        trace!(?negotiated, "completed negotiation");
+        Ok(negotiated)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    /// Agree on a compression algorithm with the peer, which must negotiate as well, and compress
+    /// the payloads this connection writes with it
+    ///
+    /// Each side sends the algorithms it supports, in order of preference, and receives the
+    /// peer's. Both sides then pick the algorithm they have in common with the best combined rank
+    /// in the two lists, breaking ties by the order of [`CompressionAlgorithm`]'s variants, so
+    /// both arrive at the same choice. Zstd keeps the level this connection is already
+    /// configured with, if any.
+    ///
+    /// This must happen while no other message is in flight. Fails with
+    /// [`ConnectionError::NoCommonCompression`] if the lists do not intersect, in which case the
+    /// compression is left as it was.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{CompressionAlgorithm, Connection};
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // Prefer LZ4, but fall back to sending payloads as is
+    ///     let preferred = [CompressionAlgorithm::Lz4, CompressionAlgorithm::None];
+    ///     let algorithm = conn.negotiate_compression(&preferred).await?;
+    ///     println!("compressing with {:?}", algorithm);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn negotiate_compression(
+        &mut self,
+        preferred: &[CompressionAlgorithm],
+    ) -> Result<CompressionAlgorithm, ConnectionError> {
+        let preferred = &preferred[..preferred.len().min(u8::MAX as usize)];
+        let mut offer = COMPRESSION_MAGIC.to_be_bytes().to_vec();
+        offer.push(preferred.len() as u8);
+        offer.extend(preferred.iter().map(|algorithm| algorithm.id()));
+        self.write_raw(&offer).await?;
+
+        let payload = self
+            .read_raw()
+            .await?
+            .ok_or(ConnectionError::Disconnected)?;
+
+        let chosen = match decode_algorithms(&payload) {
+            Some(remote) => best_common(preferred, &remote, |algorithm| algorithm.id())
+                .ok_or(ConnectionError::NoCommonCompression),
+            None => Err(ConnectionError::NegotiationFailed(
+                "the peer did not send its compression algorithms".into(),
+            )),
+        };
+        let chosen = self.record(chosen)?;
+
+        self.compression = match (chosen, self.compression) {
+            (CompressionAlgorithm::None, _) => CompressionMode::None,
+            (CompressionAlgorithm::Zstd, CompressionMode::Zstd(level)) => {
+                CompressionMode::Zstd(level)
+            }
+            (CompressionAlgorithm::Zstd, _) => {
+                CompressionMode::Zstd(zstd::DEFAULT_COMPRESSION_LEVEL)
+            }
+            (CompressionAlgorithm::Lz4, _) => CompressionMode::Lz4,
+            (CompressionAlgorithm::Snappy, _) => CompressionMode::Snappy,
+        };
+        trace!(algorithm = ?chosen, "negotiated compression");
+        Ok(chosen)
+    }
+}
+
+/// Picks the settings shared by both sides, identically on both of them
+fn choose(
+    local: &Capabilities,
+    remote: &Capabilities,
+) -> Result<NegotiatedCapabilities, ConnectionError> {
+    let format = best_common(&local.formats, &remote.formats, |format| format_id(*format))
+        .ok_or_else(|| {
+            ConnectionError::NegotiationFailed("no serialization format in common".into())
+        })?;
+
+    // Both sides must agree on the algorithm, while the level only has to suit both of them
+    let algorithm = |mode: &CompressionMode| compression_id(*mode);
+    let compression = best_common(&local.compression, &remote.compression, algorithm)
+        .map(|mode| match mode {
+            CompressionMode::Zstd(_) => CompressionMode::Zstd(
+                zstd_level(&local.compression)
+                    .min(zstd_level(&remote.compression))
+                    .unwrap_or_default(),
+            ),
+            mode => mode,
+        })
+        .ok_or_else(|| {
+            ConnectionError::NegotiationFailed("no compression mode in common".into())
+        })?;
+
+    Ok(NegotiatedCapabilities {
+        format,
+        compression,
+        max_message_size: local.max_message_size.min(remote.max_message_size),
+    })
+}
+
+/// Returns the item both lists share with the lowest sum of its positions in the two lists, and
+/// the lowest id among those
+fn best_common<T: Copy>(local: &[T], remote: &[T], id: impl Fn(&T) -> u8) -> Option<T> {
+    local
+        .iter()
+        .enumerate()
+        .filter_map(|(local_rank, item)| {
+            let item_id = id(item);
+            let remote_rank = remote.iter().position(|other| id(other) == item_id)?;
+            Some(((local_rank + remote_rank, item_id), *item))
+        })
+        .min_by_key(|(key, _)| *key)
+        .map(|(_, item)| item)
+}
+
+fn zstd_level(modes: &[CompressionMode]) -> Option<i32> {
+    modes.iter().find_map(|mode| match mode {
+        CompressionMode::Zstd(level) => Some(*level),
+        _ => None,
+    })
+}
+
+/// The stable identifier of a format on the wire, in the order of [`Capabilities::default`]
+fn format_id(format: SerdeFormat) -> u8 {
+    match format {
+        SerdeFormat::Bincode => 0,
+        SerdeFormat::Json => 1,
+        SerdeFormat::MsgPack => 2,
+        SerdeFormat::Cbor => 3,
+        #[cfg(feature = "postcard")]
+        SerdeFormat::Postcard => 4,
+    }
+}
+
+fn format_from_id(id: u8) -> Option<SerdeFormat> {
+    match id {
+        0 => Some(SerdeFormat::Bincode),
+        1 => Some(SerdeFormat::Json),
+        2 => Some(SerdeFormat::MsgPack),
+        3 => Some(SerdeFormat::Cbor),
+        #[cfg(feature = "postcard")]
+        4 => Some(SerdeFormat::Postcard),
+        _ => None,
+    }
+}
+
+fn compression_id(mode: CompressionMode) -> u8 {
+    mode.algorithm().id()
+}
+
+/// Encodes capabilities with fixed-width big-endian fields: the magic number, the number of
+/// formats followed by their ids, the number of compression modes followed by an id and a level
+/// each, and the message size limit
+fn encode(capabilities: &Capabilities) -> Vec<u8> {
+    let mut buf = MAGIC.to_be_bytes().to_vec();
+    let formats = &capabilities.formats[..capabilities.formats.len().min(u8::MAX as usize)];
+    buf.push(formats.len() as u8);
+    buf.extend(formats.iter().map(|format| format_id(*format)));
+
+    let modes = &capabilities.compression[..capabilities.compression.len().min(u8::MAX as usize)];
+    buf.push(modes.len() as u8);
+    for mode in modes {
+        buf.push(compression_id(*mode));
+        let level = match mode {
+            CompressionMode::Zstd(level) => *level,
+            _ => 0,
+        };
+        buf.extend_from_slice(&level.to_be_bytes());
+    }
+
+    let max_message_size = u64::try_from(capabilities.max_message_size).unwrap_or(u64::MAX);
+    buf.extend_from_slice(&max_message_size.to_be_bytes());
+    buf
+}
+
+/// Removes the next `n` bytes from the front of a buffer
+fn take<'a>(buf: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+    if buf.len() < n {
+        return None;
+    }
+    let (head, tail) = buf.split_at(n);
+    *buf = tail;
+    Some(head)
+}
+
+/// Decodes the compression algorithms sent by the peer, skipping those this build does not know
+fn decode_algorithms(mut buf: &[u8]) -> Option<Vec<CompressionAlgorithm>> {
+    if take(&mut buf, 4)? != COMPRESSION_MAGIC.to_be_bytes() {
+        return None;
+    }
+
+    let count = take(&mut buf, 1)?[0] as usize;
+    let algorithms = take(&mut buf, count)?
+        .iter()
+        .filter_map(|id| CompressionAlgorithm::from_id(*id))
+        .collect();
+    buf.is_empty().then_some(algorithms)
+}
+
+/// Decodes the capabilities sent by the peer, skipping formats and compression modes this build
+/// does not know
+fn decode(mut buf: &[u8]) -> Option<Capabilities> {
+    if take(&mut buf, 4)? != MAGIC.to_be_bytes() {
+        return None;
+    }
+
+    let count = take(&mut buf, 1)?[0] as usize;
+    let formats = take(&mut buf, count)?
+        .iter()
+        .filter_map(|id| format_from_id(*id))
+        .collect();
+
+    let count = take(&mut buf, 1)?[0] as usize;
+    let mut compression = Vec::with_capacity(count);
+    for _ in 0..count {
+        let id = take(&mut buf, 1)?[0];
+        let level = i32::from_be_bytes(take(&mut buf, 4)?.try_into().ok()?);
+        match CompressionAlgorithm::from_id(id) {
+            Some(CompressionAlgorithm::None) => compression.push(CompressionMode::None),
+            Some(CompressionAlgorithm::Zstd) => compression.push(CompressionMode::Zstd(level)),
+            Some(CompressionAlgorithm::Lz4) => compression.push(CompressionMode::Lz4),
+            Some(CompressionAlgorithm::Snappy) => compression.push(CompressionMode::Snappy),
+            None => {}
+        }
+    }
+
+    let max_message_size = u64::from_be_bytes(take(&mut buf, 8)?.try_into().ok()?);
+    if !buf.is_empty() {
+        return None;
+    }
+
+    Some(Capabilities {
+        formats,
+        compression,
+        max_message_size: usize::try_from(max_message_size).unwrap_or(usize::MAX),
+    })
+}
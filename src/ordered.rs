@@ -0,0 +1,151 @@
+use crate::{Connection, ConnectionError};
+use bytes::BufMut;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::{Error, ErrorKind};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+/// The size of the sequence number that precedes every payload
+const SEQUENCE_LEN: usize = 8;
+
+/// A connection that numbers every value it sends and checks the numbers of the values it
+/// receives, to detect messages that were lost or reordered
+///
+/// Every payload is preceded by a big-endian `u64` sequence number, starting from `0` and growing
+/// by one with each value, so both peers must use an `OrderedConnection`. By default a value with
+/// an unexpected number fails the read with [`ConnectionError::OutOfOrder`].
+/// [`OrderedConnection::allow_gap`] makes reads skip over missing numbers instead. Create one with
+/// [`Connection::into_ordered`].
+///
+/// # Examples
+///
+/// ```no_run
+/// use connection::{Connection};
+/// use std::error::Error;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn Error>> {
+///     // Connect to a peer that numbers its messages as well
+///     let mut conn = Connection::dial("127.0.0.1:8080").await?.into_ordered();
+///
+///     conn.write(&"Hello, world!").await?;
+///     let reply: Option<String> = conn.read().await?;
+///
+///     Ok(())
+/// }
+/// ```
+pub struct OrderedConnection<Io = TcpStream> {
+    inner: Connection<Io>,
+    next_send: u64,
+    next_receive: u64,
+    allow_gap: bool,
+}
+
+impl<Io: AsyncRead + AsyncWrite + Unpin> Connection<Io> {
+    /// Convert the connection into one that numbers the values it sends and checks the numbers of
+    /// the values it receives
+    pub fn into_ordered(self) -> OrderedConnection<Io> {
+        OrderedConnection {
+            inner: self,
+            next_send: 0,
+            next_receive: 0,
+            allow_gap: false,
+        }
+    }
+}
+
+impl<Io: AsyncRead + AsyncWrite + Unpin> OrderedConnection<Io> {
+    /// Choose whether reads tolerate values with unexpected sequence numbers
+    ///
+    /// When `false`, which is the default, such a value fails the read with
+    /// [`ConnectionError::OutOfOrder`] and is discarded. When `true`, a value whose number is
+    /// ahead of the expected one is logged and returned, and the values in between are
+    /// considered lost. A value whose number is behind the expected one was already superseded,
+    /// so it is logged and skipped.
+    pub fn allow_gap(mut self, allow: bool) -> Self {
+        self.allow_gap = allow;
+        self
+    }
+
+    /// Write a serializable value into the stream, preceded by the next sequence number
+    pub async fn write<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConnectionError> {
+        let payload = self.inner.record(self.inner.format().serialize(value))?;
+        let mut buf = Vec::with_capacity(SEQUENCE_LEN + payload.len());
+        buf.put_u64(self.next_send);
+        buf.extend_from_slice(&payload);
+        self.inner.write_raw(&buf).await?;
+        self.next_send += 1;
+        Ok(())
+    }
+
+    /// Reads from the socket until a complete value is received, or an error occurs
+    ///
+    /// Fails with [`ConnectionError::OutOfOrder`] if the value does not carry the expected
+    /// sequence number and gaps are not allowed.
+    pub async fn read<T: DeserializeOwned>(&mut self) -> Result<Option<T>, ConnectionError> {
+        loop {
+            let Some(payload) = self.inner.read_raw().await? else {
+                return Ok(None);
+            };
+            if payload.len() < SEQUENCE_LEN {
+                return self.inner.record(Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "frame is too short to hold a sequence number",
+                )
+                .into()));
+            }
+
+            let (sequence, value) = payload.split_at(SEQUENCE_LEN);
+            let got = u64::from_be_bytes(sequence.try_into().expect("sequence number is 8 bytes"));
+            let expected = self.next_receive;
+            if got != expected {
+                if !self.allow_gap {
+                    return self
+                        .inner
+                        .record(Err(ConnectionError::OutOfOrder { expected, got }));
+                }
+                if got < expected {
+                    warn!(expected, got, "skipped value with a stale sequence number");
+                    continue;
+                }
+                warn!(
+                    expected,
+                    got, "values were lost before this sequence number"
+                );
+            }
+
+            self.next_receive = got.wrapping_add(1);
+            let format = self.inner.format();
+            return self.inner.record(format.deserialize(value)).map(Some);
+        }
+    }
+
+    /// Returns the sequence number the next written value will carry
+    pub fn next_send_sequence(&self) -> u64 {
+        self.next_send
+    }
+
+    /// Returns the sequence number the next value read is expected to carry
+    pub fn next_receive_sequence(&self) -> u64 {
+        self.next_receive
+    }
+
+    /// Returns a reference to the underlying connection
+    pub fn get_ref(&self) -> &Connection<Io> {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying connection
+    ///
+    /// Values written or read through it bypass the sequence numbers, so the peer will not be
+    /// able to read them through an `OrderedConnection`.
+    pub fn get_mut(&mut self) -> &mut Connection<Io> {
+        &mut self.inner
+    }
+
+    /// Unwrap the underlying connection
+    pub fn into_inner(self) -> Connection<Io> {
+        self.inner
+    }
+}
@@ -0,0 +1,187 @@
+use crate::{Connection, ConnectionError};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::{watch, Mutex, MutexGuard};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// How long written values may wait for a flush unless configured otherwise
+const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_millis(5);
+
+/// How many bytes may wait for a flush unless configured otherwise (4 KiB, the default size of
+/// the write buffer)
+const DEFAULT_FLUSH_THRESHOLD: usize = 4 * 1024;
+
+/// A connection that sends values without waiting for each one to be flushed, created by
+/// [`Connection::into_pipeline`]
+///
+/// [`Pipeline::write`] only buffers the value, so many requests can be in flight before the
+/// first response is read. The first value written after a flush starts the
+/// [`Pipeline::flush_interval`], and a background task flushes every value written since once it
+/// elapses, so the last value written is sent even if nothing else happens. The buffer is also
+/// flushed once it holds [`Pipeline::flush_threshold`] bytes, before every read, and by
+/// [`Pipeline::flush`].
+///
+/// # Examples
+///
+/// ```no_run
+/// use connection::{Connection};
+/// use std::error::Error;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn Error>> {
+///     // Connect to a peer that answers every request in order
+///     let mut conn = Connection::dial("127.0.0.1:8080").await?.into_pipeline();
+///
+///     // Send every request before waiting for the first response
+///     for id in 0..100u32 {
+///         conn.write(&id).await?;
+///     }
+///     for _ in 0..100 {
+///         let response: Option<String> = conn.read().await?;
+///     }
+///
+///     Ok(())
+/// }
+/// ```
+pub struct Pipeline<Io = TcpStream> {
+    inner: Arc<Mutex<Connection<Io>>>,
+    flush_interval: Duration,
+    flush_threshold: usize,
+    /// Tells the flush task when the buffered values are due
+    flush_due: watch::Sender<Option<Instant>>,
+    flusher: Flusher,
+}
+
+/// The background task that flushes buffered values once they are due
+struct Flusher(JoinHandle<()>);
+
+impl Flusher {
+    /// Stop the task and wait until it has let go of the connection
+    async fn stop(mut self) {
+        self.0.abort();
+        let _ = (&mut self.0).await;
+    }
+}
+
+impl Drop for Flusher {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+async fn flush_when_due<Io: AsyncRead + AsyncWrite + Unpin>(
+    conn: Arc<Mutex<Connection<Io>>>,
+    mut flush_due: watch::Receiver<Option<Instant>>,
+) {
+    while flush_due.changed().await.is_ok() {
+        let Some(deadline) = *flush_due.borrow_and_update() else {
+            continue;
+        };
+        tokio::time::sleep_until(deadline).await;
+
+        // A flush or read in the meantime may have sent the values already
+        let mut conn = conn.lock().await;
+        if conn.flush_deadline().is_some_and(|d| d <= Instant::now()) {
+            // A failed flush reaches the error hook, and the next write or read runs into it again
+            let _ = conn.flush().await;
+        }
+    }
+}
+
+impl<Io: AsyncRead + AsyncWrite + Unpin + Send + 'static> Connection<Io> {
+    /// Convert the connection into one that sends values without flushing each one
+    ///
+    /// This must be called from within a Tokio runtime.
+    pub fn into_pipeline(self) -> Pipeline<Io> {
+        let inner = Arc::new(Mutex::new(self));
+        let (flush_due, flush_due_rx) = watch::channel(None);
+        let task = tokio::spawn(flush_when_due(inner.clone(), flush_due_rx));
+        Pipeline {
+            inner,
+            flush_interval: DEFAULT_FLUSH_INTERVAL,
+            flush_threshold: DEFAULT_FLUSH_THRESHOLD,
+            flush_due,
+            flusher: Flusher(task),
+        }
+    }
+}
+
+impl<Io: AsyncRead + AsyncWrite + Unpin> Pipeline<Io> {
+    /// Set how long the first value written after a flush may wait for the next one, 5 ms by
+    /// default
+    pub fn flush_interval(mut self, interval: Duration) -> Self {
+        self.flush_interval = interval;
+        self
+    }
+
+    /// Set how many buffered bytes, framing included, make a write flush the buffer, 4 KiB by
+    /// default
+    ///
+    /// A write buffer that fills up is written to the stream regardless, so thresholds above
+    /// [`Connection::write_buffer_size`] have no effect.
+    pub fn flush_threshold(mut self, bytes: usize) -> Self {
+        self.flush_threshold = bytes;
+        self
+    }
+
+    /// Write a serializable value into the buffer, flushing it if the threshold or the interval
+    /// has been reached
+    pub async fn write<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConnectionError> {
+        let mut conn = self.inner.lock().await;
+        conn.write_coalesced(value, self.flush_interval).await?;
+        if conn.unflushed() >= self.flush_threshold {
+            conn.flush().await?;
+        }
+
+        let deadline = conn.flush_deadline();
+        self.flush_due.send_if_modified(|due| {
+            let modified = *due != deadline;
+            *due = deadline;
+            modified
+        });
+        Ok(())
+    }
+
+    /// Flush the buffered values, then read from the socket until a complete value is received,
+    /// or an error occurs
+    pub async fn read<T: DeserializeOwned>(&mut self) -> Result<Option<T>, ConnectionError> {
+        let mut conn = self.inner.lock().await;
+        if conn.unflushed() > 0 {
+            conn.flush().await?;
+        }
+        conn.read().await
+    }
+
+    /// Flush the buffered values to the stream
+    pub async fn flush(&mut self) -> Result<(), ConnectionError> {
+        self.inner.lock().await.flush().await
+    }
+
+    /// Returns the number of bytes written that have not reached the stream yet
+    pub async fn unflushed(&self) -> usize {
+        self.inner.lock().await.unflushed()
+    }
+
+    /// Wait for exclusive use of the underlying connection, which the background flush shares
+    ///
+    /// Values are not flushed in the background while the returned guard is held.
+    pub async fn lock(&self) -> MutexGuard<'_, Connection<Io>> {
+        self.inner.lock().await
+    }
+
+    /// Stop flushing in the background and unwrap the underlying connection, which keeps any
+    /// values that were not flushed yet
+    pub async fn into_inner(self) -> Connection<Io> {
+        let Pipeline { inner, flusher, .. } = self;
+        flusher.stop().await;
+        match Arc::try_unwrap(inner) {
+            Ok(conn) => conn.into_inner(),
+            Err(_) => unreachable!("the flush task has let go of the connection"),
+        }
+    }
+}
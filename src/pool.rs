@@ -0,0 +1,277 @@
+use crate::{Connection, ConnectionError};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::ToSocketAddrs;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// A pool of connections to the same peer that are reused across requests
+///
+/// At most `max_size` connections are handed out at once. Idle connections are health-checked
+/// with a ping before they are reused, so the peer must be reading from its side of the
+/// connection to answer it.
+///
+/// # Examples
+///
+/// ```no_run
+/// use connection::ConnectionPool;
+/// use std::error::Error;
+/// use std::time::Duration;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn Error>> {
+///     let pool = ConnectionPool::new("127.0.0.1:8080", 2, 10, Duration::from_secs(1)).await?;
+///
+///     // The connection goes back to the pool when it is dropped
+///     let mut conn = pool.acquire().await?;
+///     conn.write(&"Hello, world!").await?;
+///
+///     Ok(())
+/// }
+/// ```
+pub struct ConnectionPool<A> {
+    inner: Arc<Pool<A>>,
+}
+
+struct Pool<A> {
+    addr: A,
+    acquire_timeout: Duration,
+    idle: Mutex<Vec<Connection>>,
+    permits: Arc<Semaphore>,
+    counters: Counters,
+}
+
+/// The counters behind [`PoolStats`], updated by every acquire and release
+#[derive(Default)]
+struct Counters {
+    active: AtomicUsize,
+    waiting: AtomicUsize,
+    acquired: AtomicU64,
+    released: AtomicU64,
+    errors: AtomicU64,
+}
+
+/// A snapshot of the health of a [`ConnectionPool`], returned by [`ConnectionPool::stats`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// The number of connections currently borrowed from the pool
+    pub active: usize,
+    /// The number of connections waiting in the pool to be acquired
+    pub idle: usize,
+    /// The number of acquires currently waiting for a connection to be returned
+    pub waiting: usize,
+    /// The number of connections handed out by [`ConnectionPool::acquire`]
+    pub total_acquired: u64,
+    /// The number of connections returned to the pool
+    pub total_released: u64,
+    /// The number of calls to [`ConnectionPool::acquire`] that failed
+    pub total_errors: u64,
+}
+
+impl fmt::Display for PoolStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "active={} idle={} waiting={} acquired={} released={} errors={}",
+            self.active,
+            self.idle,
+            self.waiting,
+            self.total_acquired,
+            self.total_released,
+            self.total_errors
+        )
+    }
+}
+
+/// Counts an acquire as waiting for as long as it is alive, even if the acquire is cancelled
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl<'a> Waiting<'a> {
+    fn new(waiting: &'a AtomicUsize) -> Self {
+        waiting.fetch_add(1, Ordering::Relaxed);
+        Waiting(waiting)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A connection borrowed from a [`ConnectionPool`], which is returned to the pool when dropped
+pub struct PooledConnection<A> {
+    conn: Option<Connection>,
+    pool: Arc<Pool<A>>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl<A: ToSocketAddrs + Clone> ConnectionPool<A> {
+    /// Create a new pool, dialing `min_idle` connections up front
+    ///
+    /// `acquire_timeout` bounds both the wait for a free connection and the health check of an
+    /// idle one.
+    pub async fn new(
+        addr: A,
+        min_idle: usize,
+        max_size: usize,
+        acquire_timeout: Duration,
+    ) -> Result<Self, ConnectionError> {
+        if max_size == 0 {
+            return Err(ConnectionError::InvalidConfiguration(
+                "max_size must be greater than zero".into(),
+            ));
+        }
+
+        if min_idle > max_size {
+            return Err(ConnectionError::InvalidConfiguration(
+                "min_idle must not exceed max_size".into(),
+            ));
+        }
+
+        let mut idle = Vec::with_capacity(max_size);
+        for _ in 0..min_idle {
+            idle.push(Connection::dial(addr.clone()).await?);
+        }
+
+        let inner = Pool {
+            addr,
+            acquire_timeout,
+            idle: Mutex::new(idle),
+            permits: Arc::new(Semaphore::new(max_size)),
+            counters: Counters::default(),
+        };
+
+        Ok(Self {
+            inner: Arc::new(inner),
+        })
+    }
+
+    /// Borrow a connection from the pool, dialing a new one if none are idle
+    ///
+    /// Fails with [`ConnectionError::PoolExhausted`] if `max_size` connections are in use and
+    /// none is returned within the pool's `acquire_timeout`.
+    pub async fn acquire(&self) -> Result<PooledConnection<A>, ConnectionError> {
+        let counters = &self.inner.counters;
+        let result = self.checkout().await;
+        match &result {
+            Ok(_) => {
+                counters.active.fetch_add(1, Ordering::Relaxed);
+                counters.acquired.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(_) => {
+                counters.errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        result
+    }
+
+    /// Waits for a free slot in the pool, then takes a healthy idle connection or dials a new one
+    async fn checkout(&self) -> Result<PooledConnection<A>, ConnectionError> {
+        let waiting = Waiting::new(&self.inner.counters.waiting);
+        let permit = self.inner.permits.clone().acquire_owned();
+        let permit = tokio::time::timeout(self.inner.acquire_timeout, permit)
+            .await
+            .map_err(|_| ConnectionError::PoolExhausted)?
+            .expect("the pool never closes its semaphore");
+        drop(waiting);
+
+        let conn = loop {
+            let idle = self.inner.idle.lock().unwrap().pop();
+            match idle {
+                Some(mut conn) => {
+                    if conn.probe(self.inner.acquire_timeout).await.is_ok() {
+                        break conn;
+                    }
+                }
+                None => break Connection::dial(self.inner.addr.clone()).await?,
+            }
+        };
+
+        Ok(PooledConnection {
+            conn: Some(conn),
+            pool: self.inner.clone(),
+            _permit: permit,
+        })
+    }
+
+    /// Returns the number of connections waiting in the pool to be acquired
+    pub fn idle_connections(&self) -> usize {
+        self.inner.idle.lock().unwrap().len()
+    }
+
+    /// Returns a snapshot of the pool's health
+    ///
+    /// Every counter is read on its own, so a snapshot taken while other tasks acquire and
+    /// release connections may be off by the operations in flight.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::ConnectionPool;
+    /// use std::error::Error;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     let pool = ConnectionPool::new("127.0.0.1:8080", 2, 10, Duration::from_secs(1)).await?;
+    ///
+    ///     // Prints something like `active=0 idle=2 waiting=0 acquired=0 released=0 errors=0`
+    ///     println!("{}", pool.stats());
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn stats(&self) -> PoolStats {
+        let counters = &self.inner.counters;
+        PoolStats {
+            active: counters.active.load(Ordering::Relaxed),
+            idle: self.idle_connections(),
+            waiting: counters.waiting.load(Ordering::Relaxed),
+            total_acquired: counters.acquired.load(Ordering::Relaxed),
+            total_released: counters.released.load(Ordering::Relaxed),
+            total_errors: counters.errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl<A> Clone for ConnectionPool<A> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<A> Deref for PooledConnection<A> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn
+            .as_ref()
+            .expect("the connection is only taken on drop")
+    }
+}
+
+impl<A> DerefMut for PooledConnection<A> {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.conn
+            .as_mut()
+            .expect("the connection is only taken on drop")
+    }
+}
+
+impl<A> Drop for PooledConnection<A> {
+    fn drop(&mut self) {
+        // The connection is back in the pool before the permit is released
+        if let Some(conn) = self.conn.take() {
+            self.pool.idle.lock().unwrap().push(conn);
+        }
+
+        let counters = &self.pool.counters;
+        counters.active.fetch_sub(1, Ordering::Relaxed);
+        counters.released.fetch_add(1, Ordering::Relaxed);
+    }
+}
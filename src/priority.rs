@@ -0,0 +1,223 @@
+use crate::{frame, Connection, ConnectionError};
+use bytes::BytesMut;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::VecDeque;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// How many received bytes [`Connection::read_prioritized`] takes in from the socket without
+/// waiting, beyond which it only reads more when nothing suitable has arrived
+const READ_AHEAD: usize = 64 * 1024;
+
+/// The priority of a value written with [`Connection::write_with_priority`], recorded in the
+/// flags of its frame
+///
+/// Priorities are ordered from `Low` to `High`. Values written any other way have `Normal`
+/// priority.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Data that may wait behind everything else, such as bulk transfers
+    Low,
+    /// The priority of every value unless another one is given
+    #[default]
+    Normal,
+    /// Messages that should overtake everything else, such as control messages
+    High,
+}
+
+impl Priority {
+    /// The bits of the frame flags recording this priority
+    pub(crate) fn flags(self) -> u8 {
+        match self {
+            Priority::Normal => 0,
+            Priority::High => 0b01_0000,
+            Priority::Low => 0b10_0000,
+        }
+    }
+
+    /// Returns the priority recorded in the frame flags, or `None` if the bits are not a known
+    /// priority
+    pub(crate) fn from_flags(flags: u8) -> Option<Self> {
+        match flags & frame::PRIORITY {
+            0 => Some(Priority::Normal),
+            0b01_0000 => Some(Priority::High),
+            0b10_0000 => Some(Priority::Low),
+            _ => None,
+        }
+    }
+}
+
+/// The frames [`Connection::read_prioritized`] took out of the read buffer without returning them,
+/// queued by priority
+#[derive(Default)]
+pub(crate) struct Skipped {
+    /// The frames of each priority from `Low` to `High`, each with its position in arrival order
+    queues: [VecDeque<(u64, BytesMut)>; 3],
+    next: u64,
+    len: usize,
+}
+
+impl Skipped {
+    fn push(&mut self, priority: Priority, frame: BytesMut) {
+        self.len += frame.len();
+        self.queues[priority as usize].push_back((self.next, frame));
+        self.next += 1;
+    }
+
+    /// Removes the earliest frame of the highest priority of at least `min`
+    fn pop(&mut self, min: Priority) -> Option<BytesMut> {
+        let queue = self.queues[min as usize..]
+            .iter_mut()
+            .rev()
+            .find(|queue| !queue.is_empty())?;
+        let (_, frame) = queue.pop_front()?;
+        self.len -= frame.len();
+        Some(frame)
+    }
+
+    /// Returns the number of bytes queued
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Puts every queued frame back in front of the buffer in the order they arrived, for reads
+    /// that take frames from the front
+    pub(crate) fn restore(&mut self, buffer: &mut BytesMut) {
+        if self.len == 0 {
+            return;
+        }
+
+        let mut restored = BytesMut::with_capacity(self.len + buffer.len());
+        loop {
+            let earliest = self
+                .queues
+                .iter_mut()
+                .filter(|queue| !queue.is_empty())
+                .min_by_key(|queue| queue[0].0);
+            let Some((_, frame)) = earliest.and_then(VecDeque::pop_front) else {
+                break;
+            };
+            restored.extend_from_slice(&frame);
+        }
+        restored.extend_from_slice(buffer);
+        *buffer = restored;
+        self.len = 0;
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.queues.iter_mut().for_each(VecDeque::clear);
+        self.len = 0;
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    /// Write a serializable value into the stream with the given priority
+    ///
+    /// This behaves like [`Connection::write`], except that the priority is recorded in the frame,
+    /// so a [`Connection::read_prioritized`] on the other side may read it ahead of values that
+    /// arrived earlier. A [`Connection::read`] receives it in order like any other value.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection, Priority};
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // Send bulk data, then a cancellation that should not wait behind it
+    ///     conn.write_with_priority(&vec![0u8; 1024], Priority::Low).await?;
+    ///     conn.write_with_priority(&"cancel", Priority::High).await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn write_with_priority<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+        priority: Priority,
+    ) -> Result<(), ConnectionError> {
+        let buf = self.record(self.format.serialize(value))?;
+        if let Err(e) = self.write_frame(&buf, priority.flags()).await {
+            debug!(type_name = std::any::type_name::<T>(), error = %e, "write failed");
+            return Err(e);
+        }
+
+        trace!(
+            type_name = std::any::type_name::<T>(),
+            bytes_written = buf.len(),
+            ?priority,
+            "wrote prioritized value"
+        );
+        Ok(())
+    }
+
+    /// Reads the buffered value with the highest priority of at least `min_priority`, or waits
+    /// for one to arrive
+    ///
+    /// Whatever the socket already has is taken in first, up to 64 KiB, and the value with the
+    /// highest priority is returned, the earliest one among equals. Values below `min_priority`
+    /// are skipped but stay queued, to be returned by later reads in their original order. Since
+    /// they keep occupying memory until then, they should not be left behind indefinitely.
+    /// Returns `None` once the peer closes the connection, even if lower priority values remain.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection, Priority};
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // Handle control messages first, whatever data arrived before them
+    ///     if let Some(command) = conn.read_prioritized::<String>(Priority::High).await? {
+    ///         println!("command: {}", command);
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn read_prioritized<T: DeserializeOwned>(
+        &mut self,
+        min_priority: Priority,
+    ) -> Result<Option<T>, ConnectionError> {
+        loop {
+            while self.buffer.len() < READ_AHEAD && self.read_ready() {}
+
+            // Every complete frame is parsed once, then waits in the queue of its priority
+            loop {
+                if let Some(control) = frame::take_control(&mut self.buffer) {
+                    self.handle_control(control).await?;
+                    continue;
+                }
+                let result = frame::take_prioritized(&mut self.buffer, self.max_message_size);
+                match self.record(result)? {
+                    Some((priority, frame)) => self.skipped.push(priority, frame),
+                    None => break,
+                }
+            }
+
+            if let Some(mut frame) = self.skipped.pop(min_priority) {
+                self.stats.record_received_message();
+                let result = frame::parse_value(
+                    &mut frame,
+                    self.format,
+                    self.max_message_size,
+                    self.hmac_key.as_ref(),
+                    &self.middleware,
+                );
+                return self.record(result);
+            }
+
+            if 0 == self.read_to_buffer().await? {
+                return Ok(None);
+            }
+        }
+    }
+}
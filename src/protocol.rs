@@ -0,0 +1,133 @@
+use crate::{Connection, ConnectionError};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+/// A pair of message types exchanged by a client and a server
+///
+/// Implement it for a marker type to name a protocol once, instead of repeating its message
+/// types wherever a connection is used. A [`ProtocolConnection`] only sends the protocol's
+/// requests and only receives its responses, so the compiler rejects code that mixes them up.
+/// The peer answering the requests can use
+/// [`Connection::into_typed::<P::Response, P::Request>`](Connection::into_typed).
+///
+/// # Examples
+///
+/// ```
+/// use connection::Protocol;
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct Lookup { key: String }
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct Found { value: Option<String> }
+///
+/// struct KeyValue;
+///
+/// impl Protocol for KeyValue {
+///     type Request = Lookup;
+///     type Response = Found;
+/// }
+/// ```
+pub trait Protocol {
+    /// The type of the messages the client sends
+    type Request: Serialize + DeserializeOwned;
+    /// The type of the messages the server answers with
+    type Response: Serialize + DeserializeOwned;
+}
+
+/// A connection that only sends the requests of a [`Protocol`] and only receives its responses
+///
+/// Create one with [`Connection::into_protocol`].
+///
+/// # Examples
+///
+/// ```no_run
+/// use connection::{Connection, Protocol};
+/// use std::error::Error;
+///
+/// struct Length;
+///
+/// impl Protocol for Length {
+///     type Request = String;
+///     type Response = usize;
+/// }
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn Error>> {
+///     // Connect to a peer that answers every string with its length
+///     let mut conn = Connection::dial("127.0.0.1:8080").await?.into_protocol::<Length>();
+///
+///     conn.send(&"Hello, world!".to_string()).await?;
+///     let len = conn.recv().await?;
+///
+///     Ok(())
+/// }
+/// ```
+///
+/// Sending a response as a request does not compile:
+///
+/// ```compile_fail
+/// use connection::{Connection, Protocol};
+///
+/// struct Length;
+///
+/// impl Protocol for Length {
+///     type Request = String;
+///     type Response = usize;
+/// }
+///
+/// async fn send_response(conn: Connection) {
+///     let mut conn = conn.into_protocol::<Length>();
+///     conn.send(&42usize).await;
+/// }
+/// ```
+pub struct ProtocolConnection<P, Io = TcpStream> {
+    inner: Connection<Io>,
+    _marker: PhantomData<fn() -> P>,
+}
+
+impl<Io: AsyncRead + AsyncWrite + Unpin> Connection<Io> {
+    /// Convert the connection into one that only sends `P::Request` values and only receives
+    /// `P::Response` values
+    pub fn into_protocol<P: Protocol>(self) -> ProtocolConnection<P, Io> {
+        ProtocolConnection {
+            inner: self,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<P, Io> ProtocolConnection<P, Io>
+where
+    P: Protocol,
+    Io: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Write a request into the stream
+    pub async fn send(&mut self, request: &P::Request) -> Result<(), ConnectionError> {
+        self.inner.write(request).await
+    }
+
+    /// Reads from the socket until a complete response is received, or an error occurs
+    pub async fn recv(&mut self) -> Result<Option<P::Response>, ConnectionError> {
+        self.inner.read().await
+    }
+
+    /// Returns a reference to the underlying connection
+    pub fn get_ref(&self) -> &Connection<Io> {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying connection
+    pub fn get_mut(&mut self) -> &mut Connection<Io> {
+        &mut self.inner
+    }
+
+    /// Unwrap the underlying untyped connection
+    pub fn into_inner(self) -> Connection<Io> {
+        self.inner
+    }
+}
@@ -0,0 +1,91 @@
+use crate::{Connection, ConnectionError};
+use governor::{DefaultDirectRateLimiter, Quota, RateLimiter};
+use serde::Serialize;
+use std::num::NonZeroU32;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+/// A connection that writes at most a given number of messages per second
+///
+/// Every write waits for a permit from a [`governor`] rate limiter before it is handed to the
+/// connection, so a client can stay below a server's rate limit instead of being disconnected
+/// by it. Writes are spaced evenly rather than sent in bursts, so no window of one second ever
+/// holds more than the allowed number of messages, even after the connection has been quiet.
+/// Reads are not limited, and go through [`RateLimitedConnection::get_mut`].
+///
+/// This type is only available with the `rate-limit` feature enabled.
+///
+/// # Examples
+///
+/// ```no_run
+/// use connection::{Connection, RateLimitedConnection};
+/// use std::error::Error;
+/// use std::num::NonZeroU32;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn Error>> {
+///     // Connect to a peer that accepts at most 100 messages per second
+///     let conn = Connection::dial("127.0.0.1:8080").await?;
+///     let mut conn = RateLimitedConnection::new(conn, NonZeroU32::new(100).unwrap());
+///
+///     // Waits whenever the limit is reached
+///     for i in 0..1000u32 {
+///         conn.write(&i).await?;
+///     }
+///
+///     Ok(())
+/// }
+/// ```
+pub struct RateLimitedConnection<S = TcpStream> {
+    inner: Connection<S>,
+    limiter: DefaultDirectRateLimiter,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> RateLimitedConnection<S> {
+    /// Wrap a connection so that it writes at most `rate` messages per second
+    pub fn new(conn: Connection<S>, rate: NonZeroU32) -> Self {
+        let quota = Quota::per_second(rate).allow_burst(NonZeroU32::MIN);
+        RateLimitedConnection {
+            inner: conn,
+            limiter: RateLimiter::direct(quota),
+        }
+    }
+
+    /// Write a serializable value into the stream once the rate limit allows it
+    pub async fn write<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConnectionError> {
+        self.limiter.until_ready().await;
+        self.inner.write(value).await
+    }
+
+    /// Write a slice of serializable values into the stream
+    ///
+    /// Every value counts against the rate limit on its own, and is flushed as soon as its permit
+    /// is granted so that the batch is spaced out like separate writes rather than arriving in a
+    /// burst. Returns the number of values written.
+    pub async fn write_batch<T: Serialize>(
+        &mut self,
+        values: &[T],
+    ) -> Result<usize, ConnectionError> {
+        for value in values {
+            self.write(value).await?;
+        }
+        Ok(values.len())
+    }
+
+    /// Returns a reference to the underlying connection
+    pub fn get_ref(&self) -> &Connection<S> {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying connection
+    ///
+    /// Values written through it are not counted against the rate limit.
+    pub fn get_mut(&mut self) -> &mut Connection<S> {
+        &mut self.inner
+    }
+
+    /// Unwrap the underlying connection, which is no longer rate limited
+    pub fn into_inner(self) -> Connection<S> {
+        self.inner
+    }
+}
@@ -0,0 +1,188 @@
+use crate::{Connection, ConnectionError};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+use tokio::net::ToSocketAddrs;
+
+/// How long a [`ReconnectingConnection`] waits before each attempt to re-dial its peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackoffStrategy {
+    /// Wait the same amount of time before every attempt
+    Fixed(Duration),
+    /// Double the delay after every attempt, starting at `base` and never exceeding `max`
+    Exponential {
+        /// The delay before the first attempt
+        base: Duration,
+        /// The upper bound on the delay
+        max: Duration,
+    },
+    /// Like [`BackoffStrategy::Exponential`], but wait a random duration between zero and the
+    /// exponential delay so that many clients don't reconnect in lockstep
+    ExponentialWithJitter {
+        /// The delay before the first attempt
+        base: Duration,
+        /// The upper bound on the delay
+        max: Duration,
+    },
+}
+
+impl BackoffStrategy {
+    /// Returns the delay before the given attempt, where the first attempt is `0`
+    pub fn delay(&self, attempt: u32) -> Duration {
+        match *self {
+            BackoffStrategy::Fixed(delay) => delay,
+            BackoffStrategy::Exponential { base, max } => exponential(base, max, attempt),
+            BackoffStrategy::ExponentialWithJitter { base, max } => {
+                let delay = exponential(base, max, attempt);
+                let random = RandomState::new().build_hasher().finish();
+                delay.mul_f64(random as f64 / u64::MAX as f64)
+            }
+        }
+    }
+}
+
+fn exponential(base: Duration, max: Duration, attempt: u32) -> Duration {
+    let factor = 2u32.checked_pow(attempt).unwrap_or(u32::MAX);
+    base.checked_mul(factor).unwrap_or(max).min(max)
+}
+
+/// A connection that transparently re-dials its peer when the link is lost
+///
+/// When a read or write fails with [`ConnectionError::ConnectionReset`],
+/// [`ConnectionError::UnexpectedEof`] or a
+/// [fatal](ConnectionError::is_fatal) [`ConnectionError::IoError`] or
+/// [`ConnectionError::IoErrorContext`], or a read finds that the peer closed the connection, the
+/// connection is re-established according to its [`BackoffStrategy`] and the operation is retried,
+/// up to `max_attempts` times per operation. A read that runs out of attempts because the peer
+/// keeps closing the connection returns `Ok(None)`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use connection::{BackoffStrategy, ReconnectingConnection};
+/// use std::error::Error;
+/// use std::time::Duration;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn Error>> {
+///     let strategy = BackoffStrategy::Exponential {
+///         base: Duration::from_millis(100),
+///         max: Duration::from_secs(5),
+///     };
+///     let mut conn = ReconnectingConnection::new("127.0.0.1:8080", strategy, 10);
+///     conn.on_reconnect(|attempt| println!("reconnected after {} attempts", attempt));
+///
+///     // Send a message, reconnecting if the server restarted
+///     conn.write(&"Hello, world!").await?;
+///
+///     Ok(())
+/// }
+/// ```
+pub struct ReconnectingConnection<A> {
+    addr: A,
+    strategy: BackoffStrategy,
+    max_attempts: u32,
+    conn: Option<Connection>,
+    callbacks: Vec<Box<dyn Fn(u32) + Send + Sync>>,
+}
+
+impl<A: ToSocketAddrs + Clone> ReconnectingConnection<A> {
+    /// Create a new reconnecting connection; the peer is dialed lazily on the first operation
+    pub fn new(addr: A, strategy: BackoffStrategy, max_attempts: u32) -> Self {
+        Self {
+            addr,
+            strategy,
+            max_attempts,
+            conn: None,
+            callbacks: Vec::new(),
+        }
+    }
+
+    /// Register a callback invoked with the number of attempts it took each time the connection
+    /// is re-established
+    pub fn on_reconnect<F: Fn(u32) + Send + Sync + 'static>(&mut self, callback: F) {
+        self.callbacks.push(Box::new(callback));
+    }
+
+    /// Returns `true` if there is currently an established connection
+    pub fn is_connected(&self) -> bool {
+        self.conn.is_some()
+    }
+
+    /// Write a serializable value into the stream, reconnecting if necessary
+    pub async fn write<T: Serialize>(&mut self, value: &T) -> Result<(), ConnectionError> {
+        let mut attempt = 0;
+        loop {
+            let result = match self.connect(attempt).await {
+                Ok(conn) => conn.write(value).await,
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Err(e) if is_disconnect(&e) && attempt < self.max_attempts => {
+                    self.conn = None;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Reads from the socket until a complete message is received, reconnecting if necessary
+    pub async fn read<T: DeserializeOwned>(&mut self) -> Result<Option<T>, ConnectionError> {
+        let mut attempt = 0;
+        loop {
+            let result = match self.connect(attempt).await {
+                Ok(conn) => conn.read().await,
+                Err(e) => Err(e),
+            };
+
+            match result {
+                // A peer that closed the connection cleanly is gone all the same
+                Ok(None) if attempt < self.max_attempts => {
+                    self.conn = None;
+                    attempt += 1;
+                }
+                Err(e) if is_disconnect(&e) && attempt < self.max_attempts => {
+                    self.conn = None;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Returns the current connection, dialing the peer first if there isn't one
+    async fn connect(&mut self, attempt: u32) -> Result<&mut Connection, ConnectionError> {
+        if self.conn.is_none() {
+            if attempt > 0 {
+                tokio::time::sleep(self.strategy.delay(attempt - 1)).await;
+            }
+
+            self.conn = Some(Connection::dial(self.addr.clone()).await?);
+
+            if attempt > 0 {
+                for callback in &self.callbacks {
+                    callback(attempt);
+                }
+            }
+        }
+
+        Ok(self.conn.as_mut().expect("connection was just established"))
+    }
+}
+
+/// Returns `true` if the error means the link to the peer was lost
+fn is_disconnect(e: &ConnectionError) -> bool {
+    matches!(
+        e,
+        ConnectionError::Disconnected
+            | ConnectionError::ConnectionReset(_)
+            | ConnectionError::UnexpectedEof
+            | ConnectionError::WriteTimeout
+            | ConnectionError::IoError(_)
+            | ConnectionError::IoErrorContext(..)
+    ) && e.is_fatal()
+}
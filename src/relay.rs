@@ -0,0 +1,44 @@
+use crate::{Connection, ConnectionError};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Forward every frame received on `src` to `dst` without deserializing it, until `src` is closed
+///
+/// Returns the total number of payload bytes relayed. Payloads are forwarded verbatim, so both
+/// peers must agree on a serialization format, but each side applies its own
+/// [`CompressionMode`](crate::CompressionMode). Any error on either connection stops the relay
+/// and is returned immediately.
+///
+/// # Examples
+///
+/// ```no_run
+/// use connection::{relay, Connection};
+/// use std::error::Error;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn Error>> {
+///     // Connect to both ends
+///     let mut client = Connection::dial("127.0.0.1:8080").await?;
+///     let mut upstream = Connection::dial("127.0.0.1:8081").await?;
+///
+///     // Forward everything the client sends upstream
+///     let relayed = relay(&mut client, &mut upstream).await?;
+///     println!("relayed {relayed} bytes");
+///
+///     Ok(())
+/// }
+/// ```
+pub async fn relay<S, D>(
+    src: &mut Connection<S>,
+    dst: &mut Connection<D>,
+) -> Result<u64, ConnectionError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    D: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut relayed = 0;
+    while let Some(payload) = src.read_raw().await? {
+        dst.write_raw(&payload).await?;
+        relayed += payload.len() as u64;
+    }
+    Ok(relayed)
+}
@@ -0,0 +1,140 @@
+use crate::{with_timeout, Connection, ConnectionError};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+/// The state of a [`RequestResponse`] that may send its next request
+pub struct ReadyToSend;
+
+/// The state of a [`RequestResponse`] that must receive a response before sending again
+pub struct AwaitingResponse;
+
+/// A connection that strictly alternates between sending a request and receiving its response
+///
+/// Each call consumes the connection and returns it in its next state, so sending twice in a row
+/// is caught at compile time. Create one with [`Connection::into_request_response`].
+///
+/// # Examples
+///
+/// ```no_run
+/// use connection::{Connection};
+/// use std::error::Error;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn Error>> {
+///     // Connect to a peer that answers every request with its length
+///     let conn = Connection::dial("127.0.0.1:8080").await?.into_request_response();
+///
+///     let conn = conn.send(&"Hello, world!").await?;
+///     let (len, conn) = conn.recv::<usize>().await?;
+///
+///     Ok(())
+/// }
+/// ```
+///
+/// Sending twice without receiving in between does not compile:
+///
+/// ```compile_fail
+/// use connection::Connection;
+///
+/// async fn send_twice(conn: Connection) {
+///     let conn = conn.into_request_response();
+///     let conn = conn.send(&1u32).await.unwrap();
+///     conn.send(&2u32).await;
+/// }
+/// ```
+pub struct RequestResponse<State, Io = TcpStream> {
+    inner: Connection<Io>,
+    _state: PhantomData<State>,
+}
+
+impl<Io: AsyncRead + AsyncWrite + Unpin> Connection<Io> {
+    /// Write a request and wait for exactly one response, failing with
+    /// [`ConnectionError::Timeout`] if it does not arrive within `timeout`
+    ///
+    /// The timeout covers the response only. If the peer closes the connection instead of
+    /// responding, this fails with [`ConnectionError::Disconnected`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer that answers every request with its length
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     let timeout = Some(Duration::from_secs(1));
+    ///     let len: usize = conn.request(&"Hello, world!", timeout).await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn request<Req: Serialize, Resp: DeserializeOwned>(
+        &mut self,
+        req: &Req,
+        timeout: Option<Duration>,
+    ) -> Result<Resp, ConnectionError> {
+        self.write(req).await?;
+        with_timeout(timeout, self.read_response()).await
+    }
+
+    /// Convert the connection into one that enforces alternating requests and responses
+    pub fn into_request_response(self) -> RequestResponse<ReadyToSend, Io> {
+        RequestResponse {
+            inner: self,
+            _state: PhantomData,
+        }
+    }
+
+    /// Reads the response to a request, treating a closed connection as an error
+    async fn read_response<Resp: DeserializeOwned>(&mut self) -> Result<Resp, ConnectionError> {
+        self.read().await?.ok_or(ConnectionError::Disconnected)
+    }
+}
+
+impl<Io: AsyncRead + AsyncWrite + Unpin> RequestResponse<ReadyToSend, Io> {
+    /// Write a request, after which the connection must receive its response
+    pub async fn send<Req: Serialize>(
+        mut self,
+        req: &Req,
+    ) -> Result<RequestResponse<AwaitingResponse, Io>, ConnectionError> {
+        self.inner.write(req).await?;
+        Ok(RequestResponse {
+            inner: self.inner,
+            _state: PhantomData,
+        })
+    }
+}
+
+impl<Io: AsyncRead + AsyncWrite + Unpin> RequestResponse<AwaitingResponse, Io> {
+    /// Read the response to the last request, after which the connection may send again
+    pub async fn recv<Resp: DeserializeOwned>(
+        mut self,
+    ) -> Result<(Resp, RequestResponse<ReadyToSend, Io>), ConnectionError> {
+        let response = self.inner.read_response().await?;
+        let conn = RequestResponse {
+            inner: self.inner,
+            _state: PhantomData,
+        };
+        Ok((response, conn))
+    }
+}
+
+impl<State, Io> RequestResponse<State, Io> {
+    /// Returns a reference to the underlying connection
+    pub fn get_ref(&self) -> &Connection<Io> {
+        &self.inner
+    }
+
+    /// Unwrap the underlying connection
+    pub fn into_inner(self) -> Connection<Io> {
+        self.inner
+    }
+}
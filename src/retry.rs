@@ -0,0 +1,113 @@
+use crate::{Connection, ConnectionError};
+use std::time::Duration;
+use tokio::net::ToSocketAddrs;
+
+/// How many times [`Connection::dial_retry`] dials its peer and how long it waits in between
+///
+/// The delay before the first retry is `initial_delay`, and each later delay is the previous one
+/// times `multiplier`, never exceeding `max_delay`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// The total number of attempts, including the first one
+    pub max_attempts: u32,
+    /// The delay before the second attempt
+    pub initial_delay: Duration,
+    /// The upper bound on the delay between attempts
+    pub max_delay: Duration,
+    /// The factor applied to the delay after every attempt
+    pub multiplier: f64,
+}
+
+impl RetryPolicy {
+    /// Returns the delay after the given failed attempt, where the first attempt is `0`
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.powi(attempt.min(i32::MAX as u32) as i32);
+        let delay = self.initial_delay.as_secs_f64() * factor;
+        Duration::try_from_secs_f64(delay)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay)
+    }
+
+    fn validate(&self) -> Result<(), ConnectionError> {
+        if self.max_attempts == 0 {
+            return Err(ConnectionError::InvalidConfiguration(
+                "a retry policy must allow at least one attempt".into(),
+            ));
+        }
+        if !self.multiplier.is_finite() || self.multiplier < 0.0 {
+            return Err(ConnectionError::InvalidConfiguration(format!(
+                "retry multiplier must be a finite, non-negative number, got {}",
+                self.multiplier
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Five attempts, starting with a delay of 100 milliseconds that doubles up to five seconds
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(5),
+            multiplier: 2.0,
+        }
+    }
+}
+
+impl Connection {
+    /// Connect to a socket address, retrying with a growing delay until the peer accepts the
+    /// connection or the [`RetryPolicy`] runs out of attempts
+    ///
+    /// This lets a client start before the server it talks to is listening. Once every attempt
+    /// has failed, the error of the last one is returned wrapped in
+    /// [`ConnectionError::MaxRetriesExceeded`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection, RetryPolicy};
+    /// use std::error::Error;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Wait up to about a minute for the server to come up
+    ///     let policy = RetryPolicy {
+    ///         max_attempts: 20,
+    ///         initial_delay: Duration::from_millis(100),
+    ///         max_delay: Duration::from_secs(5),
+    ///         multiplier: 1.5,
+    ///     };
+    ///     let mut conn = Connection::dial_retry("127.0.0.1:8080", policy).await?;
+    ///
+    ///     // Send a message
+    ///     conn.write(&"Hello, world!").await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn dial_retry<A: ToSocketAddrs + Clone>(
+        addr: A,
+        policy: RetryPolicy,
+    ) -> Result<Connection, ConnectionError> {
+        policy.validate()?;
+
+        let mut attempt = 0;
+        loop {
+            let e = match Connection::dial(addr.clone()).await {
+                Ok(conn) => return Ok(conn),
+                Err(e) => e,
+            };
+
+            if attempt + 1 >= policy.max_attempts {
+                return Err(ConnectionError::MaxRetriesExceeded(Box::new(e)));
+            }
+            let delay = policy.delay(attempt);
+            debug!(attempt, error = %e, ?delay, "dial failed, retrying");
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
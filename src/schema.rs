@@ -0,0 +1,119 @@
+use crate::{frame, Connection, ConnectionError};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    /// Write a serializable value into the stream, recording the version of its schema in the
+    /// frame
+    ///
+    /// This behaves like [`Connection::write`], except that `version` travels in the flags of the
+    /// frame, so a [`Connection::read_with_schema_version`] on the other side can tell which
+    /// version of a type the value was serialized from and migrate it. A [`Connection::read`]
+    /// receives it like any other value. Values written any other way have version `0`.
+    ///
+    /// Fails with [`ConnectionError::InvalidConfiguration`] if `version` is greater than `7`,
+    /// since the flags only have room for three bits.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use serde::Serialize;
+    /// use std::error::Error;
+    ///
+    /// #[derive(Serialize)]
+    /// struct User { name: String, email: Option<String> }
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // The second version of `User` added the email address
+    ///     let user = User { name: "ferris".into(), email: None };
+    ///     conn.write_versioned(&user, 2).await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn write_versioned<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+        version: u8,
+    ) -> Result<(), ConnectionError> {
+        if version > frame::MAX_SCHEMA_VERSION {
+            return self.record(Err(ConnectionError::InvalidConfiguration(format!(
+                "schema version {} is greater than {}",
+                version,
+                frame::MAX_SCHEMA_VERSION
+            ))));
+        }
+
+        let buf = self.record(self.format.serialize(value))?;
+        let flags = frame::schema_version_flags(version);
+        if let Err(e) = self.write_frame(&buf, flags).await {
+            debug!(type_name = std::any::type_name::<T>(), error = %e, "write failed");
+            return Err(e);
+        }
+
+        trace!(
+            type_name = std::any::type_name::<T>(),
+            bytes_written = buf.len(),
+            version,
+            "wrote versioned value"
+        );
+        Ok(())
+    }
+
+    /// Reads from the socket until a complete value is received, returning it along with the
+    /// schema version it was written with
+    ///
+    /// Values written with [`Connection::write_versioned`] carry the version they were given, and
+    /// values written any other way have version `0`. Since every version is deserialized as a
+    /// `T`, `T` must be able to hold all of them, for example by making fields added in later
+    /// versions optional, or by being an enum of the versions.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use serde::Deserialize;
+    /// use std::error::Error;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct User { name: String, #[serde(default)] email: Option<String> }
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = Connection::dial("127.0.0.1:8080").await?;
+    ///
+    ///     // Older peers never send an email address
+    ///     while let Some((user, version)) = conn.read_with_schema_version::<User>().await? {
+    ///         let email = if version < 2 { None } else { user.email };
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn read_with_schema_version<T: DeserializeOwned>(
+        &mut self,
+    ) -> Result<Option<(T, u8)>, ConnectionError> {
+        loop {
+            self.answer_controls().await?;
+            let flags = frame::peek_flags(&self.buffer, self.max_message_size);
+            if let Some(flags) = self.record(flags)? {
+                let value = self
+                    .parse_buffered()
+                    .await?
+                    .expect("a complete frame is buffered");
+                return Ok(Some((value, frame::schema_version(flags))));
+            }
+
+            if 0 == self.read_to_buffer().await? {
+                return Ok(None);
+            }
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use crate::{Connection, ConnectionError};
+use futures::Stream;
+use std::future::Future;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::net::{TcpListener, TcpSocket, TcpStream, ToSocketAddrs};
+use tokio::sync::Semaphore;
+
+/// The number of pending connections a listener created by the server queues, like
+/// [`TcpListener::bind`]
+const LISTEN_BACKLOG: u32 = 1024;
+
+/// A TCP server that accepts incoming connections
+///
+/// # Examples
+///
+/// ```no_run
+/// use connection::{Connection, Server};
+/// use std::error::Error;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn Error>> {
+///     // Echo every message back to the client that sent it
+///     let server = Server::bind("127.0.0.1:8080").await?.max_connections(100);
+///     server
+///         .serve(|mut conn: Connection| async move {
+///             while let Ok(Some(message)) = conn.read::<String>().await {
+///                 if conn.write(&message).await.is_err() {
+///                     break;
+///                 }
+///             }
+///         })
+///         .await?;
+///
+///     Ok(())
+/// }
+/// ```
+pub struct Server {
+    listeners: Vec<TcpListener>,
+    /// The listener polled first by the next accept, so a busy listener cannot starve the others
+    next: usize,
+    max_connections: Option<usize>,
+}
+
+impl Server {
+    /// Bind a new server to a socket address
+    pub async fn bind<A: ToSocketAddrs>(addr: A) -> Result<Server, ConnectionError> {
+        let listener = TcpListener::bind(addr).await?;
+        Ok(Server::from_listener(listener))
+    }
+
+    /// Bind a new server to an IPv6 address, accepting IPv6 connections only
+    ///
+    /// Whether a plain [`Server::bind`] to an IPv6 address also accepts IPv4 connections depends
+    /// on the operating system, while this always sets `IPV6_V6ONLY`.
+    pub async fn bind_v6(addr: SocketAddrV6) -> Result<Server, ConnectionError> {
+        Ok(Server::from_listener(bind_v6_listener(addr, true)?))
+    }
+
+    /// Bind a new server to every address of the host on the given port, accepting both IPv4 and
+    /// IPv6 connections on a single IPv6 socket
+    ///
+    /// The socket is bound to `[::]:port` with `IPV6_V6ONLY` cleared, so IPv4 peers appear with
+    /// IPv4-mapped IPv6 addresses such as `::ffff:127.0.0.1`. Fails on systems without IPv6 or
+    /// that do not support dual-stack sockets, where [`Server::bind_all`] can be used instead.
+    pub async fn bind_dual_stack(port: u16) -> Result<Server, ConnectionError> {
+        let addr = SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, port, 0, 0);
+        Ok(Server::from_listener(bind_v6_listener(addr, false)?))
+    }
+
+    /// Bind a new server to every address of the host on the given port with two listeners, one
+    /// on `0.0.0.0:port` and one on `[::]:port`
+    ///
+    /// Connections from both listeners are accepted by [`Server::accept`],
+    /// [`Server::accept_stream`] and [`Server::serve`]. When `port` is `0`, the IPv6 listener
+    /// is bound to the port the operating system picked for the IPv4 one, see
+    /// [`Server::local_addrs`].
+    pub async fn bind_all(port: u16) -> Result<Server, ConnectionError> {
+        let v4 = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port)).await?;
+        let port = v4.local_addr()?.port();
+        let v6 = bind_v6_listener(SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, port, 0, 0), true)?;
+        Ok(Server {
+            listeners: vec![v4, v6],
+            next: 0,
+            max_connections: None,
+        })
+    }
+
+    /// Create a new server from an existing listener
+    pub fn from_listener(listener: TcpListener) -> Server {
+        Server {
+            listeners: vec![listener],
+            next: 0,
+            max_connections: None,
+        }
+    }
+
+    /// Limit the number of connections [`Server::serve`] handles at once
+    ///
+    /// Connections beyond the limit wait in the listener's backlog until a handler finishes.
+    pub fn max_connections(mut self, limit: usize) -> Self {
+        self.max_connections = Some(limit);
+        self
+    }
+
+    /// Returns the local socket address the server is bound to
+    ///
+    /// A server created by [`Server::bind_all`] returns the address of its IPv4 listener.
+    pub fn local_addr(&self) -> Result<SocketAddr, ConnectionError> {
+        Ok(self.listeners[0].local_addr()?)
+    }
+
+    /// Returns the local socket addresses of every listener of the server
+    pub fn local_addrs(&self) -> Result<Vec<SocketAddr>, ConnectionError> {
+        self.listeners
+            .iter()
+            .map(|listener| Ok(listener.local_addr()?))
+            .collect()
+    }
+
+    /// Accept a new incoming connection
+    pub async fn accept(&mut self) -> Result<Connection, ConnectionError> {
+        let (stream, _) = std::future::poll_fn(|cx| self.poll_accept(cx)).await?;
+        Ok(Connection::new(stream))
+    }
+
+    /// Polls every listener for a connection, starting after the one that accepted last
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<(TcpStream, SocketAddr)>> {
+        let count = self.listeners.len();
+        for offset in 0..count {
+            let index = (self.next + offset) % count;
+            if let Poll::Ready(result) = self.listeners[index].poll_accept(cx) {
+                self.next = (index + 1) % count;
+                return Poll::Ready(result);
+            }
+        }
+        Poll::Pending
+    }
+
+    /// Returns a stream that yields every incoming connection as it is accepted
+    ///
+    /// The stream never ends on its own: a failure to accept a connection is yielded as an error,
+    /// and the stream keeps accepting afterwards. It borrows the server, so the server cannot be
+    /// dropped while the stream is alive; dropping the stream stops accepting and hands the
+    /// server back. Use combinators such as [`StreamExt::take`](futures::StreamExt::take) to
+    /// accept a bounded number of connections.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::Server;
+    /// use futures::StreamExt;
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     let mut server = Server::bind("127.0.0.1:8080").await?;
+    ///
+    ///     // Greet the first ten clients
+    ///     let mut incoming = server.accept_stream().take(10);
+    ///     while let Some(conn) = incoming.next().await {
+    ///         conn?.write(&"Hello, world!").await?;
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn accept_stream(
+        &mut self,
+    ) -> impl Stream<Item = Result<Connection, ConnectionError>> + '_ {
+        futures::stream::poll_fn(move |cx| {
+            self.poll_accept(cx).map(|result| {
+                Some(
+                    result
+                        .map(|(stream, _)| Connection::new(stream))
+                        .map_err(ConnectionError::from),
+                )
+            })
+        })
+    }
+
+    /// Accept connections forever, running the handler on a new task for each one
+    ///
+    /// This only returns if accepting a connection fails.
+    pub async fn serve<F, Fut>(mut self, handler: F) -> Result<(), ConnectionError>
+    where
+        F: Fn(Connection) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let limit = self.max_connections.map(|n| Arc::new(Semaphore::new(n)));
+
+        loop {
+            let permit = match &limit {
+                Some(limit) => Some(
+                    limit
+                        .clone()
+                        .acquire_owned()
+                        .await
+                        .expect("semaphore is never closed"),
+                ),
+                None => None,
+            };
+
+            let conn = self.accept().await?;
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                handler(conn).await;
+                drop(permit);
+            });
+        }
+    }
+}
+
+/// Binds a listener to an IPv6 address, which also accepts IPv4 connections unless `only_v6`
+fn bind_v6_listener(addr: SocketAddrV6, only_v6: bool) -> Result<TcpListener, ConnectionError> {
+    let socket = TcpSocket::new_v6()?;
+    socket2::SockRef::from(&socket).set_only_v6(only_v6)?;
+    // Matches `TcpListener::bind`, which allows rebinding a port left in TIME_WAIT
+    #[cfg(unix)]
+    socket.set_reuseaddr(true)?;
+    socket.bind(addr.into())?;
+    Ok(socket.listen(LISTEN_BACKLOG)?)
+}
@@ -0,0 +1,106 @@
+use crate::{Connection, ConnectionError};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::{Mutex, MutexGuard};
+
+/// A connection that many tasks can write to at once, created by [`Connection::into_shared`]
+///
+/// Cloning the handle is cheap, and every clone writes to the same connection. Writes take turns
+/// through an asynchronous mutex, so each value is written whole and values from different tasks
+/// never interleave. The connection is closed once the last clone is dropped.
+///
+/// # Examples
+///
+/// ```no_run
+/// use connection::{Connection};
+/// use std::error::Error;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn Error>> {
+///     // Connect to a peer
+///     let conn = Connection::dial("127.0.0.1:8080").await?.into_shared();
+///
+///     // Report progress from several workers at once
+///     let mut workers = Vec::new();
+///     for id in 0..4u32 {
+///         let conn = conn.clone();
+///         workers.push(tokio::spawn(async move { conn.write(&id).await }));
+///     }
+///     for worker in workers {
+///         worker.await??;
+///     }
+///
+///     Ok(())
+/// }
+/// ```
+pub struct SharedConnection<S = TcpStream> {
+    inner: Arc<Mutex<Connection<S>>>,
+    lock_timeout: Option<Duration>,
+}
+
+impl<S> Clone for SharedConnection<S> {
+    fn clone(&self) -> Self {
+        SharedConnection {
+            inner: self.inner.clone(),
+            lock_timeout: self.lock_timeout,
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    /// Convert the connection into one that many tasks can write to at once
+    pub fn into_shared(self) -> SharedConnection<S> {
+        SharedConnection {
+            inner: Arc::new(Mutex::new(self)),
+            lock_timeout: None,
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> SharedConnection<S> {
+    /// Set how long this handle waits for its turn to use the connection
+    ///
+    /// Waiting longer fails with [`ConnectionError::Timeout`], which points at a task holding the
+    /// connection for too long, or at a deadlock. The timeout applies to this handle and the
+    /// clones made from it afterwards. Passing `None` waits forever, which is the default.
+    pub fn set_lock_timeout(&mut self, timeout: Option<Duration>) {
+        self.lock_timeout = timeout;
+    }
+
+    /// Returns how long this handle waits for its turn to use the connection
+    pub fn lock_timeout(&self) -> Option<Duration> {
+        self.lock_timeout
+    }
+
+    /// Write a serializable value into the stream once no other task is using the connection
+    pub async fn write<T: Serialize + ?Sized>(&self, value: &T) -> Result<(), ConnectionError> {
+        self.lock().await?.write(value).await
+    }
+
+    /// Write a serializable value into the stream if no other task is using the connection,
+    /// returning `None` without writing anything otherwise
+    pub async fn try_write<T: Serialize + ?Sized>(
+        &self,
+        value: &T,
+    ) -> Option<Result<(), ConnectionError>> {
+        let mut conn = self.inner.try_lock().ok()?;
+        Some(conn.write(value).await)
+    }
+
+    /// Wait for exclusive use of the connection, for example to read from it or to write several
+    /// values in a row
+    ///
+    /// Other tasks wait until the returned guard is dropped, so it should not be held across long
+    /// waits such as reads that depend on the peer.
+    pub async fn lock(&self) -> Result<MutexGuard<'_, Connection<S>>, ConnectionError> {
+        match self.lock_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.inner.lock())
+                .await
+                .map_err(|_| ConnectionError::Timeout),
+            None => Ok(self.inner.lock().await),
+        }
+    }
+}
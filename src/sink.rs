@@ -0,0 +1,72 @@
+use crate::{frame, Connection, ConnectionError};
+use bytes::{Buf, BufMut};
+use futures::Sink;
+use serde::Serialize;
+use std::io::{Error, ErrorKind};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    /// Set the number of bytes the `Sink` implementation may buffer before it applies back-pressure
+    pub fn set_max_in_flight_bytes(&mut self, limit: usize) {
+        self.max_in_flight_bytes = limit;
+    }
+
+    /// Writes as many queued frames into the stream as possible without flushing it
+    pub(crate) fn poll_write_pending(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), ConnectionError>> {
+        while !self.pending.is_empty() {
+            let n = match ready!(Pin::new(&mut self.stream).poll_write(cx, &self.pending)) {
+                Ok(0) => Err(Error::from(ErrorKind::WriteZero)),
+                result => result,
+            };
+            let n = self.record(n.map_err(ConnectionError::from))?;
+            self.pending.advance(n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Values sent through the sink are queued in memory and only written to the socket once the
+/// in-flight byte limit is reached or the sink is flushed.
+impl<S: AsyncRead + AsyncWrite + Unpin, T: Serialize> Sink<T> for Connection<S> {
+    type Error = ConnectionError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.pending.len() >= self.max_in_flight_bytes {
+            ready!(self.poll_write_pending(cx))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        let this = &mut *self;
+        let payload = this.record(this.format.serialize(&item))?;
+        let (header, body) = this.record(frame::encode(
+            &payload,
+            this.compression,
+            this.hmac_key.as_ref(),
+            &this.middleware,
+        ))?;
+        this.pending.reserve(header.len() + body.len());
+        this.pending.put_slice(&header);
+        this.pending.put_slice(&body);
+        this.stats.record_sent(body.len());
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        ready!(self.poll_write_pending(cx))?;
+        let result = ready!(Pin::new(&mut self.stream).poll_flush(cx));
+        Poll::Ready(self.record(result.map_err(ConnectionError::from)))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        ready!(<Self as Sink<T>>::poll_flush(self.as_mut(), cx))?;
+        let result = ready!(Pin::new(&mut self.stream).poll_shutdown(cx));
+        Poll::Ready(self.record(result.map_err(ConnectionError::from)))
+    }
+}
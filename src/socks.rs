@@ -0,0 +1,271 @@
+use crate::{connect, Connection, ConnectionError};
+use std::fmt;
+use std::io::Error;
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+/// The version byte that starts every SOCKS5 message
+const VERSION: u8 = 5;
+
+/// The method id of connecting without authentication
+const NO_AUTH: u8 = 0;
+
+/// The method id of username/password authentication (RFC 1929)
+const USERNAME_PASSWORD: u8 = 2;
+
+/// The method id with which a proxy rejects every offered method
+const NO_ACCEPTABLE_METHOD: u8 = 0xFF;
+
+/// The version byte of a username/password negotiation
+const USERNAME_PASSWORD_VERSION: u8 = 1;
+
+/// The command asking the proxy to open a TCP connection
+const CONNECT: u8 = 1;
+
+/// The address types of a request or reply
+const IPV4: u8 = 1;
+const DOMAIN: u8 = 3;
+const IPV6: u8 = 4;
+
+/// How a connection authenticates with a SOCKS5 proxy, see
+/// [`Connection::dial_via_socks5_with_auth`]
+#[derive(Clone, PartialEq, Eq)]
+pub enum Socks5Auth {
+    /// The proxy lets anyone connect
+    None,
+    /// The proxy requires a username and a password (RFC 1929), each at most 255 bytes long
+    UsernamePassword {
+        /// The name of the user
+        username: String,
+        /// The password of the user, which is sent to the proxy in the clear
+        password: String,
+    },
+}
+
+/// Shows the method and username without exposing the password
+impl fmt::Debug for Socks5Auth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Socks5Auth::None => f.write_str("None"),
+            Socks5Auth::UsernamePassword { username, .. } => f
+                .debug_struct("UsernamePassword")
+                .field("username", username)
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+impl Connection {
+    /// Connect to a socket address through a SOCKS5 proxy that requires no authentication
+    ///
+    /// See [`Connection::dial_via_socks5_with_auth`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer through the proxy of the network
+    ///     let mut conn = Connection::dial_via_socks5("10.0.0.1:1080", "10.1.0.1:8080").await?;
+    ///
+    ///     // Send a message
+    ///     conn.write(&"Hello, world!").await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn dial_via_socks5<P: ToSocketAddrs, A: ToSocketAddrs>(
+        proxy: P,
+        target: A,
+    ) -> Result<Connection, ConnectionError> {
+        Connection::dial_via_socks5_with_auth(proxy, target, Socks5Auth::None).await
+    }
+
+    /// Connect to a socket address through a SOCKS5 proxy, authenticating as configured
+    ///
+    /// The target is resolved locally and the proxy is asked to connect to its first address, so
+    /// the proxy never sees host names. Fails with [`ConnectionError::ProxyAuthenticationFailed`]
+    /// if the proxy does not accept the credentials, and with [`ConnectionError::ProxyError`] if
+    /// it does not speak SOCKS5 or fails to reach the target. Failing to reach the proxy itself
+    /// is reported as the IO error of the connection attempt.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection, Socks5Auth};
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer through a proxy that requires a password
+    ///     let auth = Socks5Auth::UsernamePassword {
+    ///         username: "alice".into(),
+    ///         password: "secret".into(),
+    ///     };
+    ///     let mut conn =
+    ///         Connection::dial_via_socks5_with_auth("10.0.0.1:1080", "10.1.0.1:8080", auth).await?;
+    ///
+    ///     // Send a message
+    ///     conn.write(&"Hello, world!").await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn dial_via_socks5_with_auth<P: ToSocketAddrs, A: ToSocketAddrs>(
+        proxy: P,
+        target: A,
+        auth: Socks5Auth,
+    ) -> Result<Connection, ConnectionError> {
+        let resolve_error =
+            |e| ConnectionError::IoErrorContext(e, "failed to resolve the target address".into());
+        let target = tokio::net::lookup_host(target)
+            .await
+            .map_err(resolve_error)?
+            .next()
+            .ok_or_else(|| {
+                resolve_error(Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "could not resolve to any address",
+                ))
+            })?;
+
+        let mut stream = connect(proxy)
+            .await
+            .map_err(|e| e.with_context("failed to reach the SOCKS5 proxy"))?;
+        negotiate(&mut stream, &auth, target).await?;
+        debug!(proxy_addr = ?stream.peer_addr().ok(), %target, "connected through proxy");
+        Ok(Connection::new(stream))
+    }
+}
+
+/// Authenticates with the proxy and asks it to connect to the target
+async fn negotiate(
+    stream: &mut TcpStream,
+    auth: &Socks5Auth,
+    target: SocketAddr,
+) -> Result<(), ConnectionError> {
+    let method = match auth {
+        Socks5Auth::None => NO_AUTH,
+        Socks5Auth::UsernamePassword { .. } => USERNAME_PASSWORD,
+    };
+    stream.write_all(&[VERSION, 1, method]).await?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != VERSION {
+        return Err(ConnectionError::ProxyError(
+            "the proxy does not speak SOCKS5".into(),
+        ));
+    }
+    match reply[1] {
+        chosen if chosen == method => {}
+        NO_ACCEPTABLE_METHOD if method == NO_AUTH => {
+            return Err(ConnectionError::ProxyAuthenticationFailed(
+                "the proxy requires authentication".into(),
+            ))
+        }
+        NO_ACCEPTABLE_METHOD => {
+            return Err(ConnectionError::ProxyAuthenticationFailed(
+                "the proxy does not accept username/password authentication".into(),
+            ))
+        }
+        other => {
+            return Err(ConnectionError::ProxyError(format!(
+                "the proxy chose authentication method {:#04x}, which was not offered",
+                other
+            )))
+        }
+    }
+
+    if let Socks5Auth::UsernamePassword { username, password } = auth {
+        authenticate(stream, username, password).await?;
+    }
+
+    let mut request = vec![VERSION, CONNECT, 0];
+    match target {
+        SocketAddr::V4(addr) => {
+            request.push(IPV4);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+        SocketAddr::V6(addr) => {
+            request.push(IPV6);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+    }
+    request.extend_from_slice(&target.port().to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != 0 {
+        return Err(ConnectionError::ProxyError(format!(
+            "the proxy failed to connect to {}: {}",
+            target,
+            reply_message(reply[1])
+        )));
+    }
+
+    // The address the proxy connected from is of no use to the caller
+    let bound_len = match reply[3] {
+        IPV4 => 4,
+        IPV6 => 16,
+        DOMAIN => usize::from(stream.read_u8().await?),
+        other => {
+            return Err(ConnectionError::ProxyError(format!(
+                "the proxy replied with unknown address type {:#04x}",
+                other
+            )))
+        }
+    };
+    let mut bound = vec![0u8; bound_len + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(())
+}
+
+/// Sends the username and password to the proxy (RFC 1929)
+async fn authenticate(
+    stream: &mut TcpStream,
+    username: &str,
+    password: &str,
+) -> Result<(), ConnectionError> {
+    let (Ok(username_len), Ok(password_len)) =
+        (u8::try_from(username.len()), u8::try_from(password.len()))
+    else {
+        return Err(ConnectionError::InvalidConfiguration(
+            "SOCKS5 usernames and passwords must be at most 255 bytes long".into(),
+        ));
+    };
+
+    let mut request = vec![USERNAME_PASSWORD_VERSION, username_len];
+    request.extend_from_slice(username.as_bytes());
+    request.push(password_len);
+    request.extend_from_slice(password.as_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != 0 {
+        return Err(ConnectionError::ProxyAuthenticationFailed(
+            "the proxy rejected the username or password".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// Describes the reply code of a failed request (RFC 1928, section 6)
+fn reply_message(code: u8) -> &'static str {
+    match code {
+        1 => "general SOCKS server failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
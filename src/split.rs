@@ -0,0 +1,301 @@
+use crate::auth::HmacKey;
+use crate::frame::Control;
+use crate::{
+    frame, with_write_timeout, CompressionMode, Connection, ConnectionError, Middleware,
+    SerdeFormat,
+};
+use bytes::{Bytes, BytesMut};
+use futures::{Sink, Stream};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufWriter, ReadHalf, WriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+/// The read half of a [`Connection`], created by [`Connection::split`]
+pub struct ConnectionReader<S = TcpStream> {
+    buffer: BytesMut,
+    stream: ReadHalf<S>,
+    format: SerdeFormat,
+    read_timeout: Option<Duration>,
+    max_message_size: usize,
+    hmac_key: Option<HmacKey>,
+    middleware: Vec<Arc<dyn Middleware>>,
+    /// Used to answer the pings the peer sends
+    outgoing: Arc<Outgoing<S>>,
+}
+
+/// The write half of a [`Connection`], created by [`Connection::split`]
+pub struct ConnectionWriter<S = TcpStream> {
+    outgoing: Arc<Outgoing<S>>,
+    format: SerdeFormat,
+    compression: CompressionMode,
+    hmac_key: Option<HmacKey>,
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+/// The stream both halves write to, the writer its values and the reader its pongs
+struct Outgoing<S> {
+    stream: Mutex<OutgoingStream<S>>,
+    /// Pings received that have not been answered yet
+    pongs_owed: AtomicUsize,
+    write_timeout: Option<Duration>,
+}
+
+struct OutgoingStream<S> {
+    stream: BufWriter<WriteHalf<S>>,
+    /// Frames the connection had not sent yet when it was split
+    pending: BytesMut,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    /// Split the connection into a read half and a write half which can be used concurrently
+    ///
+    /// Any bytes that have already been received but not yet read are kept by the reader, and any
+    /// values that were written but not yet flushed are sent by the writer ahead of the first
+    /// value it writes. Pings from the peer are answered by the reader as it reads them, or by the
+    /// writer once it finishes the value it is writing.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let conn = Connection::dial("127.0.0.1:8080").await?;
+    ///     let (mut reader, mut writer) = conn.split();
+    ///
+    ///     // Read messages in the background
+    ///     tokio::spawn(async move {
+    ///         while let Ok(Some(message)) = reader.read::<String>().await {
+    ///             println!("{}", message);
+    ///         }
+    ///     });
+    ///
+    ///     // Send a message
+    ///     writer.write(&"Hello, world!").await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn split(mut self) -> (ConnectionReader<S>, ConnectionWriter<S>) {
+        self.skipped.restore(&mut self.buffer);
+        let mut pending = BytesMut::from(self.stream.buffer());
+        pending.extend_from_slice(&self.pending);
+        let (read_half, write_half) = tokio::io::split(self.stream.into_inner());
+        let outgoing = Arc::new(Outgoing {
+            stream: Mutex::new(OutgoingStream {
+                stream: BufWriter::with_capacity(self.write_buffer_size, write_half),
+                pending,
+            }),
+            pongs_owed: AtomicUsize::new(0),
+            write_timeout: self.write_timeout,
+        });
+
+        let reader = ConnectionReader {
+            buffer: self.buffer,
+            stream: read_half,
+            format: self.format,
+            read_timeout: self.read_timeout,
+            max_message_size: self.max_message_size,
+            hmac_key: self.hmac_key,
+            middleware: self.middleware.clone(),
+            outgoing: outgoing.clone(),
+        };
+
+        let writer = ConnectionWriter {
+            outgoing,
+            format: self.format,
+            compression: self.compression,
+            hmac_key: self.hmac_key,
+            middleware: self.middleware,
+        };
+
+        (reader, writer)
+    }
+
+    /// Split the connection into a [`Sink`] of values to send and a [`Stream`] of values received,
+    /// which can be used concurrently
+    ///
+    /// Every value sent through the sink is written and flushed before the next one is accepted.
+    /// The stream ends when the peer closes the connection or after the first error is yielded.
+    /// The connection is closed once both halves are dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use futures::{SinkExt, StreamExt};
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let conn = Connection::dial("127.0.0.1:8080").await?;
+    ///     let (mut sink, mut stream) = conn.into_sink_and_stream::<String, String>();
+    ///
+    ///     // Send a message, then wait for the reply
+    ///     sink.send("Hello, world!".to_string()).await?;
+    ///     let reply = stream.next().await.transpose()?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn into_sink_and_stream<T: Serialize, R: DeserializeOwned>(
+        self,
+    ) -> (
+        impl Sink<T, Error = ConnectionError> + Unpin,
+        impl Stream<Item = Result<R, ConnectionError>> + Unpin,
+    ) {
+        let (reader, writer) = self.split();
+
+        let sink = futures::sink::unfold(writer, |mut writer, value: T| async move {
+            // Serialize before awaiting so the value need not be `Sync`
+            let payload = writer.format.serialize(&value)?;
+            writer.write_raw(&payload).await?;
+            Ok(writer)
+        });
+
+        let stream = futures::stream::unfold(Some(reader), |reader| async move {
+            let mut reader = reader?;
+            match reader.read().await {
+                Ok(Some(value)) => Some((Ok(value), Some(reader))),
+                Ok(None) => None,
+                Err(e) => Some((Err(e), None)),
+            }
+        });
+
+        (Box::pin(sink), Box::pin(stream))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> ConnectionReader<S> {
+    /// Reads from the socket until a complete message is received, or an error occurs
+    pub async fn read<T: DeserializeOwned>(&mut self) -> Result<Option<T>, ConnectionError> {
+        loop {
+            self.answer_controls().await?;
+
+            let value = frame::parse_value(
+                &mut self.buffer,
+                self.format,
+                self.max_message_size,
+                self.hmac_key.as_ref(),
+                &self.middleware,
+            )?;
+            if let Some(value) = value {
+                return Ok(Some(value));
+            }
+
+            let n = frame::read_to_buffer(&mut self.stream, &mut self.buffer, self.read_timeout);
+            if 0 == n.await? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Reads from the socket until a complete frame is received, returning its payload without
+    /// deserializing it
+    pub async fn read_raw(&mut self) -> Result<Option<Bytes>, ConnectionError> {
+        loop {
+            self.answer_controls().await?;
+
+            let payload = frame::take_payload(
+                &mut self.buffer,
+                self.max_message_size,
+                self.hmac_key.as_ref(),
+                &self.middleware,
+            )?;
+            if let Some(payload) = payload {
+                return Ok(Some(payload.freeze()));
+            }
+
+            let n = frame::read_to_buffer(&mut self.stream, &mut self.buffer, self.read_timeout);
+            if 0 == n.await? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Answer every ping at the front of the internal buffer, ignoring the other control frames
+    async fn answer_controls(&mut self) -> Result<(), ConnectionError> {
+        let mut pings = 0;
+        while let Some(control) = frame::take_control(&mut self.buffer) {
+            if control == Control::Ping {
+                pings += 1;
+            }
+        }
+        if pings > 0 {
+            self.outgoing.pongs_owed.fetch_add(pings, Ordering::SeqCst);
+            self.outgoing.answer_pings().await?;
+        }
+        Ok(())
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> ConnectionWriter<S> {
+    /// Write a serializable value into the stream
+    pub async fn write<T: Serialize>(&mut self, value: &T) -> Result<(), ConnectionError> {
+        let buf = self.format.serialize(value)?;
+        self.write_raw(&buf).await
+    }
+
+    /// Write a payload that is already serialized into the stream, skipping the serializer
+    pub async fn write_raw(&mut self, bytes: &[u8]) -> Result<(), ConnectionError> {
+        {
+            let mut outgoing = self.outgoing.stream.lock().await;
+            outgoing.write_pending(self.outgoing.write_timeout).await?;
+            frame::write_frame(
+                &mut outgoing.stream,
+                bytes,
+                0,
+                self.compression,
+                self.outgoing.write_timeout,
+                self.hmac_key.as_ref(),
+                &self.middleware,
+            )
+            .await?;
+        }
+
+        // The reader leaves the pings it receives meanwhile to be answered here
+        self.outgoing.answer_pings().await
+    }
+}
+
+impl<S: AsyncWrite> Outgoing<S> {
+    /// Write a pong for every ping owed, unless a write is in progress, which answers them once it
+    /// is done
+    async fn answer_pings(&self) -> Result<(), ConnectionError> {
+        while self.pongs_owed.load(Ordering::SeqCst) > 0 {
+            let Ok(mut outgoing) = self.stream.try_lock() else {
+                return Ok(());
+            };
+            outgoing.write_pending(self.write_timeout).await?;
+            for _ in 0..self.pongs_owed.swap(0, Ordering::SeqCst) {
+                frame::write_control(&mut outgoing.stream, Control::Pong, self.write_timeout)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S: AsyncWrite> OutgoingStream<S> {
+    /// Write the frames the connection had not sent yet when it was split, which must go first
+    async fn write_pending(&mut self, timeout: Option<Duration>) -> Result<(), ConnectionError> {
+        if !self.pending.is_empty() {
+            let pending = self.pending.split();
+            with_write_timeout(timeout, async {
+                self.stream.write_all(&pending).await?;
+                Ok(())
+            })
+            .await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+use crate::frame::FRAME_HEADER_SIZE;
+#[cfg(feature = "metrics")]
+use crate::metrics::Metrics;
+use crate::ConnectionError;
+
+/// Counters describing how much data a [`Connection`](crate::Connection) has transferred
+///
+/// Byte counts include the framing overhead of every message.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    /// The number of bytes written to the stream
+    pub bytes_sent: u64,
+    /// The number of bytes read from the stream
+    pub bytes_received: u64,
+    /// The number of messages written to the stream
+    pub messages_sent: u64,
+    /// The number of messages read from the stream
+    pub messages_received: u64,
+    /// The number of operations that failed with an error
+    pub errors: u64,
+}
+
+/// Keeps the [`ConnectionStats`] of a connection up to date, and exports every update through
+/// the `metrics` facade when the `metrics` feature is enabled
+pub(crate) struct StatsRecorder {
+    stats: ConnectionStats,
+    #[cfg(feature = "metrics")]
+    metrics: Metrics,
+}
+
+impl StatsRecorder {
+    /// Create a recorder for a connection with the given name, which labels its metrics
+    pub(crate) fn new(name: Option<&str>) -> Self {
+        Self {
+            stats: ConnectionStats::default(),
+            #[cfg(feature = "metrics")]
+            metrics: Metrics::new(name),
+        }
+    }
+
+    /// Returns the statistics recorded so far
+    pub(crate) fn get(&self) -> &ConnectionStats {
+        &self.stats
+    }
+
+    /// Record a message with a payload of the given size being written
+    pub(crate) fn record_sent(&mut self, payload_len: usize) {
+        let n = (FRAME_HEADER_SIZE + payload_len) as u64;
+        self.stats.bytes_sent += n;
+        self.stats.messages_sent += 1;
+        #[cfg(feature = "metrics")]
+        {
+            self.metrics.record_sent_bytes(n);
+            self.metrics.record_sent_message();
+        }
+    }
+
+    /// Record bytes being written to the stream outside of a message
+    pub(crate) fn record_sent_bytes(&mut self, n: usize) {
+        self.stats.bytes_sent += n as u64;
+        #[cfg(feature = "metrics")]
+        self.metrics.record_sent_bytes(n as u64);
+    }
+
+    /// Record bytes being read from the stream
+    pub(crate) fn record_received_bytes(&mut self, n: usize) {
+        self.stats.bytes_received += n as u64;
+        #[cfg(feature = "metrics")]
+        self.metrics.record_received_bytes(n as u64);
+    }
+
+    /// Record a complete message being read from the stream
+    pub(crate) fn record_received_message(&mut self) {
+        self.stats.messages_received += 1;
+        #[cfg(feature = "metrics")]
+        self.metrics.record_received_message();
+    }
+
+    /// Record the outcome of an operation, counting it if it failed
+    pub(crate) fn record<T>(
+        &mut self,
+        result: Result<T, ConnectionError>,
+    ) -> Result<T, ConnectionError> {
+        if let Err(e) = &result {
+            self.stats.errors += 1;
+            #[cfg(feature = "metrics")]
+            self.metrics.record_error(e);
+        }
+        result
+    }
+}
@@ -0,0 +1,99 @@
+use crate::{Connection, ConnectionError};
+use futures::future::BoxFuture;
+use futures::{FutureExt, Stream};
+use serde::de::DeserializeOwned;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+/// A [`Stream`] of values read from a [`Connection`], created by [`Connection::into_stream`]
+///
+/// The stream ends when the peer closes the connection or after the first error is yielded.
+pub struct ConnectionStream<T, S = TcpStream> {
+    state: State<T, S>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+/// A pending read which hands the connection back once it completes
+type ReadFuture<T, S> =
+    BoxFuture<'static, (Box<Connection<S>>, Result<Option<T>, ConnectionError>)>;
+
+enum State<T, S> {
+    /// Waiting to be polled for the next value
+    Idle(Box<Connection<S>>),
+    /// A read is in progress and owns the connection until it completes
+    Reading(ReadFuture<T, S>),
+    /// The connection was closed or an error occurred
+    Done,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> Connection<S> {
+    /// Convert the connection into a [`Stream`] of deserialized values
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::{Connection};
+    /// use futures::StreamExt;
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let conn = Connection::dial("127.0.0.1:8080").await?;
+    ///     let mut stream = conn.into_stream::<String>();
+    ///
+    ///     // Read messages until the peer disconnects
+    ///     while let Some(message) = stream.next().await {
+    ///         println!("{}", message?);
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn into_stream<T: DeserializeOwned + Send + 'static>(self) -> ConnectionStream<T, S> {
+        ConnectionStream {
+            state: State::Idle(Box::new(self)),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, S> Stream for ConnectionStream<T, S>
+where
+    T: DeserializeOwned + Send + 'static,
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Item = Result<T, ConnectionError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match std::mem::replace(&mut self.state, State::Done) {
+                State::Idle(mut conn) => {
+                    let read = async move {
+                        let result = conn.read::<T>().await;
+                        (conn, result)
+                    };
+                    self.state = State::Reading(read.boxed());
+                }
+                State::Reading(mut read) => {
+                    return match read.poll_unpin(cx) {
+                        Poll::Pending => {
+                            self.state = State::Reading(read);
+                            Poll::Pending
+                        }
+                        Poll::Ready((conn, Ok(Some(value)))) => {
+                            self.state = State::Idle(conn);
+                            Poll::Ready(Some(Ok(value)))
+                        }
+                        Poll::Ready((_, Ok(None))) => Poll::Ready(None),
+                        Poll::Ready((_, Err(e))) => Poll::Ready(Some(Err(e))),
+                    };
+                }
+                State::Done => return Poll::Ready(None),
+            }
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use crate::{connect, Connection, ConnectionError};
+use std::sync::Arc;
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName};
+use tokio_rustls::{client, server, TlsAcceptor, TlsConnector};
+
+pub use tokio_rustls::rustls;
+
+/// The stream of the client side of a TLS connection
+pub type ClientTlsStream = client::TlsStream<TcpStream>;
+
+/// The stream of the server side of a TLS connection
+pub type ServerTlsStream = server::TlsStream<TcpStream>;
+
+/// The client side of a connection secured with TLS on top of a [`TcpStream`], created by
+/// [`Connection::dial_tls`]
+///
+/// This type is only available with the `tls` feature enabled.
+pub type TlsConnection = Connection<ClientTlsStream>;
+
+/// The server side of a connection secured with TLS on top of a [`TcpStream`], created by
+/// [`Connection::accept_tls`]
+///
+/// This type is only available with the `tls` feature enabled.
+pub type TlsServerConnection = Connection<ServerTlsStream>;
+
+impl Connection<ClientTlsStream> {
+    /// Connect to a socket address and perform a TLS handshake, verifying the peer as `server_name`
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::rustls::{ClientConfig, RootCertStore};
+    /// use connection::TlsConnection;
+    /// use std::error::Error;
+    /// use std::sync::Arc;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Trust the certificates of your peers
+    ///     let roots = RootCertStore::empty();
+    ///     let config = ClientConfig::builder()
+    ///         .with_root_certificates(roots)
+    ///         .with_no_client_auth();
+    ///
+    ///     // Connect to a peer
+    ///     let mut conn =
+    ///         TlsConnection::dial_tls("127.0.0.1:8443", "localhost", Arc::new(config)).await?;
+    ///
+    ///     // Send a message
+    ///     conn.write(&"Hello, world!").await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn dial_tls<A: ToSocketAddrs>(
+        addr: A,
+        server_name: &str,
+        tls_config: Arc<rustls::ClientConfig>,
+    ) -> Result<TlsConnection, ConnectionError> {
+        let name = ServerName::try_from(server_name.to_owned()).map_err(|_| {
+            ConnectionError::InvalidConfiguration(format!("invalid server name `{}`", server_name))
+        })?;
+
+        let stream = connect(addr).await?;
+        let stream = TlsConnector::from(tls_config)
+            .connect(name, stream)
+            .await
+            .map_err(handshake_error)?;
+
+        debug!(server_name, "completed tls handshake");
+        Ok(Connection::new(stream))
+    }
+
+    /// Returns the end-entity certificate the server presented during the TLS handshake
+    ///
+    /// The certificate is in DER form and has already been verified against the configured roots.
+    pub fn peer_certificate(&self) -> Option<&CertificateDer<'static>> {
+        let (_, session) = self.stream.get_ref().get_ref();
+        session.peer_certificates()?.first()
+    }
+}
+
+impl Connection<ServerTlsStream> {
+    /// Perform the server side of a TLS handshake on an accepted stream
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::rustls::ServerConfig;
+    /// use connection::TlsServerConnection;
+    /// use std::error::Error;
+    /// use std::sync::Arc;
+    /// use tokio::net::TcpListener;
+    ///
+    /// async fn serve(config: Arc<ServerConfig>) -> Result<(), Box<dyn Error>> {
+    ///     let listener = TcpListener::bind("127.0.0.1:8443").await?;
+    ///     let (stream, _) = listener.accept().await?;
+    ///     let mut conn = TlsServerConnection::accept_tls(stream, config).await?;
+    ///
+    ///     // Read a message
+    ///     let message: Option<String> = conn.read().await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn accept_tls(
+        stream: TcpStream,
+        tls_config: Arc<rustls::ServerConfig>,
+    ) -> Result<TlsServerConnection, ConnectionError> {
+        let stream = TlsAcceptor::from(tls_config)
+            .accept(stream)
+            .await
+            .map_err(handshake_error)?;
+
+        debug!("completed tls handshake");
+        Ok(Connection::new(stream))
+    }
+
+    /// Returns the end-entity certificate the client presented during the TLS handshake
+    ///
+    /// This is `None` if the client presented no certificate, which is always the case unless the
+    /// server requires client authentication. The certificate is in DER form and has
+    /// already been verified against the configured roots.
+    pub fn peer_certificate(&self) -> Option<&CertificateDer<'static>> {
+        let (_, session) = self.stream.get_ref().get_ref();
+        session.peer_certificates()?.first()
+    }
+}
+
+/// Surfaces the rustls error behind a failed handshake, falling back to the IO error
+fn handshake_error(e: std::io::Error) -> ConnectionError {
+    match e.get_ref().and_then(|e| e.downcast_ref::<rustls::Error>()) {
+        Some(tls) => ConnectionError::TlsError(tls.clone()),
+        None => ConnectionError::IoError(e),
+    }
+}
@@ -0,0 +1,87 @@
+use crate::{Connection, ConnectionError};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+/// A connection that only sends values of type `S` and only receives values of type `R`
+///
+/// Pinning the message types catches protocol mismatches at compile time instead of when a value
+/// fails to deserialize. Create one with [`Connection::into_typed`].
+///
+/// # Examples
+///
+/// ```no_run
+/// use connection::{Connection, TypedConnection};
+/// use std::error::Error;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn Error>> {
+///     // Connect to a peer that answers every request with its length
+///     let conn = Connection::dial("127.0.0.1:8080").await?;
+///     let mut conn: TypedConnection<String, usize> = conn.into_typed();
+///
+///     conn.send(&"Hello, world!".to_string()).await?;
+///     let len = conn.recv().await?;
+///
+///     Ok(())
+/// }
+/// ```
+///
+/// Sending a value of any other type does not compile:
+///
+/// ```compile_fail
+/// use connection::{Connection, TypedConnection};
+///
+/// async fn send_wrong_type(conn: Connection) {
+///     let mut conn: TypedConnection<String, usize> = conn.into_typed();
+///     conn.send(&42usize).await;
+/// }
+/// ```
+pub struct TypedConnection<S, R, Io = TcpStream> {
+    inner: Connection<Io>,
+    _marker: PhantomData<fn(S) -> R>,
+}
+
+impl<Io: AsyncRead + AsyncWrite + Unpin> Connection<Io> {
+    /// Convert the connection into one that only sends `S` values and only receives `R` values
+    pub fn into_typed<S: Serialize, R: DeserializeOwned>(self) -> TypedConnection<S, R, Io> {
+        TypedConnection {
+            inner: self,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<S, R, Io> TypedConnection<S, R, Io>
+where
+    S: Serialize,
+    R: DeserializeOwned,
+    Io: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Write a value into the stream
+    pub async fn send(&mut self, msg: &S) -> Result<(), ConnectionError> {
+        self.inner.write(msg).await
+    }
+
+    /// Reads from the socket until a complete value is received, or an error occurs
+    pub async fn recv(&mut self) -> Result<Option<R>, ConnectionError> {
+        self.inner.read().await
+    }
+
+    /// Returns a reference to the underlying connection
+    pub fn get_ref(&self) -> &Connection<Io> {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying connection
+    pub fn get_mut(&mut self) -> &mut Connection<Io> {
+        &mut self.inner
+    }
+
+    /// Unwrap the underlying untyped connection
+    pub fn into_inner(self) -> Connection<Io> {
+        self.inner
+    }
+}
@@ -0,0 +1,38 @@
+use crate::{Connection, ConnectionError};
+use std::path::Path;
+use tokio::net::UnixStream;
+
+/// A connection backed by a Unix domain socket
+///
+/// This type is only available on Unix platforms.
+pub type UnixConnection = Connection<UnixStream>;
+
+impl Connection<UnixStream> {
+    /// Connect to a Unix domain socket and return a new connection with the default buffer capacity
+    ///
+    /// This is not named `dial` because [`Connection::dial`] would then be ambiguous wherever the
+    /// stream type is inferred, as in `Connection::dial(addr)`, for the same reason the TLS
+    /// counterpart is `dial_tls`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use connection::UnixConnection;
+    /// use std::error::Error;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn Error>> {
+    ///     // Connect to a peer
+    ///     let mut conn = UnixConnection::dial_unix("/tmp/connection.sock").await?;
+    ///
+    ///     // Send a message
+    ///     conn.write(&"Hello, world!").await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn dial_unix<P: AsRef<Path>>(path: P) -> Result<UnixConnection, ConnectionError> {
+        let stream = UnixStream::connect(path).await?;
+        Ok(Connection::new(stream))
+    }
+}
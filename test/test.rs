@@ -12,8 +12,24 @@ mod tests {
     }
 
     use super::*;
-    use connection::Connection;
+    #[cfg(feature = "rate-limit")]
+    use connection::RateLimitedConnection;
+    use connection::{
+        broadcast, relay, BackoffStrategy, Capabilities, ChannelBridge, CompressionMode,
+        Connection, ConnectionBuilder, ConnectionError, ConnectionPool, FrameHeader,
+        LengthDelimitedConnectionCodec, Middleware, Multiplexer, NegotiatedCapabilities,
+        OutgoingFrame, PoolStats, Priority, Protocol, ProtocolNegotiator, ReconnectingConnection,
+        RetryPolicy, SerdeFormat, Server, Socks5Auth, TypedConnection,
+    };
+    #[cfg(feature = "tls")]
+    use connection::{TlsConnection, TlsServerConnection};
+    use futures::{SinkExt, StreamExt};
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
     use tokio::net::TcpListener;
+    use tokio_util::codec::Framed;
 
     async fn setup() -> (TcpListener, Connection) {
         let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
@@ -46,4 +62,4414 @@ mod tests {
         let parsed_message: String = server_connection.read().await.unwrap().unwrap();
         assert_eq!("Hello, world!", parsed_message);
     }
+
+    #[tokio::test]
+    async fn read_back_to_back_messages() {
+        let (server_listener, mut client_connection) = setup().await;
+        for id in 0..3 {
+            let message = TestMessage {
+                id,
+                name: format!("Message {}", id),
+                payload: vec![id as u8; id as usize],
+            };
+            client_connection.write(&message).await.unwrap();
+        }
+
+        let mut server_connection = Connection::new(server_listener.accept().await.unwrap().0);
+        for id in 0..3 {
+            let parsed_message: TestMessage = server_connection.read().await.unwrap().unwrap();
+            assert_eq!(id, parsed_message.id);
+            assert_eq!(vec![id as u8; id as usize], parsed_message.payload);
+        }
+    }
+
+    #[tokio::test]
+    async fn read_returns_none_after_peer_closes() {
+        let (server_listener, mut client_connection) = setup().await;
+        client_connection.write(&"Hello, world!").await.unwrap();
+        drop(client_connection);
+
+        let mut server_connection = Connection::new(server_listener.accept().await.unwrap().0);
+        let parsed_message: String = server_connection.read().await.unwrap().unwrap();
+        assert_eq!("Hello, world!", parsed_message);
+        assert!(server_connection.read::<String>().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn write_and_read_json_message() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut client_connection = Connection::dial_with_format(addr, SerdeFormat::Json)
+            .await
+            .unwrap();
+        let message = TestMessage {
+            id: 123,
+            name: "Test Message".to_string(),
+            payload: vec![1, 2, 3, 4, 5],
+        };
+
+        client_connection.write(&message).await.unwrap();
+
+        let stream = listener.accept().await.unwrap().0;
+        let mut server_connection = Connection::new_with_format(stream, SerdeFormat::Json);
+        assert_eq!(SerdeFormat::Json, server_connection.format());
+        let parsed_message: TestMessage = server_connection.read().await.unwrap().unwrap();
+        assert_eq!(message, parsed_message);
+    }
+
+    #[tokio::test]
+    async fn json_payload_is_human_readable() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut client_connection = Connection::dial_with_format(addr, SerdeFormat::Json)
+            .await
+            .unwrap();
+        client_connection.write(&vec![1, 2, 3]).await.unwrap();
+
+        // Read the frame straight off the socket to inspect the JSON payload
+        let mut stream = listener.accept().await.unwrap().0;
+        let mut header = [0u8; FrameHeader::SIZE];
+        stream.read_exact(&mut header).await.unwrap();
+        let header = FrameHeader::from_bytes(header);
+        assert_eq!(FrameHeader::new(7, 0), header);
+        let mut payload = vec![0u8; header.length as usize];
+        stream.read_exact(&mut payload).await.unwrap();
+        assert_eq!(b"[1,2,3]".to_vec(), payload);
+    }
+
+    #[tokio::test]
+    async fn builder_configures_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut client_connection = ConnectionBuilder::new()
+            .capacity(64)
+            .format(SerdeFormat::Json)
+            .read_timeout(Duration::from_secs(5))
+            .write_timeout(Duration::from_secs(5))
+            .nodelay(true)
+            .connect(addr)
+            .await
+            .unwrap();
+        assert_eq!(SerdeFormat::Json, client_connection.format());
+        client_connection.write(&"Hello, world!").await.unwrap();
+
+        let stream = listener.accept().await.unwrap().0;
+        let mut server_connection = Connection::builder()
+            .format(SerdeFormat::Json)
+            .build(stream)
+            .unwrap();
+        let parsed_message: String = server_connection.read().await.unwrap().unwrap();
+        assert_eq!("Hello, world!", parsed_message);
+    }
+
+    #[test]
+    fn connection_error_exposes_its_source() {
+        use std::error::Error;
+
+        let io = std::io::Error::new(std::io::ErrorKind::BrokenPipe, "pipe closed");
+        let error = ConnectionError::IoError(io);
+        let source = error.source().unwrap();
+        assert_eq!("pipe closed", source.to_string());
+        assert!(source.downcast_ref::<std::io::Error>().is_some());
+
+        let bincode_error = bincode::deserialize::<String>(&[1]).unwrap_err();
+        let message = bincode_error.to_string();
+        let error = ConnectionError::from(bincode_error);
+        assert_eq!(message, error.source().unwrap().to_string());
+
+        let json_error = serde_json::from_str::<u32>("x").unwrap_err();
+        let message = json_error.to_string();
+        let error = ConnectionError::from(json_error);
+        assert_eq!(message, error.source().unwrap().to_string());
+
+        assert!(ConnectionError::Timeout.source().is_none());
+    }
+
+    #[tokio::test]
+    async fn error_context_is_displayed_and_chained() {
+        use std::error::Error;
+
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+        client_connection.write(&[1u8]).await.unwrap();
+
+        let error = server_connection
+            .read::<String>()
+            .await
+            .unwrap_err()
+            .with_context("reading the login message")
+            .with_context("handling client 7");
+        let message = error.to_string();
+        assert!(message.starts_with("handling client 7: `reading the login message: "));
+
+        // The chain leads back to the original deserialization error
+        let inner = error.source().unwrap();
+        assert!(inner.to_string().starts_with("reading the login message: "));
+        let root = inner.source().unwrap();
+        assert!(message.contains(&root.to_string()));
+        assert!(root.source().is_some());
+
+        // The context does not change how the error is classified
+        assert!(error.is_recoverable());
+        assert!(ConnectionError::IdleTimeout.with_context("idle").is_fatal());
+    }
+
+    #[test]
+    fn connection_errors_are_classified() {
+        use std::io::{Error, ErrorKind};
+
+        for kind in [
+            ErrorKind::WouldBlock,
+            ErrorKind::Interrupted,
+            ErrorKind::TimedOut,
+        ] {
+            let error = ConnectionError::IoError(Error::from(kind));
+            assert!(error.is_recoverable());
+            assert!(!error.is_fatal());
+        }
+        for kind in [ErrorKind::BrokenPipe, ErrorKind::ConnectionReset] {
+            assert!(ConnectionError::IoError(Error::from(kind)).is_fatal());
+        }
+
+        let bincode_error = bincode::deserialize::<String>(&[1]).unwrap_err();
+        assert!(ConnectionError::from(bincode_error).is_recoverable());
+        let json_error = serde_json::from_str::<u32>("x").unwrap_err();
+        assert!(ConnectionError::from(json_error).is_recoverable());
+        assert!(ConnectionError::Timeout.is_recoverable());
+        assert!(ConnectionError::PoolExhausted.is_recoverable());
+
+        assert!(ConnectionError::WriteTimeout.is_fatal());
+        assert!(ConnectionError::Disconnected.is_fatal());
+        assert!(ConnectionError::ConnectionReset("closed".into()).is_fatal());
+        assert!(ConnectionError::InvalidConfiguration("capacity".into()).is_fatal());
+        assert!(ConnectionError::MessageTooLarge {
+            claimed: 2,
+            limit: 1
+        }
+        .is_fatal());
+    }
+
+    #[tokio::test]
+    async fn debug_output_shows_connection_state() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client_connection = ConnectionBuilder::new()
+            .name("primary")
+            .capacity(128)
+            .connect(addr)
+            .await
+            .unwrap();
+        assert_eq!(Some("primary"), client_connection.name());
+
+        let debug = format!("{:?}", client_connection);
+        assert!(debug.starts_with("Connection {"));
+        assert!(debug.contains("name: Some(\"primary\")"));
+        assert!(debug.contains(&format!("peer_addr: Some({})", addr)));
+        assert!(debug.contains("format: Bincode"));
+        assert!(debug.contains("compression: None"));
+        assert!(debug.contains("buffer_len: 0"));
+        assert!(debug.contains("buffer_capacity: 128"));
+        assert!(!debug.contains("BufWriter"));
+        assert!(!debug.contains("fd"));
+
+        let (client_stream, _server_stream) = tokio::io::duplex(64);
+        let debug = format!("{:?}", Connection::new(client_stream));
+        assert!(debug.contains("name: None"));
+        assert!(debug.contains("peer_addr: None"));
+    }
+
+    #[tokio::test]
+    async fn builder_rejects_zero_capacity() {
+        let (server_listener, _client_connection) = setup().await;
+        let stream = server_listener.accept().await.unwrap().0;
+        let result = ConnectionBuilder::new().capacity(0).build(stream);
+        assert!(matches!(
+            result,
+            Err(ConnectionError::InvalidConfiguration(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn builder_sizes_read_and_write_buffers_separately() {
+        let (server_listener, mut client_connection) = setup().await;
+        let stream = server_listener.accept().await.unwrap().0;
+        let mut server_connection = ConnectionBuilder::new()
+            .read_buffer_size(16 * 1024)
+            .write_buffer_size(1400)
+            .build(stream)
+            .unwrap();
+        assert!(server_connection.buffer_capacity() >= 16 * 1024);
+        assert_eq!(1400, server_connection.write_buffer_size());
+
+        let message = "x".repeat(4096);
+        server_connection.write(&message).await.unwrap();
+        let received: String = client_connection.read().await.unwrap().unwrap();
+        assert_eq!(message, received);
+
+        let (server_listener, _client_connection) = setup().await;
+        let stream = server_listener.accept().await.unwrap().0;
+        let result = ConnectionBuilder::new().write_buffer_size(0).build(stream);
+        assert!(matches!(
+            result,
+            Err(ConnectionError::InvalidConfiguration(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn compressed_and_uncompressed_messages_mix() {
+        let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+        let mut client_connection = Connection::new(client_stream);
+        client_connection.set_compression(CompressionMode::Zstd(3));
+        let mut server_connection = Connection::new(server_stream);
+        assert_eq!(CompressionMode::Zstd(3), client_connection.compression());
+
+        let payload = vec![7u8; 256 * 1024];
+        client_connection.write(&payload).await.unwrap();
+        assert!(client_connection.stats().bytes_sent < 1024);
+        let received: Vec<u8> = server_connection.read().await.unwrap().unwrap();
+        assert_eq!(payload, received);
+
+        client_connection.set_compression(CompressionMode::None);
+        client_connection.write(&"Hello, world!").await.unwrap();
+        client_connection.set_compression(CompressionMode::Zstd(1));
+        client_connection.write(&payload).await.unwrap();
+
+        let message: String = server_connection.read().await.unwrap().unwrap();
+        assert_eq!("Hello, world!", message);
+        let received: Vec<u8> = server_connection.read().await.unwrap().unwrap();
+        assert_eq!(payload, received);
+
+        // Every algorithm is decompressed regardless of the reader's own mode
+        for compression in [CompressionMode::Lz4, CompressionMode::Snappy] {
+            client_connection.set_compression(compression);
+            client_connection.write(&payload).await.unwrap();
+            let received: Vec<u8> = server_connection.read().await.unwrap().unwrap();
+            assert_eq!(payload, received);
+        }
+    }
+
+    #[tokio::test]
+    async fn frame_header_records_compression_algorithm() {
+        for (compression, id) in [
+            (CompressionMode::None, 0),
+            (CompressionMode::Zstd(3), 1),
+            (CompressionMode::Lz4, 2),
+            (CompressionMode::Snappy, 3),
+        ] {
+            let (client_stream, mut server_stream) = tokio::io::duplex(64 * 1024);
+            let mut client_connection = Connection::new(client_stream);
+            client_connection.set_compression(compression);
+            client_connection.write(&vec![7u8; 1024]).await.unwrap();
+
+            let mut header = [0u8; FrameHeader::SIZE];
+            server_stream.read_exact(&mut header).await.unwrap();
+            let header = FrameHeader::from_bytes(header);
+            assert_eq!(id, header.compression);
+            assert_eq!(0, header.flags);
+        }
+
+        // A payload that does not shrink is sent as is
+        let (client_stream, mut server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        client_connection.set_compression(CompressionMode::Lz4);
+        client_connection.write_raw(b"abc").await.unwrap();
+        let mut frame = [0u8; FrameHeader::SIZE + 3];
+        server_stream.read_exact(&mut frame).await.unwrap();
+        assert_eq!([0, 0, 0, 3, 0, 0, 0, 0, b'a', b'b', b'c'], frame);
+
+        // An unknown algorithm is rejected
+        let (mut client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut server_connection = Connection::new(server_stream);
+        client_stream
+            .write_all(&[0, 0, 0, 1, 0, 9, 0, 0, 0])
+            .await
+            .unwrap();
+        assert!(server_connection.read_raw().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn decompressed_payload_respects_message_size_limit() {
+        for compression in [
+            CompressionMode::Zstd(3),
+            CompressionMode::Lz4,
+            CompressionMode::Snappy,
+        ] {
+            let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+            let mut client_connection = Connection::new(client_stream);
+            client_connection.set_compression(compression);
+            let mut server_connection = Connection::new(server_stream);
+            server_connection.set_max_message_size(1024);
+
+            client_connection
+                .write(&vec![0u8; 64 * 1024])
+                .await
+                .unwrap();
+            let result = server_connection.read::<Vec<u8>>().await;
+            assert!(matches!(
+                result,
+                Err(ConnectionError::MessageTooLarge { limit: 1024, .. })
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn builder_configures_compression() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client_connection = ConnectionBuilder::new()
+            .compression(CompressionMode::Zstd(3))
+            .connect(addr)
+            .await
+            .unwrap();
+        assert_eq!(CompressionMode::Zstd(3), client_connection.compression());
+
+        let stream = listener.accept().await.unwrap().0;
+        let result = ConnectionBuilder::new()
+            .compression(CompressionMode::Zstd(1000))
+            .build(stream);
+        assert!(matches!(
+            result,
+            Err(ConnectionError::InvalidConfiguration(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn split_halves_read_and_write_concurrently() {
+        let (server_listener, client_connection) = setup().await;
+        let server_connection = Connection::new(server_listener.accept().await.unwrap().0);
+        let (mut client_reader, mut client_writer) = client_connection.split();
+        let (mut server_reader, mut server_writer) = server_connection.split();
+
+        // The server echoes every message back to the client
+        let echo = tokio::spawn(async move {
+            while let Some(id) = server_reader.read::<u32>().await.unwrap() {
+                server_writer.write(&id).await.unwrap();
+            }
+        });
+
+        let writer = tokio::spawn(async move {
+            for id in 0..100u32 {
+                client_writer.write(&id).await.unwrap();
+            }
+        });
+
+        let reader = tokio::spawn(async move {
+            for id in 0..100u32 {
+                assert_eq!(id, client_reader.read::<u32>().await.unwrap().unwrap());
+            }
+        });
+
+        writer.await.unwrap();
+        reader.await.unwrap();
+        echo.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn split_keeps_values_that_were_not_flushed() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut pipeline = Connection::new(client_stream)
+            .into_pipeline()
+            .flush_interval(Duration::from_secs(60));
+        let mut server_connection = Connection::new(server_stream);
+
+        // One value waits for the pipeline, one was never flushed, one is queued by the sink
+        pipeline.write(&1u32).await.unwrap();
+        let mut client_connection = pipeline.into_inner().await;
+        client_connection.write_no_flush(&2u32).await.unwrap();
+        client_connection.feed(3u32).await.unwrap();
+
+        let (_reader, mut writer) = client_connection.split();
+        writer.write(&4u32).await.unwrap();
+
+        for id in 1..=4u32 {
+            assert_eq!(Some(id), server_connection.read::<u32>().await.unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn connection_wraps_any_async_stream() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        let (mut server_reader, mut server_writer) = Connection::new(server_stream).split();
+
+        client_connection.write(&"ping".to_string()).await.unwrap();
+        let message: String = server_reader.read().await.unwrap().unwrap();
+        server_writer
+            .write(&format!("{} pong", message))
+            .await
+            .unwrap();
+
+        let reply: String = client_connection.read().await.unwrap().unwrap();
+        assert_eq!("ping pong", reply);
+    }
+
+    #[tokio::test]
+    async fn try_read_returns_buffered_message() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+
+        client_connection.write(&1u32).await.unwrap();
+        client_connection.write(&2u32).await.unwrap();
+
+        assert_eq!(Some(1), server_connection.try_read::<u32>().await.unwrap());
+        assert_eq!(Some(2), server_connection.try_read::<u32>().await.unwrap());
+        assert_eq!(None, server_connection.try_read::<u32>().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn frames_match_the_wire_format_test_vectors() {
+        async fn written<T: Serialize>(format: SerdeFormat, value: &T) -> String {
+            let (client_stream, mut server_stream) = tokio::io::duplex(1024);
+            let mut client_connection = Connection::new_with_format(client_stream, format);
+            client_connection.write(value).await.unwrap();
+            drop(client_connection);
+
+            let mut bytes = Vec::new();
+            server_stream.read_to_end(&mut bytes).await.unwrap();
+            bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+        }
+
+        // The same vectors are published in WIRE_FORMAT.md
+        assert_eq!(
+            "0000000d00000000050000000000000048656c6c6f",
+            written(SerdeFormat::Bincode, &"Hello").await
+        );
+        assert_eq!(
+            "00000007000000005b312c322c335d",
+            written(SerdeFormat::Json, &[1, 2, 3]).await
+        );
+
+        let header = FrameHeader {
+            length: 0x0102_0304,
+            flags: 2,
+            compression: 1,
+            reserved: [6, 7],
+        };
+        assert_eq!([1, 2, 3, 4, 2, 1, 6, 7], header.to_bytes());
+        assert_eq!(header, FrameHeader::from_bytes(header.to_bytes()));
+    }
+
+    #[tokio::test]
+    async fn try_read_returns_none_until_frame_is_complete() {
+        let (mut client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut server_connection = Connection::new(server_stream);
+        assert_eq!(None, server_connection.try_read::<u32>().await.unwrap());
+
+        // Send the header of an uncompressed 4-byte bincode payload, then the payload itself
+        client_stream
+            .write_all(&[0, 0, 0, 4, 0, 0, 0, 0])
+            .await
+            .unwrap();
+        assert_eq!(None, server_connection.try_read::<u32>().await.unwrap());
+
+        client_stream.write_all(&7u32.to_le_bytes()).await.unwrap();
+        assert_eq!(Some(7), server_connection.try_read::<u32>().await.unwrap());
+
+        drop(client_stream);
+        assert!(matches!(
+            server_connection.try_read::<u32>().await,
+            Err(ConnectionError::Disconnected)
+        ));
+    }
+
+    /// Answer every `String` request with its length
+    fn spawn_length_server(stream: tokio::io::DuplexStream) {
+        tokio::spawn(async move {
+            let mut conn = Connection::new(stream);
+            while let Ok(Some(request)) = conn.read::<String>().await {
+                conn.write(&request.len()).await.unwrap();
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn request_returns_response() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        spawn_length_server(server_stream);
+        let mut client_connection = Connection::new(client_stream);
+
+        let timeout = Some(Duration::from_secs(1));
+        let len: usize = client_connection
+            .request(&"hello".to_string(), timeout)
+            .await
+            .unwrap();
+        assert_eq!(5, len);
+    }
+
+    #[tokio::test]
+    async fn request_times_out_without_response() {
+        let (client_stream, _server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+
+        let timeout = Some(Duration::from_millis(50));
+        let result = client_connection
+            .request::<_, usize>(&"hello".to_string(), timeout)
+            .await;
+        assert!(matches!(result, Err(ConnectionError::Timeout)));
+    }
+
+    #[tokio::test]
+    async fn request_response_alternates_send_and_recv() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        spawn_length_server(server_stream);
+        let mut conn = Connection::new(client_stream).into_request_response();
+
+        for request in ["a", "bb", "ccc"] {
+            let awaiting = conn.send(&request.to_string()).await.unwrap();
+            let (len, ready) = awaiting.recv::<usize>().await.unwrap();
+            assert_eq!(request.len(), len);
+            conn = ready;
+        }
+    }
+
+    #[tokio::test]
+    async fn write_raw_and_read_raw_round_trip() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+
+        client_connection.write_raw(b"opaque").await.unwrap();
+        client_connection.write_raw(b"").await.unwrap();
+        drop(client_connection);
+
+        let payload = server_connection.read_raw().await.unwrap().unwrap();
+        assert_eq!(&b"opaque"[..], &payload[..]);
+        let payload = server_connection.read_raw().await.unwrap().unwrap();
+        assert!(payload.is_empty());
+        assert!(server_connection.read_raw().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn bincode_encodes_enums_the_same_on_every_architecture() {
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        enum ClientMsg {
+            Login { user: String },
+            Ping,
+            Data(Vec<u8>),
+        }
+
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+
+        // Variants are tagged with a little-endian u32 and lengths are little-endian u64s
+        let expected: [(ClientMsg, &[u8]); 3] = [
+            (
+                ClientMsg::Login {
+                    user: "ab".to_string(),
+                },
+                &[0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, b'a', b'b'],
+            ),
+            (ClientMsg::Ping, &[1, 0, 0, 0]),
+            (
+                ClientMsg::Data(vec![9]),
+                &[2, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 9],
+            ),
+        ];
+        for (message, bytes) in expected {
+            client_connection.write(&message).await.unwrap();
+            let payload = server_connection.read_raw().await.unwrap().unwrap();
+            assert_eq!(bytes, &payload[..]);
+
+            server_connection.write_raw(&payload).await.unwrap();
+            assert_eq!(Some(message), client_connection.read().await.unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn raw_payloads_interoperate_with_serialized_values() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+
+        // A serialized value read raw is its bincode representation
+        client_connection.write(&7u32).await.unwrap();
+        let payload = server_connection.read_raw().await.unwrap().unwrap();
+        assert_eq!(bincode::serialize(&7u32).unwrap(), payload);
+
+        // Relaying that representation raw reads back as the original value
+        server_connection.write_raw(&payload).await.unwrap();
+        assert_eq!(Some(7u32), client_connection.read().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn padding_round_trips_between_messages() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+
+        client_connection.write(&"first").await.unwrap();
+        client_connection.write_zeroes(3).await.unwrap();
+        client_connection.write(&"second").await.unwrap();
+        client_connection.write_zeroes(0).await.unwrap();
+        // More padding than the duplex buffer holds, written in several chunks
+        let writer = tokio::spawn(async move {
+            client_connection.write_zeroes(10_000).await.unwrap();
+            client_connection.write(&"third").await.unwrap();
+            client_connection.write_zeroes(2).await.unwrap();
+            client_connection
+        });
+
+        assert_eq!(
+            Some("first".to_string()),
+            server_connection.read().await.unwrap()
+        );
+        server_connection.skip(3).await.unwrap();
+        assert_eq!(
+            Some("second".to_string()),
+            server_connection.read().await.unwrap()
+        );
+        server_connection.skip(10_000).await.unwrap();
+        assert_eq!(
+            Some("third".to_string()),
+            server_connection.read().await.unwrap()
+        );
+
+        let client_connection = writer.await.unwrap();
+        // Three framed strings and 10,005 bytes of padding
+        assert_eq!(64 + 10_005, client_connection.stats().bytes_sent);
+        drop(client_connection);
+
+        // Running out of bytes while skipping is an error rather than a clean close
+        let result = server_connection.skip(3).await;
+        assert!(matches!(result, Err(ConnectionError::ConnectionReset(_))));
+    }
+
+    #[tokio::test]
+    async fn read_many_returns_a_burst_in_one_call() {
+        let (server_listener, mut client_connection) = setup().await;
+        let (stream, _) = server_listener.accept().await.unwrap();
+        let mut server_connection = Connection::new(stream);
+
+        let messages: Vec<u32> = (0..100).collect();
+        client_connection.write_batch(&messages).await.unwrap();
+        client_connection.write(&100u32).await.unwrap();
+        // Give the burst time to arrive before reading it
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert_eq!(
+            messages,
+            server_connection.read_many::<u32>(100).await.unwrap()
+        );
+        assert!(server_connection
+            .read_many::<u32>(0)
+            .await
+            .unwrap()
+            .is_empty());
+        drop(client_connection);
+        assert_eq!(
+            vec![100],
+            server_connection.read_many::<u32>(100).await.unwrap()
+        );
+        assert!(server_connection
+            .read_many::<u32>(100)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn coalesced_writes_share_a_single_flush() {
+        use std::pin::Pin;
+        use std::task::{Context, Poll};
+        use tokio::io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf};
+
+        /// Counts how often the stream is flushed
+        struct CountFlushes(DuplexStream, Arc<AtomicU32>);
+
+        impl AsyncRead for CountFlushes {
+            fn poll_read(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &mut ReadBuf<'_>,
+            ) -> Poll<std::io::Result<()>> {
+                Pin::new(&mut self.0).poll_read(cx, buf)
+            }
+        }
+
+        impl AsyncWrite for CountFlushes {
+            fn poll_write(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &[u8],
+            ) -> Poll<std::io::Result<usize>> {
+                Pin::new(&mut self.0).poll_write(cx, buf)
+            }
+
+            fn poll_flush(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+            ) -> Poll<std::io::Result<()>> {
+                self.1.fetch_add(1, Ordering::SeqCst);
+                Pin::new(&mut self.0).poll_flush(cx)
+            }
+
+            fn poll_shutdown(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+            ) -> Poll<std::io::Result<()>> {
+                Pin::new(&mut self.0).poll_shutdown(cx)
+            }
+        }
+
+        let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+        let flushes = Arc::new(AtomicU32::new(0));
+        let mut pipeline = Connection::new(CountFlushes(client_stream, flushes.clone()))
+            .into_pipeline()
+            .flush_interval(Duration::from_millis(50));
+        let mut server_connection = Connection::new(server_stream);
+
+        let server = tokio::spawn(async move {
+            let a = server_connection.read::<u32>().await.unwrap().unwrap();
+            let b = server_connection.read::<u32>().await.unwrap().unwrap();
+            server_connection.write(&(a + b)).await.unwrap();
+            server_connection
+        });
+
+        // Both writes wait in the buffer, and the read waiting on the reply flushes them together
+        pipeline.write(&1u32).await.unwrap();
+        pipeline.write(&2u32).await.unwrap();
+        assert_eq!(0, flushes.load(Ordering::SeqCst));
+        assert_eq!(Some(3), pipeline.read::<u32>().await.unwrap());
+        assert_eq!(1, flushes.load(Ordering::SeqCst));
+        let mut server_connection = server.await.unwrap();
+
+        // Writes within the delay are flushed together in the background once it elapses
+        pipeline.write(&3u32).await.unwrap();
+        pipeline.write(&4u32).await.unwrap();
+        assert_eq!(Some(3), server_connection.read::<u32>().await.unwrap());
+        assert_eq!(Some(4), server_connection.read::<u32>().await.unwrap());
+        assert_eq!(2, flushes.load(Ordering::SeqCst));
+
+        // Batches still flush as soon as they are written
+        pipeline.lock().await.write_batch(&[5u32, 6]).await.unwrap();
+        assert_eq!(3, flushes.load(Ordering::SeqCst));
+        assert_eq!(Some(5), server_connection.read::<u32>().await.unwrap());
+        assert_eq!(Some(6), server_connection.read::<u32>().await.unwrap());
+    }
+
+    /// Accept one connection and answer every batch of `n` values with the number received
+    fn spawn_counting_server(listener: TcpListener, n: u32) {
+        tokio::spawn(async move {
+            let mut conn = Connection::new(listener.accept().await.unwrap().0);
+            loop {
+                for _ in 0..n {
+                    if conn.read::<u32>().await.unwrap().is_none() {
+                        return;
+                    }
+                }
+                conn.write(&n).await.unwrap();
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn pipelined_writes_outpace_flushed_writes() {
+        const N: u32 = 5_000;
+        let (server_listener, mut client_connection) = setup().await;
+        spawn_counting_server(server_listener, N);
+        // Without this, the last values flushed can wait for a delayed acknowledgment
+        client_connection.set_nodelay(true).unwrap();
+
+        let start = std::time::Instant::now();
+        for id in 0..N {
+            client_connection.write(&id).await.unwrap();
+        }
+        assert_eq!(Some(N), client_connection.read().await.unwrap());
+        let flushed = start.elapsed();
+
+        let mut pipeline = client_connection
+            .into_pipeline()
+            .flush_interval(Duration::from_secs(60))
+            .flush_threshold(64);
+        pipeline.write(&0u32).await.unwrap();
+        assert_eq!(12, pipeline.unflushed().await);
+        for id in 1..N {
+            pipeline.write(&id).await.unwrap();
+            assert!(pipeline.unflushed().await < 64);
+        }
+        assert_eq!(Some(N), pipeline.read().await.unwrap());
+        assert_eq!(0, pipeline.unflushed().await);
+
+        let mut pipeline = pipeline.into_inner().await.into_pipeline();
+        let start = std::time::Instant::now();
+        for id in 0..N {
+            pipeline.write(&id).await.unwrap();
+        }
+        assert_eq!(Some(N), pipeline.read().await.unwrap());
+        let pipelined = start.elapsed();
+        assert!(
+            pipelined < flushed,
+            "pipelined writes took {pipelined:?}, flushed writes took {flushed:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn pipelined_values_are_flushed_in_the_background() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut pipeline = Connection::new(client_stream)
+            .into_pipeline()
+            .flush_interval(Duration::from_millis(20));
+        let mut server_connection = Connection::new(server_stream);
+
+        // A lone value is sent once the interval elapses, without another write or read
+        pipeline.write(&1u32).await.unwrap();
+        assert_eq!(12, pipeline.unflushed().await);
+        let read = server_connection.read::<u32>();
+        let value = tokio::time::timeout(Duration::from_secs(1), read).await;
+        assert_eq!(Some(1), value.unwrap().unwrap());
+        assert_eq!(0, pipeline.unflushed().await);
+
+        // Values that are not due yet stay with the connection
+        pipeline.write(&2u32).await.unwrap();
+        let mut client_connection = pipeline.into_inner().await;
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        assert!(server_connection.try_read::<u32>().await.unwrap().is_none());
+        client_connection.flush().await.unwrap();
+        assert_eq!(Some(2), server_connection.read::<u32>().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn ordered_connection_detects_out_of_order_values() {
+        // A bincode-encoded u32 preceded by its sequence number, as an OrderedConnection writes it
+        let numbered = |sequence: u64, value: u32| {
+            [sequence.to_be_bytes().as_slice(), &value.to_le_bytes()].concat()
+        };
+
+        let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+        let mut client_connection = Connection::new(client_stream).into_ordered();
+        let mut server_connection = Connection::new(server_stream).into_ordered();
+
+        client_connection.write(&1u32).await.unwrap();
+        client_connection.write(&2u32).await.unwrap();
+        assert_eq!(2, client_connection.next_send_sequence());
+        assert_eq!(Some(1), server_connection.read::<u32>().await.unwrap());
+        assert_eq!(Some(2), server_connection.read::<u32>().await.unwrap());
+
+        // Strict ordering rejects the value and keeps expecting the same number
+        let raw = client_connection.get_mut();
+        raw.write_raw(&numbered(5, 5)).await.unwrap();
+        raw.write_raw(&numbered(2, 3)).await.unwrap();
+        assert!(matches!(
+            server_connection.read::<u32>().await,
+            Err(ConnectionError::OutOfOrder {
+                expected: 2,
+                got: 5
+            })
+        ));
+        assert_eq!(Some(3), server_connection.read::<u32>().await.unwrap());
+
+        let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+        let mut client_connection = Connection::new(client_stream).into_ordered();
+        let mut server_connection = Connection::new(server_stream)
+            .into_ordered()
+            .allow_gap(true);
+
+        // Gaps are skipped over, and values behind the expected number are dropped
+        client_connection.write(&1u32).await.unwrap();
+        let raw = client_connection.get_mut();
+        raw.write_raw(&numbered(4, 5)).await.unwrap();
+        raw.write_raw(&numbered(2, 3)).await.unwrap();
+        raw.write_raw(&numbered(5, 6)).await.unwrap();
+        assert_eq!(Some(1), server_connection.read::<u32>().await.unwrap());
+        assert_eq!(Some(5), server_connection.read::<u32>().await.unwrap());
+        assert_eq!(Some(6), server_connection.read::<u32>().await.unwrap());
+        assert_eq!(6, server_connection.next_receive_sequence());
+    }
+
+    #[tokio::test]
+    async fn schema_versions_travel_with_their_values() {
+        #[derive(serde::Serialize)]
+        struct UserV1 {
+            name: String,
+        }
+
+        #[derive(serde::Serialize)]
+        struct UserV2 {
+            name: String,
+            email: String,
+        }
+
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct User {
+            name: String,
+            #[serde(default)]
+            email: Option<String>,
+        }
+
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new_with_format(client_stream, SerdeFormat::Json);
+        let mut server_connection = Connection::new_with_format(server_stream, SerdeFormat::Json);
+
+        let name = "ferris".to_string();
+        let v1 = UserV1 { name: name.clone() };
+        client_connection.write_versioned(&v1, 1).await.unwrap();
+        let v2 = UserV2 {
+            name: name.clone(),
+            email: "ferris@example.com".to_string(),
+        };
+        client_connection.write_versioned(&v2, 7).await.unwrap();
+        client_connection.write(&v1).await.unwrap();
+        assert!(matches!(
+            client_connection.write_versioned(&v1, 8).await,
+            Err(ConnectionError::InvalidConfiguration(_))
+        ));
+
+        let (user, version) = server_connection
+            .read_with_schema_version::<User>()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((None, 1), (user.email, version));
+        let (user, version) = server_connection
+            .read_with_schema_version::<User>()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (Some("ferris@example.com".to_string()), 7),
+            (user.email, version)
+        );
+        let (user, version) = server_connection
+            .read_with_schema_version::<User>()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((name, 0), (user.name, version));
+
+        // Versioned values read like any other value
+        client_connection.write_versioned(&v1, 5).await.unwrap();
+        let user: User = server_connection.read().await.unwrap().unwrap();
+        assert_eq!(None, user.email);
+
+        drop(client_connection);
+        assert!(server_connection
+            .read_with_schema_version::<User>()
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn read_prioritized_reads_higher_priorities_first() {
+        let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+
+        client_connection
+            .write_with_priority(&1u32, Priority::Low)
+            .await
+            .unwrap();
+        client_connection.write(&2u32).await.unwrap();
+        client_connection
+            .write_with_priority(&3u32, Priority::High)
+            .await
+            .unwrap();
+        client_connection
+            .write_with_priority(&4u32, Priority::Low)
+            .await
+            .unwrap();
+        client_connection
+            .write_with_priority(&5u32, Priority::Normal)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            Some(3),
+            server_connection
+                .read_prioritized::<u32>(Priority::Low)
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            Some(2),
+            server_connection
+                .read_prioritized::<u32>(Priority::Normal)
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            Some(5),
+            server_connection
+                .read_prioritized::<u32>(Priority::Normal)
+                .await
+                .unwrap()
+        );
+
+        // Skipped values wait for a later value of high enough priority
+        let writer = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            client_connection
+                .write_with_priority(&6u32, Priority::High)
+                .await
+                .unwrap();
+            client_connection
+        });
+        assert_eq!(
+            Some(6),
+            server_connection
+                .read_prioritized::<u32>(Priority::High)
+                .await
+                .unwrap()
+        );
+        let _client_connection = writer.await.unwrap();
+
+        // The skipped values are still read in order
+        assert_eq!(Some(1), server_connection.read::<u32>().await.unwrap());
+        assert_eq!(Some(4), server_connection.read::<u32>().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn read_prioritized_keeps_many_skipped_values_in_order() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024 * 1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+
+        for id in 0..10_000u32 {
+            client_connection
+                .write_with_priority(&id, Priority::Low)
+                .await
+                .unwrap();
+        }
+        client_connection
+            .write_with_priority(&u32::MAX, Priority::High)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            Some(u32::MAX),
+            server_connection
+                .read_prioritized::<u32>(Priority::High)
+                .await
+                .unwrap()
+        );
+        for id in 0..10_000u32 {
+            assert_eq!(
+                Some(id),
+                server_connection
+                    .read_prioritized::<u32>(Priority::Low)
+                    .await
+                    .unwrap()
+            );
+        }
+        drop(client_connection);
+        assert_eq!(None, server_connection.read::<u32>().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn middleware_transforms_payloads_in_order() {
+        /// Flips the bits set in the mask
+        struct Xor(u8);
+
+        impl Middleware for Xor {
+            fn on_write(&self, bytes: &mut Vec<u8>) {
+                bytes.iter_mut().for_each(|byte| *byte ^= self.0);
+            }
+
+            fn on_read(&self, bytes: &mut Vec<u8>) {
+                bytes.iter_mut().for_each(|byte| *byte ^= self.0);
+            }
+        }
+
+        /// Appends a marker byte, which must still be last when it is removed
+        struct Marker(u8);
+
+        impl Middleware for Marker {
+            fn on_write(&self, bytes: &mut Vec<u8>) {
+                bytes.push(self.0);
+            }
+
+            fn on_read(&self, bytes: &mut Vec<u8>) {
+                assert_eq!(Some(self.0), bytes.pop());
+            }
+        }
+
+        let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+        let mut client_connection = Connection::new(client_stream)
+            .with_middleware(Marker(1))
+            .with_middleware(Xor(0xAA));
+        let server_connection = Connection::new(server_stream)
+            .with_middleware(Marker(1))
+            .with_middleware(Xor(0xAA));
+
+        let message = TestMessage {
+            id: 1,
+            name: "middleware".to_string(),
+            payload: vec![1, 2, 3],
+        };
+        client_connection.write(&message).await.unwrap();
+        client_connection.write_raw(b"hi").await.unwrap();
+
+        // Both halves of a split connection keep the middleware
+        let (mut reader, _writer) = server_connection.split();
+        assert_eq!(Some(message), reader.read::<TestMessage>().await.unwrap());
+        assert_eq!(b"hi"[..], reader.read_raw().await.unwrap().unwrap());
+
+        // The marker is added before the payload is flipped
+        let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+        let mut client_connection = Connection::new(client_stream)
+            .with_middleware(Marker(1))
+            .with_middleware(Xor(0xAA));
+        let mut server_connection = Connection::new(server_stream);
+        client_connection.write_raw(b"hi").await.unwrap();
+        assert_eq!(
+            [b'h' ^ 0xAA, b'i' ^ 0xAA, 1 ^ 0xAA][..],
+            server_connection.read_raw().await.unwrap().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn shared_connections_accept_writes_from_many_tasks() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let client_connection = Connection::new(client_stream).into_shared();
+        let mut server_connection = Connection::new(server_stream);
+
+        let writers: Vec<_> = (0..100u32)
+            .map(|id| {
+                let client_connection = client_connection.clone();
+                tokio::spawn(async move { client_connection.write(&id).await.unwrap() })
+            })
+            .collect();
+
+        let mut ids = Vec::new();
+        for _ in 0..100 {
+            ids.push(server_connection.read::<u32>().await.unwrap().unwrap());
+        }
+        for writer in writers {
+            writer.await.unwrap();
+        }
+        ids.sort();
+        assert_eq!((0..100).collect::<Vec<u32>>(), ids);
+
+        // While another task holds the connection, try_write gives up and write times out
+        let mut client_connection = client_connection;
+        client_connection.set_lock_timeout(Some(Duration::from_millis(20)));
+        let other = client_connection.clone();
+        let guard = other.lock().await.unwrap();
+        assert!(client_connection.try_write(&100u32).await.is_none());
+        assert!(matches!(
+            client_connection.write(&100u32).await,
+            Err(ConnectionError::Timeout)
+        ));
+
+        drop(guard);
+        client_connection.try_write(&101u32).await.unwrap().unwrap();
+        assert_eq!(Some(101), server_connection.read::<u32>().await.unwrap());
+    }
+
+    #[cfg(feature = "rate-limit")]
+    #[tokio::test]
+    async fn rate_limited_connections_throttle_writes() {
+        let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+        let mut server_connection = Connection::new(server_stream);
+        let reader = tokio::spawn(async move {
+            let mut ids = Vec::new();
+            while let Some(id) = server_connection.read::<u32>().await.unwrap() {
+                ids.push(id);
+            }
+            ids
+        });
+
+        let start = std::time::Instant::now();
+        let rate = std::num::NonZeroU32::new(100).unwrap();
+        let mut client_connection =
+            RateLimitedConnection::new(Connection::new(client_stream), rate);
+        for id in 0..100u32 {
+            client_connection.write(&id).await.unwrap();
+        }
+        let batch: Vec<u32> = (100..200).collect();
+        assert_eq!(100, client_connection.write_batch(&batch).await.unwrap());
+        assert!(start.elapsed() >= Duration::from_secs(1));
+
+        drop(client_connection);
+        assert_eq!((0..200).collect::<Vec<u32>>(), reader.await.unwrap());
+    }
+
+    #[cfg(feature = "rate-limit")]
+    #[tokio::test]
+    async fn rate_limited_batch_is_not_sent_in_a_burst() {
+        let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+        let mut server_connection = Connection::new(server_stream);
+        let rate = std::num::NonZeroU32::new(10).unwrap();
+        let mut client_connection =
+            RateLimitedConnection::new(Connection::new(client_stream), rate);
+        let writer =
+            tokio::spawn(async move { client_connection.write_batch(&[1u32, 2, 3, 4, 5]).await });
+
+        // The first value arrives long before the last one is permitted
+        let first =
+            tokio::time::timeout(Duration::from_millis(200), server_connection.read::<u32>());
+        assert_eq!(Some(1), first.await.unwrap().unwrap());
+        assert!(!writer.is_finished());
+        assert_eq!(5, writer.await.unwrap().unwrap());
+    }
+
+    #[tokio::test]
+    async fn read_all_collects_messages_until_the_peer_closes() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut server_connection = Connection::new(server_stream);
+        drop(client_stream);
+        assert!(server_connection
+            .read_all::<u32>()
+            .await
+            .unwrap()
+            .is_empty());
+
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+        for id in 1..=3u32 {
+            client_connection.write(&id).await.unwrap();
+        }
+        drop(client_connection);
+        assert_eq!(
+            vec![1, 2, 3],
+            server_connection.read_all::<u32>().await.unwrap()
+        );
+
+        // The third message is cut short, which fails the whole read
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+        client_connection.write(&1u32).await.unwrap();
+        client_connection.write(&2u32).await.unwrap();
+        let (mut client_stream, _) = client_connection.into_inner().await.unwrap();
+        client_stream
+            .write_all(&[0, 0, 0, 4, 0, 0, 0, 0, 3])
+            .await
+            .unwrap();
+        drop(client_stream);
+        assert!(matches!(
+            server_connection.read_all::<u32>().await,
+            Err(ConnectionError::UnexpectedEof)
+        ));
+    }
+
+    #[tokio::test]
+    async fn read_latest_skips_superseded_values() {
+        let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+
+        for reading in 1..=5u32 {
+            client_connection.write_overwriting(&reading).await.unwrap();
+        }
+        assert_eq!(
+            Some(5),
+            server_connection.read_latest::<u32>().await.unwrap()
+        );
+        assert_eq!(5, server_connection.stats().messages_received);
+
+        // Values written without the flag are never skipped
+        client_connection.write_overwriting(&6u32).await.unwrap();
+        client_connection.write(&7u32).await.unwrap();
+        client_connection.write_overwriting(&8u32).await.unwrap();
+        client_connection.write_overwriting(&9u32).await.unwrap();
+        assert_eq!(
+            Some(7),
+            server_connection.read_latest::<u32>().await.unwrap()
+        );
+        assert_eq!(
+            Some(9),
+            server_connection.read_latest::<u32>().await.unwrap()
+        );
+
+        // A plain read receives overwritable values in order
+        client_connection.write_overwriting(&10u32).await.unwrap();
+        client_connection.write_overwriting(&11u32).await.unwrap();
+        assert_eq!(Some(10), server_connection.read::<u32>().await.unwrap());
+        assert_eq!(Some(11), server_connection.read::<u32>().await.unwrap());
+
+        // The next value is awaited if nothing has arrived yet
+        let writer = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            client_connection.write_overwriting(&12u32).await.unwrap();
+        });
+        assert_eq!(
+            Some(12),
+            server_connection.read_latest::<u32>().await.unwrap()
+        );
+        writer.await.unwrap();
+        assert_eq!(None, server_connection.read_latest::<u32>().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn write_vectored_sends_one_frame() {
+        use std::io::IoSlice;
+
+        for compression in [CompressionMode::None, CompressionMode::Zstd(3)] {
+            let (client_stream, server_stream) = tokio::io::duplex(1024);
+            let mut client_connection = Connection::new(client_stream);
+            client_connection.set_compression(compression);
+            let mut server_connection = Connection::new(server_stream);
+
+            // A serialized value split across segments reads back as the value
+            let payload = bincode::serialize("Hello, world!").unwrap();
+            let (head, tail) = payload.split_at(3);
+            let bufs = [IoSlice::new(head), IoSlice::new(&[]), IoSlice::new(tail)];
+            let written = client_connection.write_vectored(&bufs).await.unwrap();
+            assert_eq!(payload.len(), written);
+            let message: String = server_connection.read().await.unwrap().unwrap();
+            assert_eq!("Hello, world!", message);
+
+            // A payload larger than the duplex buffer is written in several calls
+            let body = vec![7u8; 10_000];
+            let writer = tokio::spawn(async move {
+                let bufs = [IoSlice::new(b"head"), IoSlice::new(&body)];
+                client_connection.write_vectored(&bufs).await.unwrap()
+            });
+            let payload = server_connection.read_raw().await.unwrap().unwrap();
+            assert_eq!(10_004, writer.await.unwrap());
+            assert_eq!(&b"head"[..], &payload[..4]);
+            assert!(payload[4..].iter().all(|byte| *byte == 7));
+            assert_eq!(10_004, payload.len());
+        }
+    }
+
+    #[tokio::test]
+    async fn write_stream_sends_payloads_larger_than_the_message_limit() {
+        let data: Vec<u8> = (0..10 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+        let mut client_connection = Connection::new(client_stream);
+        client_connection.set_max_message_size(64 * 1024);
+        let mut server_connection = Connection::new(server_stream);
+        server_connection.set_max_message_size(64 * 1024);
+
+        let source = data.clone();
+        let writer = tokio::spawn(async move {
+            let mut reader = &source[..];
+            let sent = client_connection
+                .write_stream(&mut reader, 64 * 1024)
+                .await
+                .unwrap();
+            client_connection.write(&"after").await.unwrap();
+            (sent, client_connection)
+        });
+
+        let mut received = Vec::new();
+        let n = server_connection.read_stream(&mut received).await.unwrap();
+        let (sent, mut client_connection) = writer.await.unwrap();
+        assert_eq!(data.len() as u64, sent);
+        assert_eq!(data.len() as u64, n);
+        assert!(received == data);
+        // An exact multiple of the chunk size ends with an empty chunk
+        assert_eq!(161, server_connection.stats().messages_received);
+
+        // Messages after the stream are read as usual
+        assert_eq!(
+            Some("after".to_string()),
+            server_connection.read().await.unwrap()
+        );
+
+        // A chunk cannot be read as a message
+        let mut reader = &b"more than four bytes"[..];
+        client_connection
+            .write_stream(&mut reader, 4)
+            .await
+            .unwrap();
+        assert!(server_connection.read_raw().await.is_err());
+
+        let result = client_connection.write_stream(&mut &b""[..], 0).await;
+        assert!(matches!(
+            result,
+            Err(ConnectionError::InvalidConfiguration(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn codec_functions_match_the_connection_wire_format() {
+        let message = TestMessage {
+            id: 3,
+            name: "Codec".to_string(),
+            payload: vec![4, 5, 6],
+        };
+
+        // Frames encoded standalone decode standalone, one after the other
+        let mut frames = connection::codec::encode(&message).unwrap().to_vec();
+        frames.extend_from_slice(&connection::codec::encode(&7u32).unwrap());
+        let (decoded, consumed) = connection::codec::decode::<TestMessage>(&frames).unwrap();
+        assert_eq!(message, decoded);
+        assert_eq!(
+            (7u32, frames.len() - consumed),
+            connection::codec::decode(&frames[consumed..]).unwrap()
+        );
+        assert!(connection::codec::decode::<u32>(&frames[..consumed - 1]).is_err());
+
+        // A connection reads what encode produces, and decode reads what a connection writes
+        let (mut client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut server_connection = Connection::new(server_stream);
+        client_stream.write_all(&frames).await.unwrap();
+        assert_eq!(Some(message), server_connection.read().await.unwrap());
+        server_connection.write(&"reply").await.unwrap();
+        let mut written = vec![0u8; 8 + 13];
+        client_stream.read_exact(&mut written).await.unwrap();
+        assert_eq!(
+            ("reply".to_string(), written.len()),
+            connection::codec::decode::<String>(&written).unwrap()
+        );
+    }
+
+    #[test]
+    fn frame_iterator_splits_a_capture_into_frames() {
+        use connection::codec::FrameIterator;
+
+        // Two encoded values and a hand-made compressed frame, with a ping in between
+        let mut capture = connection::codec::encode(&"first").unwrap().to_vec();
+        capture.extend_from_slice(&[0xFF; 4]);
+        capture.extend_from_slice(&connection::codec::encode(&2u32).unwrap());
+        let header = FrameHeader {
+            compression: 1,
+            ..FrameHeader::new(3, 0b1000)
+        };
+        capture.extend_from_slice(&header.to_bytes());
+        capture.extend_from_slice(&[7, 8, 9]);
+
+        let frames = FrameIterator::new(&capture)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(3, frames.len());
+        assert_eq!(FrameHeader::new(13, 0), frames[0].header);
+        assert_eq!(
+            bincode::serialize("first").unwrap(),
+            frames[0].payload.to_vec()
+        );
+        assert_eq!(&2u32.to_le_bytes(), frames[1].payload);
+        assert_eq!(header, frames[2].header);
+        assert_eq!(&[7, 8, 9], frames[2].payload);
+
+        // A capture cut short ends with an error
+        let mut frames = FrameIterator::new(&capture[..capture.len() - 1]);
+        assert!(frames.next().unwrap().is_ok());
+        assert!(frames.next().unwrap().is_ok());
+        assert!(matches!(
+            frames.next(),
+            Some(Err(ConnectionError::UnexpectedEof))
+        ));
+        assert!(frames.next().is_none());
+        assert!(frames.remaining().is_empty());
+    }
+
+    #[tokio::test]
+    async fn framed_codec_interoperates_with_connection() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let codec = LengthDelimitedConnectionCodec::new(SerdeFormat::Json);
+        let mut client_framed = Framed::new(client_stream, codec);
+        let mut server_connection = Connection::new_with_format(server_stream, SerdeFormat::Json);
+
+        client_framed.send(&"hello".to_string()).await.unwrap();
+        let message: String = server_connection.read().await.unwrap().unwrap();
+        assert_eq!("hello", message);
+
+        server_connection.write(&42u32).await.unwrap();
+        let payload = client_framed.next().await.unwrap().unwrap();
+        assert_eq!(42u32, serde_json::from_slice::<u32>(&payload).unwrap());
+    }
+
+    #[tokio::test]
+    async fn framed_conversions_keep_buffered_bytes() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+
+        // Two frames arrive together, but only the first is read before converting
+        server_connection.write_no_flush(&1u32).await.unwrap();
+        server_connection.write(&2u32).await.unwrap();
+        assert_eq!(Some(1u32), client_connection.read().await.unwrap());
+
+        // An unflushed write survives the conversion as well
+        client_connection.write_no_flush(&3u32).await.unwrap();
+        let mut client_framed = client_connection.into_framed();
+        let payload = client_framed.next().await.unwrap().unwrap();
+        assert_eq!(2u32, bincode::deserialize::<u32>(&payload).unwrap());
+
+        SinkExt::<u32>::flush(&mut client_framed).await.unwrap();
+        assert_eq!(Some(3u32), server_connection.read().await.unwrap());
+
+        let mut client_connection = Connection::from_framed(client_framed);
+        server_connection.write(&4u32).await.unwrap();
+        assert_eq!(Some(4u32), client_connection.read().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn partial_frame_is_not_parsed_as_a_shorter_value() {
+        let (mut client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut server_connection = Connection::new(server_stream);
+
+        // The first half of an 8-byte payload is a valid bincode u32 on its own
+        client_stream
+            .write_all(&[0, 0, 0, 8, 0, 0, 0, 0])
+            .await
+            .unwrap();
+        client_stream.write_all(&7u32.to_le_bytes()).await.unwrap();
+        assert_eq!(None, server_connection.try_read::<u32>().await.unwrap());
+
+        client_stream.write_all(&0u32.to_le_bytes()).await.unwrap();
+        assert_eq!(Some(7), server_connection.try_read::<u64>().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn frame_of_another_type_is_rejected_and_skipped() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+
+        client_connection.write(&7u64).await.unwrap();
+        client_connection.write(&8u32).await.unwrap();
+
+        let result = server_connection.read::<u32>().await;
+        assert!(matches!(result, Err(ConnectionError::BincodeError(_))));
+        assert_eq!(Some(8u32), server_connection.read().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn relay_forwards_frames_verbatim() {
+        let (client_stream, relay_in_stream) = tokio::io::duplex(64 * 1024);
+        let (relay_out_stream, server_stream) = tokio::io::duplex(64 * 1024);
+        let mut client_connection = Connection::new(client_stream);
+        client_connection.set_compression(CompressionMode::Zstd(3));
+        let mut relay_in = Connection::new(relay_in_stream);
+        let mut relay_out = Connection::new(relay_out_stream);
+        let mut server_connection = Connection::new(server_stream);
+
+        let message = TestMessage {
+            id: 1,
+            name: "Relayed".to_string(),
+            payload: vec![7; 4096],
+        };
+        client_connection.write(&message).await.unwrap();
+        client_connection.write(&"Hello, world!").await.unwrap();
+        drop(client_connection);
+
+        let relayed = relay(&mut relay_in, &mut relay_out).await.unwrap();
+        let expected = bincode::serialize(&message).unwrap().len()
+            + bincode::serialize(&"Hello, world!").unwrap().len();
+        assert_eq!(expected as u64, relayed);
+
+        let received: TestMessage = server_connection.read().await.unwrap().unwrap();
+        assert_eq!(message, received);
+        let received: String = server_connection.read().await.unwrap().unwrap();
+        assert_eq!("Hello, world!", received);
+    }
+
+    #[tokio::test]
+    async fn sink_and_stream_halves_exchange_messages_concurrently() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let (mut client_sink, mut client_stream) =
+            Connection::new(client_stream).into_sink_and_stream::<u32, u32>();
+        let (mut server_sink, mut server_stream) =
+            Connection::new(server_stream).into_sink_and_stream::<u32, u32>();
+
+        // The server echoes every value back, doubled
+        let echo = tokio::spawn(async move {
+            while let Some(value) = server_stream.next().await {
+                server_sink.send(value.unwrap() * 2).await.unwrap();
+            }
+        });
+
+        let writer = tokio::spawn(async move {
+            for value in 0..100u32 {
+                client_sink.send(value).await.unwrap();
+            }
+            client_sink
+        });
+        let reader = tokio::spawn(async move {
+            let mut received = Vec::new();
+            while received.len() < 100 {
+                received.push(client_stream.next().await.unwrap().unwrap());
+            }
+            received
+        });
+
+        let expected: Vec<u32> = (0..100u32).map(|value| value * 2).collect();
+        assert_eq!(expected, reader.await.unwrap());
+
+        // Dropping both client halves closes the connection, which ends the echo task
+        drop(writer.await.unwrap());
+        echo.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn idle_timeout_closes_a_silent_connection() {
+        // The peer never sends anything
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+        server_connection.set_idle_timeout(Some(Duration::from_millis(50)));
+        assert_eq!(
+            Some(Duration::from_millis(50)),
+            server_connection.idle_timeout()
+        );
+
+        let start = std::time::Instant::now();
+        let result = server_connection.read::<String>().await;
+        assert!(matches!(result, Err(ConnectionError::IdleTimeout)));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // The connection stays closed, and the peer sees it close
+        let result = server_connection.write(&"too late").await;
+        assert!(matches!(result, Err(ConnectionError::IdleTimeout)));
+        assert_eq!(None, client_connection.read::<String>().await.unwrap());
+        assert_eq!(2, server_connection.stats().errors);
+    }
+
+    #[tokio::test]
+    async fn activity_restarts_the_idle_timer() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+        server_connection.set_idle_timeout(Some(Duration::from_millis(100)));
+
+        // Messages keep arriving more often than the idle timeout
+        for id in 0..5u32 {
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            client_connection.write(&id).await.unwrap();
+            assert_eq!(Some(id), server_connection.read().await.unwrap());
+        }
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        let result = server_connection.write(&"too late").await;
+        assert!(matches!(result, Err(ConnectionError::IdleTimeout)));
+    }
+
+    #[tokio::test]
+    async fn write_with_ack_waits_for_the_peer_to_read() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+
+        let server = tokio::spawn(async move {
+            let first: String = server_connection.read_with_ack().await.unwrap().unwrap();
+            let second: u32 = server_connection.read_with_ack().await.unwrap().unwrap();
+            (first, second)
+        });
+
+        client_connection
+            .write_with_ack("Hello, world!", Duration::from_secs(5))
+            .await
+            .unwrap();
+        client_connection
+            .write_with_ack(&7u32, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(("Hello, world!".to_string(), 7), server.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn write_with_ack_times_out_without_an_ack() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+
+        let result = client_connection
+            .write_with_ack(&1u32, Duration::from_millis(50))
+            .await;
+        assert!(matches!(result, Err(ConnectionError::Timeout)));
+
+        // The late acknowledgment is skipped by a plain read
+        assert_eq!(Some(1u32), server_connection.read_with_ack().await.unwrap());
+        server_connection.write(&2u32).await.unwrap();
+        assert_eq!(Some(2u32), client_connection.read().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn guard_says_goodbye_on_drop() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream).into_guarded("goodbye");
+        let mut server_connection = Connection::new(server_stream);
+
+        client_connection.write(&"Hello, world!").await.unwrap();
+        drop(client_connection);
+
+        let message: String = server_connection.read().await.unwrap().unwrap();
+        assert_eq!("Hello, world!", message);
+        let message: String = server_connection.read().await.unwrap().unwrap();
+        assert_eq!("goodbye", message);
+        assert_eq!(None, server_connection.read::<String>().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn guard_into_inner_skips_the_goodbye() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let guard = Connection::new(client_stream).into_guarded("goodbye");
+        let mut server_connection = Connection::new(server_stream);
+
+        let mut client_connection = guard.into_inner();
+        client_connection.write(&1u32).await.unwrap();
+        drop(client_connection);
+
+        assert_eq!(Some(1u32), server_connection.read().await.unwrap());
+        assert_eq!(None, server_connection.read::<String>().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn peek_does_not_consume_the_message() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+
+        client_connection.write(&"first").await.unwrap();
+        client_connection.write(&"second").await.unwrap();
+
+        let peeked: Option<String> = server_connection.peek().await.unwrap();
+        assert_eq!(Some("first".to_string()), peeked);
+        let peeked: Option<String> = server_connection.peek().await.unwrap();
+        assert_eq!(Some("first".to_string()), peeked);
+        let read: Option<String> = server_connection.read().await.unwrap();
+        assert_eq!(Some("first".to_string()), read);
+
+        // A failed peek leaves the message for a read of the right type
+        assert!(server_connection.peek::<u32>().await.is_err());
+        let read: Option<String> = server_connection.read().await.unwrap();
+        assert_eq!(Some("second".to_string()), read);
+        assert_eq!(1, server_connection.stats().errors);
+        assert_eq!(2, server_connection.stats().messages_received);
+
+        drop(client_connection);
+        assert_eq!(None, server_connection.peek::<String>().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn reading_a_u32_as_a_string_fails_instead_of_hanging() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+
+        client_connection.write(&42u32).await.unwrap();
+
+        let result =
+            tokio::time::timeout(Duration::from_secs(5), server_connection.read::<String>())
+                .await
+                .expect("read should fail rather than wait for more bytes");
+        assert!(matches!(result, Err(ConnectionError::BincodeError(_))));
+    }
+
+    #[tokio::test]
+    async fn buffer_size_can_be_inspected_and_grown() {
+        let (mut client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut server_connection = Connection::new_with_capacity(server_stream, 64);
+        assert!(server_connection.buffer_capacity() >= 64);
+        assert_eq!(0, server_connection.buffer_len());
+
+        client_stream.write_all(&[0, 0, 0, 8, 0, 1]).await.unwrap();
+        assert_eq!(None, server_connection.try_read::<u64>().await.unwrap());
+        assert_eq!(6, server_connection.buffer_len());
+
+        server_connection.set_min_buffer_capacity(4096);
+        assert!(server_connection.buffer_capacity() >= 4096);
+        assert_eq!(6, server_connection.buffer_len());
+
+        let capacity = server_connection.buffer_capacity();
+        server_connection.set_min_buffer_capacity(16);
+        assert_eq!(capacity, server_connection.buffer_capacity());
+    }
+
+    #[tokio::test]
+    async fn into_inner_returns_stream_and_unread_bytes() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+
+        // Two frames arrive together, but only the first is read
+        server_connection.write_no_flush(&1u32).await.unwrap();
+        server_connection.write(&2u32).await.unwrap();
+        assert_eq!(Some(1u32), client_connection.read().await.unwrap());
+
+        client_connection.write_no_flush(&3u32).await.unwrap();
+        let (mut stream, unread) = client_connection.into_inner().await.unwrap();
+        assert_eq!(&[0, 0, 0, 4, 0, 0, 0, 0, 2, 0, 0, 0][..], &unread[..]);
+
+        // The unflushed write was delivered before the stream was returned
+        assert_eq!(Some(3u32), server_connection.read().await.unwrap());
+
+        // The stream can still be used directly
+        stream
+            .write_all(&[0, 0, 0, 4, 0, 0, 0, 0, 5, 0, 0, 0])
+            .await
+            .unwrap();
+        assert_eq!(Some(5u32), server_connection.read().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn connection_reads_and_writes_raw_bytes() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::from(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+
+        // Bytes buffered behind a message are read before the stream itself
+        server_connection.write_no_flush(&1u32).await.unwrap();
+        server_connection.write_no_flush(&2u32).await.unwrap();
+        AsyncWriteExt::write_all(&mut server_connection, b"tail")
+            .await
+            .unwrap();
+        AsyncWriteExt::shutdown(&mut server_connection)
+            .await
+            .unwrap();
+        assert_eq!(Some(1u32), client_connection.read().await.unwrap());
+
+        let mut rest = Vec::new();
+        tokio::io::copy(&mut client_connection, &mut rest)
+            .await
+            .unwrap();
+        assert_eq!(
+            &[0, 0, 0, 4, 0, 0, 0, 0, 2, 0, 0, 0, b't', b'a', b'i', b'l'][..],
+            &rest[..]
+        );
+    }
+
+    #[tokio::test]
+    async fn connection_converts_to_and_from_tcp_stream() {
+        let (server_listener, client_connection) = setup().await;
+        let server_connection: Connection = server_listener.accept().await.unwrap().0.into();
+
+        let stream: tokio::net::TcpStream = client_connection.into();
+        let mut client_connection = Connection::from(stream);
+        client_connection.write(&"Hello").await.unwrap();
+
+        let mut server_stream = tokio::net::TcpStream::from(server_connection);
+        let mut header = [0u8; FrameHeader::SIZE];
+        server_stream.read_exact(&mut header).await.unwrap();
+        let header = FrameHeader::from_bytes(header);
+        assert_eq!(0, header.flags);
+        let mut payload = vec![0u8; header.length as usize];
+        server_stream.read_exact(&mut payload).await.unwrap();
+        assert_eq!(bincode::serialize("Hello").unwrap(), payload);
+    }
+
+    #[tokio::test]
+    async fn stream_yields_messages_until_peer_closes() {
+        let (server_listener, mut client_connection) = setup().await;
+        for id in 0..5u32 {
+            client_connection.write(&id).await.unwrap();
+        }
+        drop(client_connection);
+
+        let server_connection = Connection::new(server_listener.accept().await.unwrap().0);
+        let ids: Vec<u32> = server_connection
+            .into_stream::<u32>()
+            .map(|id| id.unwrap())
+            .filter(|id| futures::future::ready(id % 2 == 0))
+            .collect()
+            .await;
+        assert_eq!(vec![0, 2, 4], ids);
+    }
+
+    #[tokio::test]
+    async fn sink_forwards_channel_into_connection() {
+        let (server_listener, client_connection) = setup().await;
+        let (mut tx, rx) = futures::channel::mpsc::channel::<u32>(16);
+
+        let forward = tokio::spawn(async move {
+            let mut client_connection = client_connection;
+            client_connection.set_max_in_flight_bytes(16);
+            rx.map(Ok).forward(&mut client_connection).await.unwrap();
+        });
+
+        for id in 0..50u32 {
+            tx.send(id).await.unwrap();
+        }
+        drop(tx);
+        forward.await.unwrap();
+
+        let mut server_connection = Connection::new(server_listener.accept().await.unwrap().0);
+        for id in 0..50u32 {
+            assert_eq!(id, server_connection.read::<u32>().await.unwrap().unwrap());
+        }
+        assert!(server_connection.read::<u32>().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn write_sends_frames_queued_by_sink_first() {
+        let (server_listener, mut client_connection) = setup().await;
+        client_connection.feed(1u32).await.unwrap();
+        client_connection.write(&2u32).await.unwrap();
+
+        let mut server_connection = Connection::new(server_listener.accept().await.unwrap().0);
+        assert_eq!(1, server_connection.read::<u32>().await.unwrap().unwrap());
+        assert_eq!(2, server_connection.read::<u32>().await.unwrap().unwrap());
+    }
+
+    #[tokio::test]
+    async fn read_times_out_when_peer_stalls() {
+        let (server_listener, _client_connection) = setup().await;
+        let mut server_connection = Connection::new(server_listener.accept().await.unwrap().0);
+        server_connection.set_read_timeout(Some(Duration::from_millis(50)));
+        assert_eq!(
+            Some(Duration::from_millis(50)),
+            server_connection.read_timeout()
+        );
+
+        let start = std::time::Instant::now();
+        let result = server_connection.read::<String>().await;
+        assert!(matches!(result, Err(ConnectionError::Timeout)));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn receive_timeout_applies_to_a_single_read() {
+        let (server_listener, mut client_connection) = setup().await;
+        let mut server_connection = Connection::new(server_listener.accept().await.unwrap().0);
+
+        let start = std::time::Instant::now();
+        let result = server_connection
+            .receive_timeout::<String>(Duration::from_millis(50))
+            .await;
+        assert!(matches!(result, Err(ConnectionError::Timeout)));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(None, server_connection.read_timeout());
+
+        client_connection.write(&"Hello, world!").await.unwrap();
+        let message: String = server_connection
+            .receive_timeout(Duration::from_secs(5))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!("Hello, world!", message);
+    }
+
+    #[tokio::test]
+    async fn deadlines_are_absolute() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+
+        let deadline = tokio::time::Instant::now() + Duration::from_millis(100);
+        client_connection
+            .write_before(&"Hello, world!", deadline)
+            .await
+            .unwrap();
+        let message: String = server_connection
+            .read_before(deadline)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!("Hello, world!", message);
+
+        // The second read shares what is left of the deadline rather than starting over
+        let result = server_connection.read_before::<String>(deadline).await;
+        assert!(matches!(result, Err(ConnectionError::Timeout)));
+        assert!(tokio::time::Instant::now() >= deadline);
+
+        let result = server_connection.read_before::<String>(deadline).await;
+        assert!(matches!(result, Err(ConnectionError::Timeout)));
+    }
+
+    #[cfg(feature = "error-hook")]
+    #[tokio::test]
+    async fn error_hook_sees_every_error_once() {
+        let (server_listener, _client_connection) = setup().await;
+        let mut server_connection = Connection::new(server_listener.accept().await.unwrap().0);
+
+        let calls = Arc::new(AtomicU32::new(0));
+        let counter = calls.clone();
+        server_connection.set_error_hook(move |e| {
+            assert!(matches!(e, ConnectionError::Timeout));
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        for _ in 0..2 {
+            let result = server_connection
+                .receive_timeout::<String>(Duration::from_millis(10))
+                .await;
+            assert!(matches!(result, Err(ConnectionError::Timeout)));
+        }
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+        assert_eq!(2, server_connection.stats().errors);
+    }
+
+    #[cfg(feature = "error-hook")]
+    #[tokio::test]
+    async fn error_hook_sees_sink_errors() {
+        use std::pin::Pin;
+        use std::task::{Context, Poll};
+        use tokio::io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf};
+
+        /// Accepts every write but fails every flush and shutdown
+        struct FailFlushes(DuplexStream);
+
+        impl AsyncRead for FailFlushes {
+            fn poll_read(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &mut ReadBuf<'_>,
+            ) -> Poll<std::io::Result<()>> {
+                Pin::new(&mut self.0).poll_read(cx, buf)
+            }
+        }
+
+        impl AsyncWrite for FailFlushes {
+            fn poll_write(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &[u8],
+            ) -> Poll<std::io::Result<usize>> {
+                Pin::new(&mut self.0).poll_write(cx, buf)
+            }
+
+            fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+                Poll::Ready(Err(std::io::ErrorKind::BrokenPipe.into()))
+            }
+
+            fn poll_shutdown(
+                self: Pin<&mut Self>,
+                _: &mut Context<'_>,
+            ) -> Poll<std::io::Result<()>> {
+                Poll::Ready(Err(std::io::ErrorKind::BrokenPipe.into()))
+            }
+        }
+
+        let (client_stream, _server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(FailFlushes(client_stream));
+        let calls = Arc::new(AtomicU32::new(0));
+        let counter = calls.clone();
+        client_connection.set_error_hook(move |e| {
+            assert!(matches!(e, ConnectionError::IoError(_)));
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        assert!(client_connection.send(1u32).await.is_err());
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+        assert!(SinkExt::<u32>::close(&mut client_connection).await.is_err());
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+        assert_eq!(2, client_connection.stats().errors);
+    }
+
+    #[cfg(feature = "error-hook")]
+    #[tokio::test]
+    async fn panicking_error_hook_does_not_break_the_connection() {
+        let (server_listener, mut client_connection) = setup().await;
+        let mut server_connection = Connection::new(server_listener.accept().await.unwrap().0);
+        server_connection.set_error_hook(|_| panic!("error hook panicked"));
+
+        let result = server_connection
+            .receive_timeout::<String>(Duration::from_millis(10))
+            .await;
+        assert!(matches!(result, Err(ConnectionError::Timeout)));
+
+        client_connection.write(&"Hello, world!").await.unwrap();
+        let message: String = server_connection.read().await.unwrap().unwrap();
+        assert_eq!("Hello, world!", message);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn metrics_are_exported_per_connection_name() {
+        let handle = metrics_exporter_prometheus::PrometheusBuilder::new()
+            .install_recorder()
+            .unwrap();
+        // Other tests run concurrently, so only the series of these named connections are checked
+        let metric = |name: &str, labels: &str| {
+            let series = format!("{name}{{{labels}}} ");
+            handle
+                .render()
+                .lines()
+                .find_map(|line| line.strip_prefix(series.as_str()).map(str::to_string))
+        };
+
+        let (server_listener, client_stream) = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let stream = tokio::net::TcpStream::connect(listener.local_addr().unwrap());
+            (listener, stream.await.unwrap())
+        };
+        let mut client_connection = ConnectionBuilder::new()
+            .name("metrics-client")
+            .build(client_stream)
+            .unwrap();
+        let server_stream = server_listener.accept().await.unwrap().0;
+        let mut server_connection = ConnectionBuilder::new()
+            .name("metrics-server")
+            .build(server_stream)
+            .unwrap();
+        let client = r#"connection_name="metrics-client""#;
+        let server = r#"connection_name="metrics-server""#;
+        assert_eq!(Some("1".into()), metric("connection_active_count", client));
+
+        // A bincode string is an 8 byte length followed by its bytes, plus an 8 byte frame header
+        client_connection.write(&"hello").await.unwrap();
+        client_connection.write(&"world").await.unwrap();
+        for _ in 0..2 {
+            server_connection.read::<String>().await.unwrap();
+        }
+        assert_eq!(
+            Some("42".into()),
+            metric("connection_bytes_sent_total", client)
+        );
+        assert_eq!(
+            Some("2".into()),
+            metric("connection_messages_sent_total", client)
+        );
+        assert_eq!(
+            Some("42".into()),
+            metric("connection_bytes_received_total", server)
+        );
+        assert_eq!(
+            Some("2".into()),
+            metric("connection_messages_received_total", server)
+        );
+
+        let result = server_connection
+            .receive_timeout::<String>(Duration::from_millis(10))
+            .await;
+        assert!(matches!(result, Err(ConnectionError::Timeout)));
+        let errors = format!(r#"{server},kind="timeout""#);
+        assert_eq!(Some("1".into()), metric("connection_errors_total", &errors));
+
+        drop(client_connection);
+        assert_eq!(Some("0".into()), metric("connection_active_count", client));
+    }
+
+    #[tokio::test]
+    async fn write_erased_sends_heterogeneous_values() {
+        for format in [SerdeFormat::Bincode, SerdeFormat::Json] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let mut client_connection = Connection::dial_with_format(addr, format).await.unwrap();
+            let stream = listener.accept().await.unwrap().0;
+            let mut server_connection = Connection::new_with_format(stream, format);
+
+            let message = TestMessage {
+                id: 7,
+                name: "Erased".to_string(),
+                payload: vec![1, 2, 3],
+            };
+            let outgoing: Vec<Box<dyn connection::erased_serde::Serialize>> = vec![
+                Box::new("Hello, world!".to_string()),
+                Box::new(42u64),
+                Box::new(message),
+            ];
+            for value in &outgoing {
+                client_connection
+                    .write_erased(value.as_ref())
+                    .await
+                    .unwrap();
+            }
+
+            let text: String = server_connection.read().await.unwrap().unwrap();
+            assert_eq!("Hello, world!", text);
+            let number: u64 = server_connection.read().await.unwrap().unwrap();
+            assert_eq!(42, number);
+            let message: TestMessage = server_connection.read().await.unwrap().unwrap();
+            assert_eq!("Erased", message.name);
+            assert_eq!(vec![1, 2, 3], message.payload);
+        }
+    }
+
+    #[tokio::test]
+    async fn drain_discards_partial_frames() {
+        let (server_listener, mut client_connection) = setup().await;
+        let mut server_connection = Connection::new(server_listener.accept().await.unwrap().0);
+
+        // Buffer the start of a frame that is never completed
+        client_connection
+            .write_all(&[0, 0, 0, 10, 1, 2])
+            .await
+            .unwrap();
+        client_connection.flush().await.unwrap();
+        let result = server_connection
+            .receive_timeout::<String>(Duration::from_millis(50))
+            .await;
+        assert!(matches!(result, Err(ConnectionError::Timeout)));
+
+        // Leave more garbage waiting on the socket
+        client_connection.write_all(&[3, 4, 5]).await.unwrap();
+        client_connection.flush().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        server_connection.drain().await.unwrap();
+
+        client_connection.write(&"Hello, world!").await.unwrap();
+        let message: String = server_connection.read().await.unwrap().unwrap();
+        assert_eq!("Hello, world!", message);
+        server_connection.write(&message).await.unwrap();
+        let echoed: String = client_connection.read().await.unwrap().unwrap();
+        assert_eq!("Hello, world!", echoed);
+    }
+
+    #[tokio::test]
+    async fn write_times_out_when_peer_stops_reading() {
+        let (server_listener, mut client_connection) = setup().await;
+        // The server accepts the connection but never reads from it
+        let _stalled_stream = server_listener.accept().await.unwrap().0;
+        client_connection.set_write_timeout(Some(Duration::from_millis(50)));
+
+        let payload = vec![0u8; 1024 * 1024];
+        let mut result = Ok(());
+        for _ in 0..64 {
+            result = client_connection.write(&payload).await;
+            if result.is_err() {
+                break;
+            }
+        }
+        // Part of the payload may have been sent, so the connection cannot be used anymore
+        let error = result.unwrap_err();
+        assert!(matches!(error, ConnectionError::WriteTimeout));
+        assert!(error.is_fatal());
+    }
+
+    #[cfg(feature = "test-helpers")]
+    #[tokio::test]
+    async fn mock_pair_exchanges_messages() {
+        let (mut client_connection, mut server_connection) = connection::mock::mock_pair();
+        let message = TestMessage {
+            id: 123,
+            name: "Test Message".to_string(),
+            payload: vec![1, 2, 3, 4, 5],
+        };
+
+        client_connection.write(&message).await.unwrap();
+        let parsed_message: TestMessage = server_connection.read().await.unwrap().unwrap();
+        assert_eq!(message, parsed_message);
+
+        server_connection.write(&"Hello, world!").await.unwrap();
+        let parsed_message: String = client_connection.read().await.unwrap().unwrap();
+        assert_eq!("Hello, world!", parsed_message);
+    }
+
+    #[cfg(feature = "test-helpers")]
+    #[tokio::test]
+    async fn mock_read_returns_none_after_peer_drops() {
+        let (client_connection, mut server_connection) = connection::mock::mock_pair();
+        drop(client_connection);
+        assert!(server_connection.read::<String>().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn write_and_read_msgpack_message() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut client_connection = Connection::dial_with_format(addr, SerdeFormat::MsgPack)
+            .await
+            .unwrap();
+        let message = TestMessage {
+            id: 123,
+            name: "Test Message".to_string(),
+            payload: vec![1, 2, 3, 4, 5],
+        };
+
+        client_connection.write(&message).await.unwrap();
+
+        let stream = listener.accept().await.unwrap().0;
+        let mut server_connection = Connection::new_with_format(stream, SerdeFormat::MsgPack);
+        let parsed_message: TestMessage = server_connection.read().await.unwrap().unwrap();
+        assert_eq!(message, parsed_message);
+    }
+
+    #[tokio::test]
+    async fn msgpack_ignores_unknown_fields() {
+        #[derive(Serialize)]
+        struct NewerMessage {
+            id: u32,
+            name: String,
+            payload: Vec<u8>,
+            priority: u8,
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut client_connection = Connection::dial_with_format(addr, SerdeFormat::MsgPack)
+            .await
+            .unwrap();
+        let message = NewerMessage {
+            id: 7,
+            name: "Newer Message".to_string(),
+            payload: vec![9],
+            priority: 1,
+        };
+
+        client_connection.write(&message).await.unwrap();
+
+        let stream = listener.accept().await.unwrap().0;
+        let mut server_connection = Connection::new_with_format(stream, SerdeFormat::MsgPack);
+        let parsed_message: TestMessage = server_connection.read().await.unwrap().unwrap();
+        assert_eq!(7, parsed_message.id);
+        assert_eq!("Newer Message", parsed_message.name);
+        assert_eq!(vec![9], parsed_message.payload);
+    }
+
+    #[tokio::test]
+    async fn write_batch_sends_all_messages() {
+        let (server_listener, mut client_connection) = setup().await;
+        let ids: Vec<u32> = (0..10).collect();
+        assert_eq!(10, client_connection.write_batch(&ids).await.unwrap());
+
+        let mut server_connection = Connection::new(server_listener.accept().await.unwrap().0);
+        for id in ids {
+            assert_eq!(id, server_connection.read::<u32>().await.unwrap().unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn write_many_sends_mixed_types_with_one_flush() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+
+        let written = client_connection
+            .write_many()
+            .push(&"HELLO".to_string())
+            .push(&7u16)
+            .push(&vec![1u8, 2, 3])
+            .flush()
+            .await
+            .unwrap();
+        assert_eq!(3, written);
+        assert_eq!(3, client_connection.stats().messages_sent);
+
+        let greeting: String = server_connection.read().await.unwrap().unwrap();
+        assert_eq!("HELLO", greeting);
+        assert_eq!(Some(7u16), server_connection.read().await.unwrap());
+        assert_eq!(
+            Some(vec![1u8, 2, 3]),
+            server_connection.read().await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn write_many_writes_nothing_if_a_value_fails_to_serialize() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new_with_format(client_stream, SerdeFormat::Json);
+        let mut server_connection = Connection::new_with_format(server_stream, SerdeFormat::Json);
+
+        // JSON objects only support string keys
+        let invalid = std::collections::HashMap::from([((1u8, 2u8), 3u8)]);
+        let result = client_connection
+            .write_many()
+            .push(&1u32)
+            .push(&invalid)
+            .push(&2u32)
+            .flush()
+            .await;
+        assert!(matches!(result, Err(ConnectionError::JsonError(_))));
+
+        client_connection.write(&3u32).await.unwrap();
+        assert_eq!(Some(3u32), server_connection.read().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn write_no_flush_is_sent_on_flush() {
+        let (server_listener, mut client_connection) = setup().await;
+        let mut server_connection = Connection::new(server_listener.accept().await.unwrap().0);
+        server_connection.set_read_timeout(Some(Duration::from_millis(50)));
+
+        client_connection.write_no_flush(&"Hello").await.unwrap();
+        assert!(matches!(
+            server_connection.read::<String>().await,
+            Err(ConnectionError::Timeout)
+        ));
+
+        client_connection.write_no_flush(&"world!").await.unwrap();
+        client_connection.flush().await.unwrap();
+        assert_eq!(
+            "Hello",
+            server_connection.read::<String>().await.unwrap().unwrap()
+        );
+        assert_eq!(
+            "world!",
+            server_connection.read::<String>().await.unwrap().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn reconnecting_connection_retries_after_reset() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            // The first connection is dropped part way through a frame
+            let mut stream = listener.accept().await.unwrap().0;
+            stream.write_all(&[0, 0]).await.unwrap();
+            drop(stream);
+
+            // The second connection behaves
+            let mut server_connection = Connection::new(listener.accept().await.unwrap().0);
+            server_connection.write(&"Hello, world!").await.unwrap();
+            server_connection
+        });
+
+        let reconnects = Arc::new(AtomicU32::new(0));
+        let counter = reconnects.clone();
+        let strategy = BackoffStrategy::Fixed(Duration::from_millis(10));
+        let mut client_connection = ReconnectingConnection::new(addr, strategy, 3);
+        client_connection.on_reconnect(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        let parsed_message: String = client_connection.read().await.unwrap().unwrap();
+        assert_eq!("Hello, world!", parsed_message);
+        assert_eq!(1, reconnects.load(Ordering::SeqCst));
+        assert!(client_connection.is_connected());
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reconnecting_connection_redials_after_clean_close() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            // The first connection sends a message, then closes on a frame boundary
+            let mut server_connection = Connection::new(listener.accept().await.unwrap().0);
+            server_connection.write(&"first").await.unwrap();
+            drop(server_connection);
+
+            let mut server_connection = Connection::new(listener.accept().await.unwrap().0);
+            server_connection.write(&"second").await.unwrap();
+            server_connection
+        });
+
+        let strategy = BackoffStrategy::Fixed(Duration::from_millis(10));
+        let mut client_connection = ReconnectingConnection::new(addr, strategy, 3);
+        let first: String = client_connection.read().await.unwrap().unwrap();
+        assert_eq!("first", first);
+        let second: String = client_connection.read().await.unwrap().unwrap();
+        assert_eq!("second", second);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reconnecting_connection_gives_up_after_max_attempts() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // Nothing is listening on the address anymore
+        drop(listener);
+
+        let strategy = BackoffStrategy::Fixed(Duration::from_millis(1));
+        let mut client_connection = ReconnectingConnection::new(addr, strategy, 2);
+        let result = client_connection.write(&"Hello, world!").await;
+        assert!(matches!(result, Err(ConnectionError::IoErrorContext(..))));
+        assert!(!client_connection.is_connected());
+    }
+
+    #[test]
+    fn backoff_strategy_delays() {
+        let fixed = BackoffStrategy::Fixed(Duration::from_millis(10));
+        assert_eq!(Duration::from_millis(10), fixed.delay(5));
+
+        let exponential = BackoffStrategy::Exponential {
+            base: Duration::from_millis(10),
+            max: Duration::from_millis(100),
+        };
+        assert_eq!(Duration::from_millis(10), exponential.delay(0));
+        assert_eq!(Duration::from_millis(40), exponential.delay(2));
+        assert_eq!(Duration::from_millis(100), exponential.delay(10));
+        assert_eq!(Duration::from_millis(100), exponential.delay(u32::MAX));
+
+        let jitter = BackoffStrategy::ExponentialWithJitter {
+            base: Duration::from_millis(10),
+            max: Duration::from_millis(100),
+        };
+        assert!(jitter.delay(2) <= Duration::from_millis(40));
+    }
+
+    #[tokio::test]
+    async fn dial_retry_waits_for_a_late_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        // The server only starts listening after the first few attempts have failed
+        let server = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let listener = TcpListener::bind(addr).await.unwrap();
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut server_connection = Connection::new(stream);
+            server_connection.read::<String>().await.unwrap()
+        });
+
+        let policy = RetryPolicy {
+            max_attempts: 50,
+            initial_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(50),
+            multiplier: 2.0,
+        };
+        let mut client_connection = Connection::dial_retry(addr, policy).await.unwrap();
+        client_connection.write(&"Hello, world!").await.unwrap();
+        assert_eq!(Some("Hello, world!".to_string()), server.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn dial_retry_gives_up_after_max_attempts() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            initial_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(1),
+            multiplier: 2.0,
+        };
+        let result = Connection::dial_retry(addr, policy).await;
+        match result {
+            Err(ConnectionError::MaxRetriesExceeded(e)) => {
+                assert!(matches!(*e, ConnectionError::IoErrorContext(..)))
+            }
+            _ => panic!("expected MaxRetriesExceeded"),
+        }
+
+        let policy = RetryPolicy {
+            max_attempts: 0,
+            ..policy
+        };
+        let result = Connection::dial_retry(addr, policy).await;
+        assert!(matches!(
+            result,
+            Err(ConnectionError::InvalidConfiguration(_))
+        ));
+    }
+
+    #[test]
+    fn retry_policy_delays() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(100),
+            multiplier: 3.0,
+        };
+        assert_eq!(Duration::from_millis(10), policy.delay(0));
+        assert_eq!(Duration::from_millis(90), policy.delay(2));
+        assert_eq!(Duration::from_millis(100), policy.delay(3));
+        assert_eq!(Duration::from_millis(100), policy.delay(u32::MAX));
+    }
+
+    #[tokio::test]
+    async fn socket_options_can_be_changed_after_construction() {
+        let (_server_listener, client_connection) = setup().await;
+
+        client_connection.set_nodelay(true).unwrap();
+        assert!(client_connection.nodelay().unwrap());
+        client_connection.set_nodelay(false).unwrap();
+        assert!(!client_connection.nodelay().unwrap());
+
+        client_connection.set_ttl(42).unwrap();
+        assert_eq!(42, client_connection.ttl().unwrap());
+
+        // IPv6 sockets set their hop limit instead
+        let listener = TcpListener::bind("[::1]:0").await.unwrap();
+        let v6_connection = Connection::dial(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        v6_connection.set_ttl(7).unwrap();
+        assert_eq!(7, v6_connection.ttl().unwrap());
+
+        client_connection
+            .set_keepalive(Some(Duration::from_secs(60)))
+            .unwrap();
+        let (stream, _) = client_connection.into_inner().await.unwrap();
+        let socket = socket2::SockRef::from(&stream);
+        assert!(socket.keepalive().unwrap());
+
+        let client_connection = Connection::new(stream);
+        client_connection.set_keepalive(None).unwrap();
+        let (stream, _) = client_connection.into_inner().await.unwrap();
+        assert!(!socket2::SockRef::from(&stream).keepalive().unwrap());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn socket_buffer_sizes_can_be_changed() {
+        let (_server_listener, client_connection) = setup().await;
+
+        client_connection.set_recv_buffer_size(256 * 1024).unwrap();
+        assert!(client_connection.recv_buffer_size().unwrap() >= 256 * 1024);
+
+        client_connection.set_send_buffer_size(256 * 1024).unwrap();
+        assert!(client_connection.send_buffer_size().unwrap() >= 256 * 1024);
+    }
+
+    // Every address in 127.0.0.0/8 is local on Linux, which stands in for a second interface
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn dial_from_binds_the_local_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut client_connection = Connection::dial_from("127.0.0.2:0", addr).await.unwrap();
+        let (stream, peer_addr) = listener.accept().await.unwrap();
+        let mut server_connection = Connection::new(stream);
+        assert_eq!(
+            "127.0.0.2".parse::<std::net::IpAddr>().unwrap(),
+            peer_addr.ip()
+        );
+        assert_eq!(peer_addr, client_connection.local_addr().unwrap());
+
+        client_connection.write(&"Hello, world!").await.unwrap();
+        let message: String = server_connection.read().await.unwrap().unwrap();
+        assert_eq!("Hello, world!", message);
+
+        let result = Connection::dial_from("[::1]:0", addr).await;
+        assert!(matches!(result, Err(ConnectionError::IoError(_))));
+    }
+
+    #[tokio::test]
+    async fn connection_from_a_blocking_std_stream() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut client_connection = Connection::dial(addr).await.unwrap();
+
+        // The accepted stream is in blocking mode, which would stall the runtime if kept
+        let (stream, _) = listener.accept().unwrap();
+        let mut server_connection = Connection::from_std(stream).unwrap();
+
+        let reader = tokio::spawn(async move {
+            let message: String = server_connection.read().await.unwrap().unwrap();
+            message
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        client_connection.write(&"Hello, world!").await.unwrap();
+        assert_eq!("Hello, world!", reader.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn closing_mid_frame_is_distinct_from_a_clean_close() {
+        // A clean close between two messages ends the stream
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+        client_connection.write(&7u32).await.unwrap();
+        drop(client_connection);
+        assert_eq!(Some(7), server_connection.read::<u32>().await.unwrap());
+        assert_eq!(None, server_connection.read::<u32>().await.unwrap());
+
+        // A clean close while a response is still expected is a disconnect
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+        let server = tokio::spawn(async move {
+            server_connection.read::<String>().await.unwrap();
+        });
+        let result = client_connection
+            .request::<_, usize>(&"hello".to_string(), None)
+            .await;
+        assert!(matches!(result, Err(ConnectionError::Disconnected)));
+        server.await.unwrap();
+
+        // Closing after the header and half of the payload of a 4-byte bincode value is an error
+        let (mut client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut server_connection = Connection::new(server_stream);
+        client_stream
+            .write_all(&[0, 0, 0, 4, 0, 0, 0, 0, 7, 0])
+            .await
+            .unwrap();
+        drop(client_stream);
+        assert!(matches!(
+            server_connection.read::<u32>().await,
+            Err(ConnectionError::UnexpectedEof)
+        ));
+
+        let (mut client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut server_connection = Connection::new(server_stream);
+        client_stream.write_all(&[0, 0, 0, 4]).await.unwrap();
+        drop(client_stream);
+        assert_eq!(None, server_connection.try_read::<u32>().await.unwrap());
+        assert!(matches!(
+            server_connection.try_read::<u32>().await,
+            Err(ConnectionError::UnexpectedEof)
+        ));
+
+        // A reset connection fails with the error of the socket
+        let (server_listener, client_connection) = setup().await;
+        let mut server_connection = Connection::new(server_listener.accept().await.unwrap().0);
+        client_connection.set_linger(Some(Duration::ZERO)).unwrap();
+        drop(client_connection);
+        assert!(matches!(
+            server_connection.read::<u32>().await,
+            Err(ConnectionError::IoError(e)) if e.kind() == std::io::ErrorKind::ConnectionReset
+        ));
+    }
+
+    #[tokio::test]
+    async fn zero_linger_resets_the_connection_on_close() {
+        let (server_listener, client_connection) = setup().await;
+        let mut server_connection = Connection::new(server_listener.accept().await.unwrap().0);
+        assert_eq!(None, client_connection.linger().unwrap());
+
+        client_connection.set_linger(Some(Duration::ZERO)).unwrap();
+        assert_eq!(Some(Duration::ZERO), client_connection.linger().unwrap());
+
+        // The peer sees a RST instead of the FIN of a graceful close, which is what spares the
+        // closing side its TIME_WAIT
+        drop(client_connection);
+        let result = server_connection.read::<String>().await;
+        assert!(matches!(
+            result,
+            Err(ConnectionError::IoError(e)) if e.kind() == std::io::ErrorKind::ConnectionReset
+        ));
+    }
+
+    #[tokio::test]
+    async fn abort_resets_the_connection_without_flushing() {
+        let (server_listener, mut client_connection) = setup().await;
+        let mut server_connection = Connection::new(server_listener.accept().await.unwrap().0);
+
+        client_connection.write_no_flush(&"unsent").await.unwrap();
+        client_connection.abort();
+
+        let result = server_connection.read::<String>().await;
+        assert!(matches!(
+            result,
+            Err(ConnectionError::IoError(e)) if e.kind() == std::io::ErrorKind::ConnectionReset
+        ));
+    }
+
+    #[tokio::test]
+    async fn dial_errors_name_the_addresses() {
+        // A listener that is dropped right away leaves a port nobody listens on
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let error = Connection::dial(addr).await.unwrap_err();
+        assert!(matches!(error, ConnectionError::IoErrorContext(..)));
+        let expected = format!("failed to connect to {}", addr);
+        assert!(error.to_string().contains(&expected));
+
+        // 192.0.2.0/24 is reserved for documentation, so no interface has this address
+        let error = Connection::dial_from("192.0.2.1:0", addr)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("failed to bind 192.0.2.1:0"));
+    }
+
+    #[tokio::test]
+    async fn dialing_several_addresses_falls_back_in_order() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let open = listener.local_addr().unwrap();
+        let closed = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap()
+        };
+
+        // Both dial and dial_first_of skip the address that refuses the connection
+        let client_connection = Connection::dial(&[closed, open][..]).await.unwrap();
+        assert_eq!(open, client_connection.peer_addr().unwrap());
+        let client_connection = Connection::dial_first_of(&[closed, open]).await.unwrap();
+        assert_eq!(open, client_connection.peer_addr().unwrap());
+
+        let error = Connection::dial_first_of(&[closed, closed])
+            .await
+            .unwrap_err();
+        match &error {
+            ConnectionError::AllAddressesFailed(errors) => {
+                assert_eq!(2, errors.len());
+                assert!(errors
+                    .iter()
+                    .all(|e| e.kind() == std::io::ErrorKind::ConnectionRefused));
+            }
+            e => panic!("unexpected error: {e}"),
+        }
+        assert!(error.is_fatal());
+        assert!(matches!(
+            Connection::dial_first_of(&[]).await,
+            Err(ConnectionError::InvalidConfiguration(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn cloned_connection_shares_the_socket() {
+        let (server_listener, mut client_connection) = setup().await;
+        let mut server_connection = Connection::new(server_listener.accept().await.unwrap().0);
+
+        let mut cloned_connection = client_connection.try_clone().await.unwrap();
+        assert_eq!(
+            client_connection.local_addr().unwrap(),
+            cloned_connection.local_addr().unwrap()
+        );
+
+        cloned_connection.write(&"from the clone").await.unwrap();
+        let message: String = server_connection.read().await.unwrap().unwrap();
+        assert_eq!("from the clone", message);
+
+        server_connection.write(&message).await.unwrap();
+        let echoed: String = client_connection.read().await.unwrap().unwrap();
+        assert_eq!("from the clone", echoed);
+    }
+
+    #[tokio::test]
+    async fn broadcast_reaches_every_open_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut clients = Vec::new();
+        let mut servers = Vec::new();
+        for _ in 0..3 {
+            clients.push(Connection::dial(addr).await.unwrap());
+            servers.push(Connection::new(listener.accept().await.unwrap().0));
+        }
+
+        // Close the writing half of the middle connection before broadcasting
+        clients[1].shutdown().await.unwrap();
+
+        let results = broadcast(&mut clients, &"Hello, everyone!".to_string()).await;
+        assert_eq!(3, results.len());
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+        assert!(results[2].is_ok());
+
+        for i in [0, 2] {
+            let message: String = servers[i].read().await.unwrap().unwrap();
+            assert_eq!("Hello, everyone!", message);
+        }
+        assert!(servers[1].read::<String>().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn builder_applies_socket_options() {
+        let (server_listener, _client_connection) = setup().await;
+        let stream = server_listener.accept().await.unwrap().0;
+        let server_connection = ConnectionBuilder::new()
+            .nodelay(true)
+            .ttl(17)
+            .keepalive(Duration::from_secs(60))
+            .build(stream)
+            .unwrap();
+
+        assert!(server_connection.nodelay().unwrap());
+        assert_eq!(17, server_connection.ttl().unwrap());
+        let (stream, _) = server_connection.into_inner().await.unwrap();
+        assert!(socket2::SockRef::from(&stream).keepalive().unwrap());
+    }
+
+    #[tokio::test]
+    async fn peer_and_local_addresses_match() {
+        let (server_listener, client_connection) = setup().await;
+        let (stream, observed_addr) = server_listener.accept().await.unwrap();
+        let server_connection = Connection::new(stream);
+
+        assert_eq!(observed_addr, client_connection.local_addr().unwrap());
+        assert_eq!(observed_addr, server_connection.peer_addr().unwrap());
+        assert_eq!(
+            server_listener.local_addr().unwrap(),
+            client_connection.peer_addr().unwrap()
+        );
+        assert_eq!(
+            server_connection.local_addr().unwrap(),
+            client_connection.peer_addr().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn close_delivers_clean_end_of_stream() {
+        let (server_listener, mut client_connection) = setup().await;
+        let mut server_connection = Connection::new(server_listener.accept().await.unwrap().0);
+
+        let client = tokio::spawn(async move {
+            client_connection.write(&"Goodbye!").await.unwrap();
+            client_connection.close().await.unwrap();
+        });
+
+        let parsed_message: String = server_connection.read().await.unwrap().unwrap();
+        assert_eq!("Goodbye!", parsed_message);
+        assert!(server_connection.read::<String>().await.unwrap().is_none());
+        drop(server_connection);
+        client.await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_connection_exchanges_messages() {
+        use connection::UnixConnection;
+        use tokio::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!("connection-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let mut client_connection = UnixConnection::dial_unix(&path).await.unwrap();
+        let mut server_connection = UnixConnection::new(listener.accept().await.unwrap().0);
+
+        let message = TestMessage {
+            id: 123,
+            name: "Test Message".to_string(),
+            payload: vec![1, 2, 3, 4, 5],
+        };
+        client_connection.write(&message).await.unwrap();
+        let parsed_message: TestMessage = server_connection.read().await.unwrap().unwrap();
+        assert_eq!(message, parsed_message);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    async fn echo(mut conn: Connection) {
+        while let Ok(Some(message)) = conn.read::<String>().await {
+            conn.write(&message).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn server_accept_stream_yields_connections() {
+        let mut server = Server::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let clients = tokio::spawn(async move {
+            for id in 0..3u32 {
+                let mut client_connection = Connection::dial(addr).await.unwrap();
+                client_connection.write(&id).await.unwrap();
+            }
+        });
+
+        let mut ids: Vec<u32> = Vec::new();
+        let mut incoming = server.accept_stream().take(3);
+        while let Some(conn) = incoming.next().await {
+            ids.push(conn.unwrap().read().await.unwrap().unwrap());
+        }
+        assert_eq!(vec![0, 1, 2], ids);
+        clients.await.unwrap();
+
+        // Dropping the stream hands the server back
+        drop(incoming);
+        let mut client_connection = Connection::dial(addr).await.unwrap();
+        let mut server_connection = server.accept().await.unwrap();
+        client_connection.write(&3u32).await.unwrap();
+        assert_eq!(Some(3u32), server_connection.read().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn servers_accept_ipv4_and_ipv6_clients() {
+        use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+
+        async fn send_from_both_loopbacks(port: u16) {
+            let v4 = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+            let v6 = SocketAddr::from((Ipv6Addr::LOCALHOST, port));
+            for (id, addr) in [(4u32, v4), (6, v6)] {
+                let mut client_connection = Connection::dial(addr).await.unwrap();
+                client_connection.write(&id).await.unwrap();
+            }
+        }
+
+        async fn receive_ids(server: &mut Server) -> Vec<u32> {
+            let mut ids: Vec<u32> = Vec::new();
+            let mut incoming = server.accept_stream().take(2);
+            while let Some(conn) = incoming.next().await {
+                ids.push(conn.unwrap().read().await.unwrap().unwrap());
+            }
+            ids.sort();
+            ids
+        }
+
+        // Separate IPv4 and IPv6 listeners on the same port
+        let mut server = Server::bind_all(0).await.unwrap();
+        let addrs = server.local_addrs().unwrap();
+        assert_eq!(2, addrs.len());
+        assert!(addrs[0].is_ipv4() && addrs[1].is_ipv6());
+        assert_eq!(addrs[0].port(), addrs[1].port());
+        let clients = tokio::spawn(send_from_both_loopbacks(addrs[0].port()));
+        assert_eq!(vec![4, 6], receive_ids(&mut server).await);
+        clients.await.unwrap();
+
+        // A single dual-stack listener
+        let mut server = Server::bind_dual_stack(0).await.unwrap();
+        let port = server.local_addr().unwrap().port();
+        let clients = tokio::spawn(send_from_both_loopbacks(port));
+        assert_eq!(vec![4, 6], receive_ids(&mut server).await);
+        clients.await.unwrap();
+
+        // An IPv6-only listener
+        let addr = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 0, 0, 0);
+        let mut server = Server::bind_v6(addr).await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut client_connection = Connection::dial(addr).await.unwrap();
+        let mut server_connection = server.accept().await.unwrap();
+        client_connection.write(&6u32).await.unwrap();
+        assert_eq!(Some(6u32), server_connection.read().await.unwrap());
+    }
+
+    /// Runs a minimal SOCKS5 proxy which relays CONNECT requests for IPv4 targets, requiring the
+    /// given username and password if any
+    async fn socks5_stub(
+        credentials: Option<(&'static str, &'static str)>,
+    ) -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut client, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut greeting = [0u8; 2];
+                    client.read_exact(&mut greeting).await.unwrap();
+                    let mut methods = vec![0u8; greeting[1] as usize];
+                    client.read_exact(&mut methods).await.unwrap();
+                    let method = if credentials.is_some() { 2 } else { 0 };
+                    if !methods.contains(&method) {
+                        client.write_all(&[5, 0xFF]).await.unwrap();
+                        return;
+                    }
+                    client.write_all(&[5, method]).await.unwrap();
+
+                    if let Some((username, password)) = credentials {
+                        let mut header = [0u8; 2];
+                        client.read_exact(&mut header).await.unwrap();
+                        let mut given_username = vec![0u8; header[1] as usize];
+                        client.read_exact(&mut given_username).await.unwrap();
+                        let mut given_password =
+                            vec![0u8; client.read_u8().await.unwrap() as usize];
+                        client.read_exact(&mut given_password).await.unwrap();
+                        let accepted = given_username == username.as_bytes()
+                            && given_password == password.as_bytes();
+                        client.write_all(&[1, u8::from(!accepted)]).await.unwrap();
+                        if !accepted {
+                            return;
+                        }
+                    }
+
+                    let mut request = [0u8; 10];
+                    client.read_exact(&mut request).await.unwrap();
+                    let ip = [request[4], request[5], request[6], request[7]];
+                    let port = u16::from_be_bytes([request[8], request[9]]);
+                    match tokio::net::TcpStream::connect(std::net::SocketAddr::from((ip, port)))
+                        .await
+                    {
+                        Ok(mut upstream) => {
+                            client
+                                .write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0])
+                                .await
+                                .unwrap();
+                            let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+                        }
+                        Err(_) => client
+                            .write_all(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0])
+                            .await
+                            .unwrap(),
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn dial_via_socks5_connects_through_the_proxy() {
+        let server = Server::bind("127.0.0.1:0").await.unwrap();
+        let target = server.local_addr().unwrap();
+        tokio::spawn(server.serve(echo));
+
+        let proxy = socks5_stub(None).await;
+        let mut conn = Connection::dial_via_socks5(proxy, target).await.unwrap();
+        conn.write(&"Hello, proxy!").await.unwrap();
+        assert_eq!(
+            Some("Hello, proxy!".to_string()),
+            conn.read().await.unwrap()
+        );
+
+        let proxy = socks5_stub(Some(("alice", "secret"))).await;
+        let auth = |password: &str| Socks5Auth::UsernamePassword {
+            username: "alice".into(),
+            password: password.into(),
+        };
+        let mut conn = Connection::dial_via_socks5_with_auth(proxy, target, auth("secret"))
+            .await
+            .unwrap();
+        conn.write(&"Hello, proxy!").await.unwrap();
+        assert_eq!(
+            Some("Hello, proxy!".to_string()),
+            conn.read().await.unwrap()
+        );
+
+        // Missing or wrong credentials
+        assert!(matches!(
+            Connection::dial_via_socks5(proxy, target).await,
+            Err(ConnectionError::ProxyAuthenticationFailed(_))
+        ));
+        assert!(matches!(
+            Connection::dial_via_socks5_with_auth(proxy, target, auth("wrong")).await,
+            Err(ConnectionError::ProxyAuthenticationFailed(_))
+        ));
+
+        // A target nobody listens on, and a proxy nobody listens on
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let result = Connection::dial_via_socks5(socks5_stub(None).await, closed).await;
+        assert!(matches!(result, Err(ConnectionError::ProxyError(e)) if e.contains("refused")));
+        assert!(matches!(
+            Connection::dial_via_socks5(closed, target).await,
+            Err(ConnectionError::Context(..))
+        ));
+    }
+
+    #[tokio::test]
+    async fn server_serves_many_clients() {
+        let server = Server::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve(echo));
+
+        for id in 0..3 {
+            let mut client_connection = Connection::dial(addr).await.unwrap();
+            let message = format!("Hello from client {}", id);
+            client_connection.write(&message).await.unwrap();
+            let parsed_message: String = client_connection.read().await.unwrap().unwrap();
+            assert_eq!(message, parsed_message);
+        }
+    }
+
+    #[tokio::test]
+    async fn server_queues_connections_beyond_limit() {
+        let server = Server::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .max_connections(1);
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve(echo));
+
+        let mut first_connection = Connection::dial(addr).await.unwrap();
+        first_connection.write(&"first").await.unwrap();
+        assert_eq!(
+            "first",
+            first_connection.read::<String>().await.unwrap().unwrap()
+        );
+
+        // The second client is not served while the first one is connected
+        let mut second_connection = Connection::dial(addr).await.unwrap();
+        second_connection.write(&"second").await.unwrap();
+        second_connection.set_read_timeout(Some(Duration::from_millis(100)));
+        assert!(matches!(
+            second_connection.read::<String>().await,
+            Err(ConnectionError::Timeout)
+        ));
+
+        drop(first_connection);
+        second_connection.set_read_timeout(None);
+        assert_eq!(
+            "second",
+            second_connection.read::<String>().await.unwrap().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn read_rejects_oversized_frame() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut client_stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let mut server_connection = Connection::new(listener.accept().await.unwrap().0);
+        server_connection.set_max_message_size(1024);
+        assert_eq!(1024, server_connection.max_message_size());
+
+        // Announce the largest possible payload without sending any of it
+        client_stream
+            .write_all(&(u32::MAX - 3).to_be_bytes())
+            .await
+            .unwrap();
+
+        let result = server_connection.read::<Vec<u8>>().await;
+        assert!(matches!(
+            result,
+            Err(ConnectionError::MessageTooLarge {
+                claimed,
+                limit: 1024,
+            }) if claimed == (u32::MAX - 3) as usize
+        ));
+    }
+
+    #[tokio::test]
+    async fn read_accepts_frame_at_size_limit() {
+        let (server_listener, mut client_connection) = setup().await;
+        let stream = server_listener.accept().await.unwrap().0;
+        let mut server_connection = ConnectionBuilder::new()
+            .max_message_size(8 + 16)
+            .build(stream)
+            .unwrap();
+
+        // bincode prefixes the vector with its length as a u64
+        client_connection.write(&vec![7u8; 16]).await.unwrap();
+        let parsed_message: Vec<u8> = server_connection.read().await.unwrap().unwrap();
+        assert_eq!(vec![7u8; 16], parsed_message);
+    }
+
+    #[tokio::test]
+    async fn size_limit_ignores_the_authentication_tag() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+        client_connection.set_hmac_key(&[7; 32]);
+        server_connection.set_hmac_key(&[7; 32]);
+        server_connection.set_max_message_size(16);
+
+        // A payload at the limit is accepted even though its tag takes the frame past it
+        client_connection.write_raw(&[7; 16]).await.unwrap();
+        let payload = server_connection.read_raw().await.unwrap().unwrap();
+        assert_eq!(&[7; 16], &payload[..]);
+
+        client_connection.write_raw(&[7; 17]).await.unwrap();
+        let result = server_connection.read_raw().await;
+        assert!(matches!(
+            result,
+            Err(ConnectionError::MessageTooLarge {
+                claimed: 17,
+                limit: 16,
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn typed_connection_exchanges_pinned_types() {
+        let (server_listener, client_connection) = setup().await;
+        let server_connection = Connection::new(server_listener.accept().await.unwrap().0);
+        let mut client_connection: TypedConnection<TestMessage, u32> =
+            client_connection.into_typed();
+        let mut server_connection: TypedConnection<u32, TestMessage> =
+            server_connection.into_typed();
+
+        let message = TestMessage {
+            id: 123,
+            name: "Test Message".to_string(),
+            payload: vec![1, 2, 3, 4, 5],
+        };
+        client_connection.send(&message).await.unwrap();
+        let parsed_message = server_connection.recv().await.unwrap().unwrap();
+        assert_eq!(message, parsed_message);
+
+        server_connection.send(&parsed_message.id).await.unwrap();
+        assert_eq!(123, client_connection.recv().await.unwrap().unwrap());
+
+        // The untyped connection can still be recovered
+        let mut client_connection = client_connection.into_inner();
+        client_connection.write(&"Hello, world!").await.unwrap();
+        let mut server_connection = server_connection.into_inner();
+        let parsed_message: String = server_connection.read().await.unwrap().unwrap();
+        assert_eq!("Hello, world!", parsed_message);
+    }
+
+    struct Lookup;
+
+    impl Protocol for Lookup {
+        type Request = u32;
+        type Response = TestMessage;
+    }
+
+    #[tokio::test]
+    async fn protocol_connection_sends_requests_and_receives_responses() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream).into_protocol::<Lookup>();
+        let mut server_connection: TypedConnection<TestMessage, u32, _> =
+            Connection::new(server_stream).into_typed();
+
+        client_connection.send(&123).await.unwrap();
+        let id = server_connection.recv().await.unwrap().unwrap();
+        let message = TestMessage {
+            id,
+            name: "Test Message".to_string(),
+            payload: vec![1, 2, 3],
+        };
+        server_connection.send(&message).await.unwrap();
+        assert_eq!(Some(message), client_connection.recv().await.unwrap());
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn tracing_events_are_emitted() {
+        let (server_listener, mut client_connection) = setup().await;
+        let mut server_connection = Connection::new(server_listener.accept().await.unwrap().0);
+        assert!(logs_contain("DEBUG"));
+        assert!(logs_contain("connected"));
+
+        client_connection.write(&42u32).await.unwrap();
+        let _: u32 = server_connection.read().await.unwrap().unwrap();
+        assert!(logs_contain("TRACE"));
+        assert!(logs_contain("wrote value"));
+        assert!(logs_contain("bytes_written=4"));
+        assert!(logs_contain("read value"));
+        assert!(logs_contain("bytes_read=12"));
+        assert!(logs_contain("type_name=\"u32\""));
+    }
+
+    #[tokio::test]
+    async fn stats_track_messages_and_bytes() {
+        let (server_listener, mut client_connection) = setup().await;
+        let mut server_connection = Connection::new(server_listener.accept().await.unwrap().0);
+
+        let messages: Vec<String> = (0..5).map(|id| "x".repeat(id)).collect();
+        let mut expected_bytes = 0;
+        for message in &messages {
+            client_connection.write(message).await.unwrap();
+            expected_bytes += FrameHeader::SIZE as u64 + bincode::serialized_size(message).unwrap();
+        }
+
+        for _ in &messages {
+            let _: String = server_connection.read().await.unwrap().unwrap();
+        }
+
+        let stats = client_connection.stats();
+        assert_eq!(5, stats.messages_sent);
+        assert_eq!(expected_bytes, stats.bytes_sent);
+        assert_eq!(0, stats.errors);
+
+        let stats = server_connection.stats();
+        assert_eq!(5, stats.messages_received);
+        assert_eq!(expected_bytes, stats.bytes_received);
+        assert_eq!(0, stats.errors);
+    }
+
+    #[tokio::test]
+    async fn stats_count_errors() {
+        let (server_listener, _client_connection) = setup().await;
+        let mut server_connection = Connection::new(server_listener.accept().await.unwrap().0);
+        server_connection.set_read_timeout(Some(Duration::from_millis(10)));
+
+        assert!(server_connection.read::<String>().await.is_err());
+        assert!(server_connection.read::<String>().await.is_err());
+        assert_eq!(2, server_connection.stats().errors);
+    }
+
+    #[tokio::test]
+    async fn write_and_read_cbor_message() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Primitives {
+            boolean: bool,
+            signed: (i8, i16, i32, i64, i128),
+            unsigned: (u8, u16, u32, u64, u128),
+            floats: (f32, f64),
+            character: char,
+            text: String,
+            bytes: Vec<u8>,
+            unit: (),
+            present: Option<u32>,
+            absent: Option<u32>,
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut client_connection = Connection::dial_with_format(addr, SerdeFormat::Cbor)
+            .await
+            .unwrap();
+        let message = Primitives {
+            boolean: true,
+            signed: (-8, -16, -32, -64, -128),
+            unsigned: (8, 16, 32, 64, 128),
+            floats: (1.5, -2.25),
+            character: 'c',
+            text: "Hello, world!".to_string(),
+            bytes: vec![0, 1, 2, 255],
+            unit: (),
+            present: Some(7),
+            absent: None,
+        };
+
+        client_connection.write(&message).await.unwrap();
+
+        let stream = listener.accept().await.unwrap().0;
+        let mut server_connection = Connection::new_with_format(stream, SerdeFormat::Cbor);
+        let parsed_message: Primitives = server_connection.read().await.unwrap().unwrap();
+        assert_eq!(message, parsed_message);
+    }
+
+    #[cfg(feature = "postcard")]
+    #[tokio::test]
+    async fn postcard_round_trip_and_rejects_other_types() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection =
+            Connection::new_with_format(client_stream, SerdeFormat::Postcard);
+        let mut server_connection =
+            Connection::new_with_format(server_stream, SerdeFormat::Postcard);
+
+        let message = TestMessage {
+            id: 42,
+            name: "Postcard".to_string(),
+            payload: vec![1, 2, 3],
+        };
+        client_connection.write(&message).await.unwrap();
+        let received: TestMessage = server_connection.read().await.unwrap().unwrap();
+        assert_eq!(message, received);
+
+        // Reading a string as a u8 leaves its bytes over
+        client_connection.write(&"Hello, world!").await.unwrap();
+        let result = server_connection.read::<u8>().await;
+        assert!(matches!(result, Err(ConnectionError::PostcardError(_))));
+        assert!(result.unwrap_err().is_recoverable());
+    }
+
+    #[cfg(feature = "test-helpers")]
+    #[tokio::test]
+    async fn keepalive_detects_peer_that_never_answers() {
+        // The server never reads, so it never answers the pings
+        let (mut client_connection, _server_connection) = connection::mock::mock_pair();
+        client_connection.start_keepalive(Duration::from_millis(20), Duration::from_millis(50));
+        let mut liveness = client_connection.liveness_rx();
+        assert!(*liveness.borrow());
+
+        let detected = tokio::time::timeout(Duration::from_secs(2), async {
+            tokio::select! {
+                _ = client_connection.read::<String>() => panic!("no message was sent"),
+                _ = liveness.changed() => {}
+            }
+        });
+        detected.await.unwrap();
+        assert!(!*liveness.borrow());
+    }
+
+    #[tokio::test]
+    async fn keepalive_stays_alive_while_peer_answers() {
+        let (server_listener, mut client_connection) = setup().await;
+        let mut server_connection = Connection::new(server_listener.accept().await.unwrap().0);
+        tokio::spawn(
+            async move { while let Ok(Some(_)) = server_connection.read::<String>().await {} },
+        );
+
+        client_connection.start_keepalive(Duration::from_millis(20), Duration::from_millis(200));
+        let liveness = client_connection.liveness_rx();
+
+        // Several pings are answered while the client waits for a message that never comes
+        let read = client_connection.read::<String>();
+        assert!(tokio::time::timeout(Duration::from_millis(300), read)
+            .await
+            .is_err());
+        assert!(*liveness.borrow());
+    }
+
+    #[cfg(feature = "test-helpers")]
+    #[tokio::test]
+    async fn keepalive_gives_up_on_connection_that_never_reads() {
+        let (mut client_connection, _server_connection) = connection::mock::mock_pair();
+        client_connection.start_keepalive(Duration::from_millis(20), Duration::from_millis(50));
+        let mut liveness = client_connection.liveness_rx();
+
+        // The client never reads, so the ping that falls due is never sent
+        tokio::time::timeout(Duration::from_secs(2), liveness.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!*liveness.borrow());
+    }
+
+    #[tokio::test]
+    async fn keepalive_ignores_pong_sent_before_ping() {
+        let (client_stream, mut server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        client_connection.start_keepalive(Duration::from_millis(50), Duration::from_millis(50));
+        let mut liveness = client_connection.liveness_rx();
+
+        // A pong nobody asked for must not count as the answer to the first ping, which is the
+        // only one left unanswered
+        server_stream
+            .write_all(&(u32::MAX - 1).to_be_bytes())
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            let mut ping = [0u8; 4];
+            server_stream.read_exact(&mut ping).await.unwrap();
+            while server_stream.read_exact(&mut ping).await.is_ok() {
+                let pong = (u32::MAX - 1).to_be_bytes();
+                if server_stream.write_all(&pong).await.is_err() {
+                    break;
+                }
+            }
+        });
+        let detected = tokio::time::timeout(Duration::from_secs(2), async {
+            tokio::select! {
+                _ = client_connection.read::<String>() => panic!("no message was sent"),
+                _ = liveness.changed() => {}
+            }
+        });
+        detected.await.unwrap();
+        assert!(!*liveness.borrow());
+    }
+
+    #[tokio::test]
+    async fn split_connection_answers_pings() {
+        let (server_listener, mut client_connection) = setup().await;
+        let server_connection = Connection::new(server_listener.accept().await.unwrap().0);
+        let (mut reader, _writer) = server_connection.split();
+        tokio::spawn(async move { while let Ok(Some(_)) = reader.read::<String>().await {} });
+
+        client_connection.start_keepalive(Duration::from_millis(20), Duration::from_millis(200));
+        let liveness = client_connection.liveness_rx();
+
+        // The reader answers every ping while the client waits for a message that never comes
+        let read = client_connection.read::<String>();
+        assert!(tokio::time::timeout(Duration::from_millis(300), read)
+            .await
+            .is_err());
+        assert!(*liveness.borrow());
+    }
+
+    #[tokio::test]
+    async fn pong_is_not_written_inside_partially_sent_sink_frame() {
+        use futures::FutureExt;
+
+        let (client_stream, mut server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+
+        // Only the first kilobyte of the frame fits into the pipe
+        let message = "x".repeat(8 * 1024);
+        client_connection.feed(message.clone()).await.unwrap();
+        assert!(SinkExt::<String>::flush(&mut client_connection)
+            .now_or_never()
+            .is_none());
+
+        // The client answers a ping while the rest of the frame is still queued
+        server_stream
+            .write_all(&u32::MAX.to_be_bytes())
+            .await
+            .unwrap();
+        let mut server_connection = Connection::new(server_stream);
+        let answer = tokio::time::timeout(
+            Duration::from_millis(200),
+            client_connection.read::<String>(),
+        );
+        let received =
+            tokio::time::timeout(Duration::from_secs(2), server_connection.read::<String>());
+        let (_, received) = tokio::join!(answer, received);
+        assert_eq!(Some(message), received.unwrap().unwrap());
+    }
+
+    /// Accept connections forever, reading from each one so that pings are answered
+    fn spawn_reading_server(listener: TcpListener, accepted: Arc<AtomicU32>) {
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                let mut conn = Connection::new(stream);
+                tokio::spawn(async move { while let Ok(Some(_)) = conn.read::<u32>().await {} });
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn pool_never_exceeds_max_size() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accepted = Arc::new(AtomicU32::new(0));
+        spawn_reading_server(listener, accepted.clone());
+
+        let pool = ConnectionPool::new(addr, 1, 3, Duration::from_secs(5))
+            .await
+            .unwrap();
+        let in_use = Arc::new(AtomicU32::new(0));
+        let most_in_use = Arc::new(AtomicU32::new(0));
+
+        let tasks = (0..10u32).map(|id| {
+            let pool = pool.clone();
+            let in_use = in_use.clone();
+            let most_in_use = most_in_use.clone();
+            tokio::spawn(async move {
+                let mut conn = pool.acquire().await.unwrap();
+                let current = in_use.fetch_add(1, Ordering::SeqCst) + 1;
+                most_in_use.fetch_max(current, Ordering::SeqCst);
+                conn.write(&id).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                in_use.fetch_sub(1, Ordering::SeqCst);
+            })
+        });
+
+        for task in tasks.collect::<Vec<_>>() {
+            task.await.unwrap();
+        }
+
+        assert!(most_in_use.load(Ordering::SeqCst) <= 3);
+        assert!(accepted.load(Ordering::SeqCst) <= 3);
+        assert!(pool.idle_connections() <= 3);
+    }
+
+    #[tokio::test]
+    async fn pool_acquire_times_out_when_exhausted() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        spawn_reading_server(listener, Arc::new(AtomicU32::new(0)));
+
+        let pool = ConnectionPool::new(addr, 0, 1, Duration::from_millis(50))
+            .await
+            .unwrap();
+        let held = pool.acquire().await.unwrap();
+        assert!(matches!(
+            pool.acquire().await,
+            Err(ConnectionError::PoolExhausted)
+        ));
+
+        drop(held);
+        assert_eq!(1, pool.idle_connections());
+        assert!(pool.acquire().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn pool_stats_track_acquires_and_releases() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        spawn_reading_server(listener, Arc::new(AtomicU32::new(0)));
+
+        let pool = ConnectionPool::new(addr, 1, 2, Duration::from_millis(300))
+            .await
+            .unwrap();
+        assert_eq!(1, pool.stats().idle);
+
+        let first = pool.acquire().await.unwrap();
+        let second = pool.acquire().await.unwrap();
+        let stats = pool.stats();
+        assert_eq!((2, 0, 2), (stats.active, stats.idle, stats.total_acquired));
+
+        let waiter = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.acquire().await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(1, pool.stats().waiting);
+
+        drop(first);
+        let third = waiter.await.unwrap().unwrap();
+        assert!(matches!(
+            pool.acquire().await,
+            Err(ConnectionError::PoolExhausted)
+        ));
+
+        drop(second);
+        drop(third);
+        let stats = pool.stats();
+        assert_eq!(
+            PoolStats {
+                active: 0,
+                idle: 2,
+                waiting: 0,
+                total_acquired: 3,
+                total_released: 3,
+                total_errors: 1,
+            },
+            stats
+        );
+        assert_eq!(
+            "active=0 idle=2 waiting=0 acquired=3 released=3 errors=1",
+            stats.to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn pool_replaces_idle_connection_that_fails_health_check() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (closed_tx, closed_rx) = tokio::sync::oneshot::channel();
+
+        tokio::spawn(async move {
+            // Close the first connection, then serve the one that replaces it
+            drop(listener.accept().await.unwrap());
+            closed_tx.send(()).unwrap();
+            let mut conn = Connection::new(listener.accept().await.unwrap().0);
+            while let Ok(Some(_)) = conn.read::<u32>().await {}
+        });
+
+        let pool = ConnectionPool::new(addr, 1, 1, Duration::from_secs(1))
+            .await
+            .unwrap();
+        closed_rx.await.unwrap();
+
+        let mut conn = pool.acquire().await.unwrap();
+        conn.write(&1u32).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn channel_bridge_preserves_order_under_concurrent_sends() {
+        let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+        let (outgoing, _) = ChannelBridge::new::<_, (u32, u32), ()>(Connection::new(client_stream));
+        let (_, mut incoming) =
+            ChannelBridge::new::<_, (), (u32, u32)>(Connection::new(server_stream));
+
+        let senders: Vec<_> = (0..4u32)
+            .map(|sender| {
+                let outgoing = outgoing.clone();
+                tokio::spawn(async move {
+                    for seq in 0..100u32 {
+                        outgoing.send((sender, seq)).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        drop(outgoing);
+        for sender in senders {
+            sender.await.unwrap();
+        }
+
+        // Messages from different senders interleave, but each sender's arrive in order
+        let mut next = [0u32; 4];
+        for _ in 0..400 {
+            let (sender, seq) = incoming.recv().await.unwrap();
+            assert_eq!(next[sender as usize], seq);
+            next[sender as usize] += 1;
+        }
+        assert_eq!([100; 4], next);
+    }
+
+    #[tokio::test]
+    async fn channel_bridge_closes_channels_on_error() {
+        let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+        let (outgoing, mut incoming) =
+            ChannelBridge::new::<_, String, u32>(Connection::new(client_stream));
+        let mut server_connection = Connection::new(server_stream);
+
+        // A reply of the wrong type fails the read, which closes the receiver
+        server_connection.write(&"not a number").await.unwrap();
+        assert_eq!(None, incoming.recv().await);
+
+        // Once the peer is gone, writes fail and the sender is closed
+        drop(server_connection);
+        let closed = tokio::time::timeout(Duration::from_secs(5), async {
+            while outgoing.send("Hello, world!".to_string()).await.is_ok() {}
+        });
+        closed.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn connection_actors_deliver_messages_in_order() {
+        let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+        let mut client = Connection::new(client_stream).into_actor();
+        let mut server = Connection::new(server_stream).into_actor();
+
+        // Echo every frame back from the server's side
+        let echo = server.sender();
+        let echoes = tokio::spawn(async move {
+            for _ in 0..1000 {
+                let frame = server.receiver().recv().await.unwrap();
+                echo.send(OutgoingFrame::raw(frame.into_payload()))
+                    .await
+                    .unwrap();
+            }
+            server
+        });
+
+        let sender = client.sender();
+        let format = client.format();
+        let sends = tokio::spawn(async move {
+            for seq in 0..1000u32 {
+                let frame = OutgoingFrame::new(&seq, format).unwrap();
+                sender.send(frame).await.unwrap();
+            }
+        });
+        for seq in 0..1000u32 {
+            let frame = client.receiver().recv().await.unwrap();
+            assert_eq!(seq, frame.decode::<u32>().unwrap());
+        }
+        sends.await.unwrap();
+
+        let server = echoes.await.unwrap();
+        server.shutdown().await.unwrap();
+        assert!(client.receiver().recv().await.is_none());
+        client.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn connection_actor_shutdown_reports_read_errors() {
+        let (client_stream, mut server_stream) = tokio::io::duplex(1024);
+        let mut actor = Connection::new(client_stream).into_actor();
+
+        // Frames queued before the shutdown are still written
+        let sender = actor.sender();
+        sender
+            .send(OutgoingFrame::raw(&b"first"[..]))
+            .await
+            .unwrap();
+        sender
+            .send(OutgoingFrame::raw(&b"second"[..]))
+            .await
+            .unwrap();
+        let mut expected = Vec::new();
+        for payload in [&b"first"[..], b"second"] {
+            expected.extend_from_slice(&FrameHeader::new(payload.len() as u32, 0).to_bytes());
+            expected.extend_from_slice(payload);
+        }
+        let mut received = vec![0u8; expected.len()];
+        server_stream.read_exact(&mut received).await.unwrap();
+        assert_eq!(expected, received);
+
+        // A frame larger than the actor's limit ends the reader with an error
+        server_stream
+            .write_all(&[0xFF, 0, 0, 0, 0, 0, 0, 0])
+            .await
+            .unwrap();
+        assert!(actor.receiver().recv().await.is_none());
+        assert!(matches!(
+            actor.shutdown().await,
+            Err(ConnectionError::MessageTooLarge { .. })
+        ));
+    }
+
+    fn multiplexer_pair(capacity: usize) -> (Multiplexer, Multiplexer) {
+        let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+        let client = Multiplexer::with_capacity(Connection::new(client_stream), capacity);
+        let server = Multiplexer::with_capacity(Connection::new(server_stream), capacity);
+        (client, server)
+    }
+
+    #[tokio::test]
+    async fn multiplexer_channels_are_independent() {
+        let (client, server) = multiplexer_pair(8);
+        let (client_a, mut client_a_rx) = client.open_channel();
+        let (client_b, _) = client.open_channel();
+        let (server_a, mut server_a_rx) = server.open_channel();
+        let (_, mut server_b_rx) = server.open_channel();
+        assert_eq!(server_a.id(), client_a.id());
+
+        client_b.send(&"on b".to_string()).await.unwrap();
+        client_a.send(&1u32).await.unwrap();
+
+        assert_eq!(Some(1u32), server_a_rx.recv().await.unwrap());
+        let message: String = server_b_rx.recv().await.unwrap().unwrap();
+        assert_eq!("on b", message);
+
+        server_a.send(&2u32).await.unwrap();
+        assert_eq!(Some(2u32), client_a_rx.recv().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn multiplexer_slow_consumer_does_not_starve_other_channels() {
+        let (client, server) = multiplexer_pair(2);
+        let (slow, _) = client.open_channel();
+        let (fast, _) = client.open_channel();
+        let (_, mut slow_rx) = server.open_channel();
+        let (_, mut fast_rx) = server.open_channel();
+
+        // Nobody reads the slow channel, so its writer stalls once it reaches capacity
+        let stalled = tokio::spawn(async move {
+            for id in 0..10u32 {
+                slow.send(&id).await.unwrap();
+            }
+        });
+
+        for id in 0..10u32 {
+            fast.send(&id).await.unwrap();
+            let received = tokio::time::timeout(Duration::from_secs(1), fast_rx.recv::<u32>());
+            assert_eq!(Some(id), received.await.unwrap().unwrap());
+        }
+        assert!(!stalled.is_finished());
+
+        for id in 0..10u32 {
+            assert_eq!(Some(id), slow_rx.recv().await.unwrap());
+        }
+        stalled.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn multiplexer_closes_when_peer_grants_too_many_credits() {
+        let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+        let client = Multiplexer::with_capacity(Connection::new(client_stream), 8);
+        let (client_tx, mut client_rx) = client.open_channel();
+
+        // Channel 0 is granted credits it never used
+        let mut server_connection = Connection::new(server_stream);
+        let frame = [0, 0, 0, 0, 1, 0xFF, 0xFF, 0xFF, 0xFF];
+        server_connection.write_raw(&frame).await.unwrap();
+
+        let received = tokio::time::timeout(Duration::from_secs(1), client_rx.recv::<u32>());
+        assert_eq!(None, received.await.unwrap().unwrap());
+        assert!(client_tx.send(&1u32).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn multiplexer_channels_close_with_connection() {
+        let (client, server) = multiplexer_pair(8);
+        let (_, mut client_rx) = client.open_channel();
+        let (server_tx, _) = server.open_channel();
+        drop(server);
+        drop(server_tx);
+
+        assert_eq!(None, client_rx.recv::<u32>().await.unwrap());
+    }
+
+    #[cfg(feature = "handshake")]
+    #[tokio::test]
+    async fn handshake_succeeds_between_matching_versions() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let stream = listener.accept().await.unwrap().0;
+            let builder = ConnectionBuilder::new().require_handshake(true);
+            let mut server_connection = builder.accept(stream).await.unwrap();
+            server_connection.read::<String>().await.unwrap().unwrap()
+        });
+
+        let mut client_connection = ConnectionBuilder::new()
+            .require_handshake(true)
+            .connect(addr)
+            .await
+            .unwrap();
+        client_connection.write(&"Hello".to_string()).await.unwrap();
+        assert_eq!("Hello", server.await.unwrap());
+    }
+
+    /// Negotiates between two in-memory connections, returning both results and connections
+    async fn negotiate(
+        client: Capabilities,
+        server: Capabilities,
+    ) -> (
+        Result<NegotiatedCapabilities, ConnectionError>,
+        Result<NegotiatedCapabilities, ConnectionError>,
+        Connection<tokio::io::DuplexStream>,
+        Connection<tokio::io::DuplexStream>,
+    ) {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+        let mut client_negotiator = ProtocolNegotiator::new(client);
+        let mut server_negotiator = ProtocolNegotiator::new(server);
+        let (client_result, server_result) = tokio::join!(
+            client_negotiator.negotiate(&mut client_connection),
+            server_negotiator.negotiate(&mut server_connection)
+        );
+        (
+            client_result,
+            server_result,
+            client_connection,
+            server_connection,
+        )
+    }
+
+    #[tokio::test]
+    async fn negotiation_between_compatible_peers() {
+        let capabilities = Capabilities {
+            formats: vec![SerdeFormat::MsgPack, SerdeFormat::Bincode],
+            compression: vec![CompressionMode::Zstd(3), CompressionMode::None],
+            max_message_size: 4096,
+        };
+        let (client_result, server_result, mut client_connection, mut server_connection) =
+            negotiate(capabilities.clone(), capabilities).await;
+
+        let expected = NegotiatedCapabilities {
+            format: SerdeFormat::MsgPack,
+            compression: CompressionMode::Zstd(3),
+            max_message_size: 4096,
+        };
+        assert_eq!(expected, client_result.unwrap());
+        assert_eq!(expected, server_result.unwrap());
+
+        // The connections speak the negotiated settings afterwards
+        assert_eq!(SerdeFormat::MsgPack, client_connection.format());
+        assert_eq!(CompressionMode::Zstd(3), server_connection.compression());
+        assert_eq!(4096, server_connection.max_message_size());
+        client_connection.write(&"Hello, world!").await.unwrap();
+        let message: String = server_connection.read().await.unwrap().unwrap();
+        assert_eq!("Hello, world!", message);
+    }
+
+    #[tokio::test]
+    async fn negotiation_between_partially_compatible_peers() {
+        let client = Capabilities {
+            formats: vec![SerdeFormat::Json, SerdeFormat::Cbor, SerdeFormat::Bincode],
+            compression: vec![CompressionMode::Zstd(9), CompressionMode::None],
+            max_message_size: 1024,
+        };
+        let server = Capabilities {
+            formats: vec![
+                SerdeFormat::MsgPack,
+                SerdeFormat::Bincode,
+                SerdeFormat::Cbor,
+            ],
+            compression: vec![CompressionMode::None, CompressionMode::Zstd(1)],
+            max_message_size: 2048,
+        };
+        let (client_result, server_result, _, _) = negotiate(client, server).await;
+
+        // CBOR and bincode both rank third in total, so the tie goes to bincode; zstd and no
+        // compression tie as well, so the tie goes to no compression
+        let expected = NegotiatedCapabilities {
+            format: SerdeFormat::Bincode,
+            compression: CompressionMode::None,
+            max_message_size: 1024,
+        };
+        assert_eq!(expected, client_result.unwrap());
+        assert_eq!(expected, server_result.unwrap());
+
+        // Peers that only share zstd settle on the lower level
+        let client = Capabilities {
+            compression: vec![CompressionMode::Zstd(9)],
+            ..Capabilities::default()
+        };
+        let server = Capabilities {
+            compression: vec![CompressionMode::None, CompressionMode::Zstd(1)],
+            ..Capabilities::default()
+        };
+        let (client_result, server_result, _, _) = negotiate(client, server).await;
+        assert_eq!(CompressionMode::Zstd(1), client_result.unwrap().compression);
+        assert_eq!(CompressionMode::Zstd(1), server_result.unwrap().compression);
+    }
+
+    #[tokio::test]
+    async fn negotiation_between_incompatible_peers() {
+        let client = Capabilities {
+            formats: vec![SerdeFormat::Json],
+            ..Capabilities::default()
+        };
+        let server = Capabilities {
+            formats: vec![SerdeFormat::Bincode, SerdeFormat::Cbor],
+            ..Capabilities::default()
+        };
+        let (client_result, server_result, _, _) = negotiate(client, server).await;
+        assert!(matches!(
+            client_result,
+            Err(ConnectionError::NegotiationFailed(_))
+        ));
+        assert!(matches!(
+            server_result,
+            Err(ConnectionError::NegotiationFailed(_))
+        ));
+
+        let client = Capabilities {
+            compression: vec![CompressionMode::Zstd(3)],
+            ..Capabilities::default()
+        };
+        let (client_result, _, _, _) = negotiate(client, Capabilities::default()).await;
+        assert!(matches!(
+            client_result,
+            Err(ConnectionError::NegotiationFailed(_))
+        ));
+
+        // A peer that sends an application message instead of its capabilities
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+        client_connection.write(&"Hello, world!").await.unwrap();
+        let mut negotiator = ProtocolNegotiator::new(Capabilities::default());
+        let result = negotiator.negotiate(&mut server_connection).await;
+        assert!(matches!(result, Err(ConnectionError::NegotiationFailed(_))));
+    }
+
+    #[tokio::test]
+    async fn negotiate_compression_picks_the_best_shared_algorithm() {
+        use connection::CompressionAlgorithm::{Lz4, Snappy, Zstd};
+
+        let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+        server_connection.set_compression(CompressionMode::Zstd(7));
+
+        // Zstd and Snappy are both ranked second overall, so the tie goes to zstd
+        let (client_result, server_result) = tokio::join!(
+            client_connection.negotiate_compression(&[Lz4, Zstd, Snappy]),
+            server_connection.negotiate_compression(&[Snappy, Zstd])
+        );
+        assert_eq!(Zstd, client_result.unwrap());
+        assert_eq!(Zstd, server_result.unwrap());
+        assert_eq!(CompressionMode::Zstd(3), client_connection.compression());
+        assert_eq!(CompressionMode::Zstd(7), server_connection.compression());
+
+        let (client_result, server_result) = tokio::join!(
+            client_connection.negotiate_compression(&[Lz4, Snappy]),
+            server_connection.negotiate_compression(&[Snappy])
+        );
+        assert_eq!(Snappy, client_result.unwrap());
+        assert_eq!(Snappy, server_result.unwrap());
+        assert_eq!(CompressionMode::Snappy, client_connection.compression());
+
+        let message = "Hello, world!".repeat(100);
+        client_connection.write(&message).await.unwrap();
+        assert_eq!(Some(message), server_connection.read().await.unwrap());
+
+        let (client_result, server_result) = tokio::join!(
+            client_connection.negotiate_compression(&[Lz4]),
+            server_connection.negotiate_compression(&[Zstd])
+        );
+        assert!(matches!(
+            client_result,
+            Err(ConnectionError::NoCommonCompression)
+        ));
+        assert!(matches!(
+            server_result,
+            Err(ConnectionError::NoCommonCompression)
+        ));
+        assert_eq!(CompressionMode::Snappy, client_connection.compression());
+    }
+
+    #[tokio::test]
+    async fn hmac_authenticated_messages_round_trip() {
+        for compression in [CompressionMode::None, CompressionMode::Zstd(3)] {
+            let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+            let mut client_connection = Connection::new(client_stream);
+            client_connection.set_compression(compression);
+            client_connection.set_hmac_key(&[7; 32]);
+            let mut server_connection = Connection::new(server_stream);
+            server_connection.set_hmac_key(&[7; 32]);
+
+            let message = "Hello, world!".repeat(100);
+            client_connection.write(&message).await.unwrap();
+            client_connection.write_raw(b"raw").await.unwrap();
+            assert_eq!(Some(message), server_connection.read().await.unwrap());
+            assert_eq!(
+                &b"raw"[..],
+                &server_connection.read_raw().await.unwrap().unwrap()[..]
+            );
+
+            // The halves of a split connection keep the key
+            let (mut reader, _writer) = server_connection.split();
+            client_connection.write(&42u32).await.unwrap();
+            assert_eq!(Some(42u32), reader.read().await.unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn hmac_rejects_tampered_and_unauthenticated_frames() {
+        // A single flipped payload byte invalidates the tag
+        let (client_stream, mut server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        client_connection.set_hmac_key(&[7; 32]);
+        client_connection.write(&"Hello").await.unwrap();
+        let mut frame = vec![0u8; FrameHeader::SIZE + 13 + 32];
+        server_stream.read_exact(&mut frame).await.unwrap();
+        assert_eq!(0b10, frame[4]);
+        frame[FrameHeader::SIZE + 8] ^= 1;
+
+        let (mut client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut server_connection = Connection::new(server_stream);
+        server_connection.set_hmac_key(&[7; 32]);
+        client_stream.write_all(&frame).await.unwrap();
+        let result = server_connection.read::<String>().await;
+        assert!(matches!(
+            result,
+            Err(ConnectionError::AuthenticationFailed(_))
+        ));
+
+        // A different key fails the same way
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        client_connection.set_hmac_key(&[7; 32]);
+        let mut server_connection = Connection::new(server_stream);
+        server_connection.set_hmac_key(&[8; 32]);
+        client_connection.write(&"Hello").await.unwrap();
+        let result = server_connection.read::<String>().await;
+        assert!(matches!(
+            result,
+            Err(ConnectionError::AuthenticationFailed(_))
+        ));
+
+        // A receiver without the key refuses authenticated frames
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        client_connection.set_hmac_key(&[7; 32]);
+        let mut server_connection = Connection::new(server_stream);
+        client_connection.write(&"Hello").await.unwrap();
+        let result = server_connection.read_raw().await;
+        assert!(matches!(
+            result,
+            Err(ConnectionError::AuthenticationFailed(_))
+        ));
+
+        // A receiver with the key refuses unauthenticated frames
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+        let mut client_connection = Connection::new(client_stream);
+        let mut server_connection = Connection::new(server_stream);
+        server_connection.set_hmac_key(&[7; 32]);
+        client_connection.write(&"Hello").await.unwrap();
+        let result = server_connection.read::<String>().await;
+        assert!(matches!(
+            result,
+            Err(ConnectionError::AuthenticationFailed(_))
+        ));
+        assert!(result.unwrap_err().is_fatal());
+    }
+
+    #[cfg(feature = "handshake")]
+    #[tokio::test]
+    async fn handshake_rejects_incompatible_version() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut stream = listener.accept().await.unwrap().0;
+            let mut frame = Vec::new();
+            frame.extend_from_slice(&FrameHeader::new(14, 0).to_bytes());
+            frame.extend_from_slice(&0x434F_4E4Eu32.to_be_bytes());
+            frame.extend_from_slice(&(connection::PROTOCOL_VERSION + 1).to_be_bytes());
+            frame.extend_from_slice(&0u64.to_be_bytes());
+            stream.write_all(&frame).await.unwrap();
+            let mut sink = Vec::new();
+            let _ = stream.read_to_end(&mut sink).await;
+        });
+
+        let result = ConnectionBuilder::new()
+            .require_handshake(true)
+            .connect(addr)
+            .await;
+        assert!(matches!(
+            result,
+            Err(ConnectionError::VersionMismatch { local, remote })
+                if local == connection::PROTOCOL_VERSION && remote == local + 1
+        ));
+    }
+
+    #[cfg(feature = "handshake")]
+    #[tokio::test]
+    async fn handshake_rejects_peer_without_handshake() {
+        let (server_listener, mut client_connection) = setup().await;
+        let stream = server_listener.accept().await.unwrap().0;
+        client_connection.write(&"Hello".to_string()).await.unwrap();
+
+        let builder = ConnectionBuilder::new().require_handshake(true);
+        assert!(matches!(
+            builder.accept(stream).await,
+            Err(ConnectionError::VersionMismatch { remote: 0, .. })
+        ));
+    }
+
+    #[cfg(feature = "tls")]
+    fn tls_configs() -> (
+        Arc<connection::rustls::ClientConfig>,
+        Arc<connection::rustls::ServerConfig>,
+    ) {
+        use connection::rustls::pki_types::PrivateKeyDer;
+        use connection::rustls::{ClientConfig, RootCertStore, ServerConfig};
+
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let cert = certified.cert.der().clone();
+        let key = PrivateKeyDer::Pkcs8(certified.key_pair.serialize_der().into());
+
+        let mut roots = RootCertStore::empty();
+        roots.add(cert.clone()).unwrap();
+        let client = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let server = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert], key)
+            .unwrap();
+
+        (Arc::new(client), Arc::new(server))
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    async fn tls_connection_exchanges_messages() {
+        let (client_config, server_config) = tls_configs();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let stream = listener.accept().await.unwrap().0;
+            let mut server_connection = TlsServerConnection::accept_tls(stream, server_config)
+                .await
+                .unwrap();
+            let message: TestMessage = server_connection.read().await.unwrap().unwrap();
+            server_connection.write(&message).await.unwrap();
+        });
+
+        let mut client_connection = TlsConnection::dial_tls(addr, "localhost", client_config)
+            .await
+            .unwrap();
+        assert!(client_connection.peer_certificate().is_some());
+        let message = TestMessage {
+            id: 7,
+            name: "Secret".to_string(),
+            payload: vec![1, 2, 3],
+        };
+        client_connection.write(&message).await.unwrap();
+        let echoed: TestMessage = client_connection.read().await.unwrap().unwrap();
+        assert_eq!(message, echoed);
+        server.await.unwrap();
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    async fn tls_peer_certificate_identifies_client() {
+        use connection::rustls::pki_types::PrivateKeyDer;
+        use connection::rustls::server::WebPkiClientVerifier;
+        use connection::rustls::{ClientConfig, RootCertStore, ServerConfig};
+        use x509_parser::extensions::GeneralName;
+
+        let server_certified =
+            rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let server_cert = server_certified.cert.der().clone();
+        let server_key = PrivateKeyDer::Pkcs8(server_certified.key_pair.serialize_der().into());
+
+        let mut params = rcgen::CertificateParams::new(vec!["client-a.example".into()]).unwrap();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "client-a");
+        let client_key_pair = rcgen::KeyPair::generate().unwrap();
+        let client_cert = params.self_signed(&client_key_pair).unwrap().der().clone();
+        let client_key = PrivateKeyDer::Pkcs8(client_key_pair.serialize_der().into());
+
+        let mut client_roots = RootCertStore::empty();
+        client_roots.add(client_cert.clone()).unwrap();
+        let verifier = WebPkiClientVerifier::builder(Arc::new(client_roots))
+            .build()
+            .unwrap();
+        let server_config = ServerConfig::builder()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(vec![server_cert.clone()], server_key)
+            .unwrap();
+
+        let mut server_roots = RootCertStore::empty();
+        server_roots.add(server_cert.clone()).unwrap();
+        let client_config = ClientConfig::builder()
+            .with_root_certificates(server_roots)
+            .with_client_auth_cert(vec![client_cert], client_key)
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let stream = listener.accept().await.unwrap().0;
+            let server_connection =
+                TlsServerConnection::accept_tls(stream, Arc::new(server_config))
+                    .await
+                    .unwrap();
+
+            // Authorize the client by the names in its certificate
+            let der = server_connection.peer_certificate().unwrap();
+            let (_, cert) = x509_parser::parse_x509_certificate(der).unwrap();
+            let common_name = cert
+                .subject()
+                .iter_common_name()
+                .next()
+                .and_then(|cn| cn.as_str().ok())
+                .map(str::to_owned);
+            let dns_names: Vec<String> = cert
+                .subject_alternative_name()
+                .unwrap()
+                .unwrap()
+                .value
+                .general_names
+                .iter()
+                .filter_map(|name| match name {
+                    GeneralName::DNSName(name) => Some(name.to_string()),
+                    _ => None,
+                })
+                .collect();
+            (common_name, dns_names)
+        });
+
+        let client_connection = TlsConnection::dial_tls(addr, "localhost", Arc::new(client_config))
+            .await
+            .unwrap();
+        assert_eq!(Some(&server_cert), client_connection.peer_certificate());
+
+        let (common_name, dns_names) = server.await.unwrap();
+        let allowlist = ["client-a.example", "client-b.example"];
+        assert_eq!(Some("client-a".to_string()), common_name);
+        assert!(dns_names
+            .iter()
+            .any(|name| allowlist.contains(&name.as_str())));
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    async fn tls_peer_certificate_is_none_without_client_auth() {
+        let (client_config, server_config) = tls_configs();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let stream = listener.accept().await.unwrap().0;
+            let server_connection = TlsServerConnection::accept_tls(stream, server_config)
+                .await
+                .unwrap();
+            server_connection.peer_certificate().is_none()
+        });
+
+        let client_connection = TlsConnection::dial_tls(addr, "localhost", client_config)
+            .await
+            .unwrap();
+        assert!(client_connection.peer_certificate().is_some());
+        assert!(server.await.unwrap());
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    async fn tls_handshake_rejects_unexpected_server_name() {
+        let (client_config, server_config) = tls_configs();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let stream = listener.accept().await.unwrap().0;
+            let _ = TlsServerConnection::accept_tls(stream, server_config).await;
+        });
+
+        let result = TlsConnection::dial_tls(addr, "example.com", client_config).await;
+        assert!(matches!(result, Err(ConnectionError::TlsError(_))));
+    }
 }